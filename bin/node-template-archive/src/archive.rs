@@ -14,11 +14,54 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use node_template_runtime::{self as runtime, opaque::Block};
-use substrate_archive::{Archive, ArchiveConfig, ArchiveBuilder};
+use substrate_archive::{Archive, ArchiveConfig, ArchiveBuilder, ConsistencyReport, ProofKey, StorageProof, Verification, VerificationReport};
+use super::cli_opts::{Call, Check, Prove};
+
+type Builder = ArchiveBuilder<Block, runtime::RuntimeApi, node_template::service::Executor>;
 
 pub async fn run_archive(config: super::config::Config) -> Result<impl Archive<Block>> {
+    Ok(builder(config)?.run().await?)
+}
+
+/// verify the archived storage instead of indexing the chain
+pub async fn verify_archive(config: super::config::Config, verification: Verification) -> Result<()> {
+    let report = builder(config)?.verify(verification).await?;
+    print_report(report)
+}
+
+/// Check the archive for consistency and print the report as JSON.
+/// Returns the report if it should be repaired
+pub async fn check_archive(config: super::config::Config, check: Check) -> Result<Option<ConsistencyReport>> {
+    let report = builder(config)?.check_consistency(check.from, check.to).await?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    match (report.is_consistent(), check.repair) {
+        (true, _) => Ok(None),
+        (false, true) => Ok(Some(report)),
+        (false, false) => Err(anyhow!(
+            "{} inconsistencies found",
+            report.inconsistencies.len()
+        )),
+    }
+}
+
+/// Print a storage proof of keys at a block as JSON, checked against the archived state root
+pub async fn prove_archive(config: super::config::Config, prove: Prove) -> Result<()> {
+    let archive = builder(config)?;
+    let proof = archive.prove_read(prove.block, &prove.keys)?;
+    let values = archive.verify_read_proof(prove.block, &proof, &prove.keys).await?;
+    print_proof(&prove, proof, values)
+}
+
+/// Call a runtime API method at a block and print the SCALE encoded result
+pub async fn call_archive(config: super::config::Config, call: Call) -> Result<()> {
+    let result = builder(config)?.call_at(call.block, &call.method, &call.args)?;
+    println!("0x{}", hex::encode(result));
+    Ok(())
+}
+
+fn builder(config: super::config::Config) -> Result<Builder> {
     let spec = config.cli().chain_spec.clone();
 
     let conf = ArchiveConfig {
@@ -27,9 +70,79 @@ pub async fn run_archive(config: super::config::Config) -> Result<impl Archive<B
         cache_size: config.cache_size(),
         block_workers: config.block_workers(),
         wasm_pages: config.wasm_pages(),
+        wasm_execution: config.wasm_execution(),
+        execution_strategies: config.execution_strategies(),
+        index_balances: config.index_balances(),
+        retention: config.retention(),
+        secondary_db: config.secondary_db(),
+        catch_up_interval: config.catch_up_interval(),
+        trie_cache_size: config.trie_cache_size(),
         psql_conf: config.psql_conf(),
     };
 
-    let archive = ArchiveBuilder::<Block, runtime::RuntimeApi, node_template::service::Executor>::new(conf, Box::new(spec))?;
-    Ok(archive.run().await?)
+    Ok(Builder::new(conf, Box::new(spec))?)
+}
+
+fn print_report(report: VerificationReport<Block>) -> Result<()> {
+    println!("verified {} blocks", report.verified);
+    for m in report.mismatches.iter() {
+        println!(
+            "block {} ({:?}): expected state root {:?}, computed {:?}",
+            m.block_num, m.hash, m.expected, m.computed
+        );
+        for key in m.keys.iter() {
+            println!(
+                "    0x{}",
+                key.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+            );
+        }
+        for (child, key) in m.child_keys.iter() {
+            println!(
+                "    child 0x{} 0x{}",
+                child
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>(),
+                key.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+            );
+        }
+    }
+    if !report.missing.is_empty() {
+        println!("not archived: {:?}", report.missing);
+    }
+    if report.is_faithful() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} blocks do not match their state root",
+            report.mismatches.len()
+        ))
+    }
+}
+
+/// print a proof, and the values it proves, as JSON
+fn print_proof(
+    prove: &Prove,
+    proof: StorageProof,
+    values: Vec<(ProofKey, Option<Vec<u8>>)>,
+) -> Result<()> {
+    let to_hex = |bytes: &[u8]| format!("0x{}", hex::encode(bytes));
+    let values = values
+        .iter()
+        .map(|(k, v)| {
+            serde_json::json!({
+                "child_key": k.child_key().map(to_hex),
+                "key": to_hex(k.key()),
+                "value": v.as_deref().map(to_hex),
+            })
+        })
+        .collect::<Vec<_>>();
+    let proof = proof.iter_nodes().map(|n| to_hex(&n)).collect::<Vec<_>>();
+    let json = serde_json::json!({
+        "block": format!("{:?}", prove.block),
+        "proof": proof,
+        "values": values,
+    });
+    println!("{}", serde_json::to_string_pretty(&json)?);
+    Ok(())
 }
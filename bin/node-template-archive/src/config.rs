@@ -17,8 +17,14 @@
 use super::cli_opts::CliOpts;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::path::{Path, PathBuf};
-use substrate_archive::MigrationConfig;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use substrate_archive::{
+    uniform_execution_strategies, ExecutionMethod, ExecutionStrategies, ExecutionStrategy,
+    MigrationConfig, RetentionPolicy, SecondaryDir,
+};
 
 #[derive(Clone)]
 pub struct Config {
@@ -29,6 +35,16 @@ pub struct Config {
     cache_size: usize,
     block_workers: Option<usize>,
     wasm_pages: Option<u64>,
+    wasm_execution: Option<ExecutionMethod>,
+    always_wasm: Option<bool>,
+    index_balances: Option<bool>,
+    retain_blocks: Option<u32>,
+    retention_snapshot: Option<bool>,
+    retention_interval: Option<u64>,
+    secondary_db_path: Option<PathBuf>,
+    temporary_secondary_db: Option<bool>,
+    catch_up_interval_ms: Option<u64>,
+    trie_cache_size: Option<usize>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -38,11 +54,22 @@ struct TomlConfig {
     cache_size: usize,
     block_workers: Option<usize>,
     wasm_pages: Option<u64>,
+    wasm_execution: Option<ExecutionMethod>,
+    always_wasm: Option<bool>,
+    index_balances: Option<bool>,
+    retain_blocks: Option<u32>,
+    retention_snapshot: Option<bool>,
+    retention_interval: Option<u64>,
+    secondary_db_path: Option<PathBuf>,
+    temporary_secondary_db: Option<bool>,
+    catch_up_interval_ms: Option<u64>,
+    trie_cache_size: Option<usize>,
     db_host: Option<String>,
     db_port: Option<String>,
     db_user: Option<String>,
     db_pass: Option<String>,
     db_name: Option<String>,
+    partition_size: Option<u32>,
 }
 
 impl Config {
@@ -57,6 +84,7 @@ impl Config {
             user: toml_conf.db_user.clone(),
             pass: toml_conf.db_pass.clone(),
             name: toml_conf.db_name.clone(),
+            partition_size: toml_conf.partition_size,
         };
 
         Ok(Self {
@@ -67,6 +95,16 @@ impl Config {
             cache_size: toml_conf.cache_size,
             block_workers: toml_conf.block_workers,
             wasm_pages: toml_conf.wasm_pages,
+            wasm_execution: toml_conf.wasm_execution,
+            always_wasm: toml_conf.always_wasm,
+            index_balances: toml_conf.index_balances,
+            retain_blocks: toml_conf.retain_blocks,
+            retention_snapshot: toml_conf.retention_snapshot,
+            retention_interval: toml_conf.retention_interval,
+            secondary_db_path: toml_conf.secondary_db_path.clone(),
+            temporary_secondary_db: toml_conf.temporary_secondary_db,
+            catch_up_interval_ms: toml_conf.catch_up_interval_ms,
+            trie_cache_size: toml_conf.trie_cache_size,
         })
     }
 
@@ -102,4 +140,44 @@ impl Config {
     pub fn wasm_pages(&self) -> Option<u64> {
         self.wasm_pages.clone()
     }
+
+    pub fn wasm_execution(&self) -> Option<ExecutionMethod> {
+        self.wasm_execution
+    }
+
+    pub fn index_balances(&self) -> bool {
+        self.index_balances.unwrap_or(false)
+    }
+
+    pub fn retention(&self) -> Option<RetentionPolicy> {
+        Some(RetentionPolicy {
+            keep_blocks: self.retain_blocks?,
+            snapshot: self.retention_snapshot.unwrap_or(true),
+            interval: Duration::from_secs(self.retention_interval.unwrap_or(3600)),
+        })
+    }
+
+    pub fn secondary_db(&self) -> Option<SecondaryDir> {
+        if self.temporary_secondary_db.unwrap_or(false) {
+            Some(SecondaryDir::Temporary)
+        } else {
+            self.secondary_db_path.clone().map(SecondaryDir::Path)
+        }
+    }
+
+    pub fn catch_up_interval(&self) -> Option<Duration> {
+        self.catch_up_interval_ms.map(Duration::from_millis)
+    }
+
+    pub fn trie_cache_size(&self) -> Option<usize> {
+        self.trie_cache_size
+    }
+
+    pub fn execution_strategies(&self) -> Option<ExecutionStrategies> {
+        if self.always_wasm.unwrap_or(false) {
+            Some(uniform_execution_strategies(ExecutionStrategy::AlwaysWasm))
+        } else {
+            None
+        }
+    }
 }
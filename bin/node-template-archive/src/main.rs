@@ -18,14 +18,40 @@ mod cli_opts;
 mod config;
 
 use anyhow::Result;
+use substrate_archive::Archive;
 
 #[tokio::main]
 pub async fn main() -> Result<()> {
     let config = config::Config::new()?;
     substrate_archive::init_logger(config.cli().log_level, log::LevelFilter::Debug);
     
+    if let Some(call) = config.cli().call.clone() {
+        return archive::call_archive(config, call).await;
+    }
+    if let Some(prove) = config.cli().prove.clone() {
+        return archive::prove_archive(config, prove).await;
+    }
+    if let Some(verification) = config.cli().verify.clone() {
+        return archive::verify_archive(config, verification).await;
+    }
+    let repairs = match config.cli().check.clone() {
+        Some(check) => archive::check_archive(config.clone(), check).await?,
+        None => None,
+    };
+    if config.cli().exits_early() {
+        return Ok(());
+    }
     let archive = archive::run_archive(config.clone()).await?;
-    ctrlc().await?;
+    if let Some(report) = repairs {
+        archive.repair(&report).await?;
+    }
+    if let Some(reindex) = config.cli().reindex.clone() {
+        archive.reindex(reindex).await?;
+    }
+    tokio::select! {
+        res = archive.block_until_stopped() => res?,
+        res = ctrlc() => res?,
+    }
     Ok(())
 }
 
@@ -14,7 +14,10 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::config::Config;
+use super::{
+    cli_opts::{Check, Prove},
+    config::Config,
+};
 
 use anyhow::{anyhow, Context, Result};
 use polkadot_service::kusama_runtime as ksm_rt;
@@ -22,7 +25,15 @@ use polkadot_service::polkadot_runtime as dot_rt;
 use polkadot_service::westend_runtime as westend_rt;
 use polkadot_service::Block;
 use sc_chain_spec::ChainSpec;
-use substrate_archive::{ArchiveBuilder, ArchiveConfig};
+use substrate_archive::{
+    backend::{ReadOnlyBackend, TArchiveClient},
+    chain_traits::{
+        ApiExt, BlakeTwo256, BlockBuilderApi, ConstructRuntimeApi, NativeExecutionDispatch,
+        StateBackend, StateBackendFor,
+    },
+    Archive, ArchiveBuilder, ArchiveConfig, BlockchainError, ConsistencyReport, ProofKey,
+    StorageProof, VerificationReport,
+};
 /*
 #[allow(unused)]
 pub enum TripleContext {
@@ -62,6 +73,13 @@ pub async fn run_archive(config: Config) -> Result<()> {
         cache_size: config.cache_size(),
         block_workers: config.block_workers(),
         wasm_pages: config.wasm_pages(),
+        wasm_execution: config.wasm_execution(),
+        execution_strategies: config.execution_strategies(),
+        index_balances: config.index_balances(),
+        retention: config.retention(),
+        secondary_db: config.secondary_db(),
+        catch_up_interval: config.catch_up_interval(),
+        trie_cache_size: config.trie_cache_size(),
         psql_conf: config.psql_conf(),
     };
 
@@ -71,8 +89,7 @@ pub async fn run_archive(config: Config) -> Result<()> {
                 ArchiveBuilder::<Block, ksm_rt::RuntimeApi, polkadot_service::KusamaExecutor>::new(
                     conf, spec,
                 )?;
-            archive.run().await?;
-            Ok(())
+            run_with(archive, &config).await
         }
         "westend" => {
             let archive = ArchiveBuilder::<
@@ -80,8 +97,7 @@ pub async fn run_archive(config: Config) -> Result<()> {
                 westend_rt::RuntimeApi,
                 polkadot_service::WestendExecutor,
             >::new(conf, spec)?;
-            archive.run().await?;
-            Ok(())
+            run_with(archive, &config).await
         }
         "polkadot" | "dot" => {
             let archive = ArchiveBuilder::<
@@ -89,13 +105,141 @@ pub async fn run_archive(config: Config) -> Result<()> {
                 dot_rt::RuntimeApi,
                 polkadot_service::PolkadotExecutor,
             >::new(conf, spec)?;
-            archive.run().await?;
-            Ok(())
+            run_with(archive, &config).await
         }
         c => Err(anyhow!("unknown chain {}", c)),
     }
 }
 
+/// run the command given on the command line with the archive of a chain
+async fn run_with<R, D>(archive: ArchiveBuilder<Block, R, D>, config: &Config) -> Result<()>
+where
+    R: ConstructRuntimeApi<Block, TArchiveClient<Block, R, D>> + Send + Sync + 'static,
+    R::RuntimeApi: BlockBuilderApi<Block, Error = BlockchainError>
+        + ApiExt<Block, StateBackend = StateBackendFor<ReadOnlyBackend<Block>, Block>>
+        + Send
+        + Sync
+        + 'static,
+    D: NativeExecutionDispatch + 'static,
+    <R::RuntimeApi as ApiExt<Block>>::StateBackend: StateBackend<BlakeTwo256>,
+{
+    if let Some(c) = config.cli().call.clone() {
+        let result = archive.call_at(c.block, &c.method, &c.args)?;
+        println!("0x{}", hex::encode(result));
+        return Ok(());
+    }
+    if let Some(p) = config.cli().prove.clone() {
+        let proof = archive.prove_read(p.block, &p.keys)?;
+        let values = archive.verify_read_proof(p.block, &proof, &p.keys).await?;
+        return print_proof(&p, proof, values);
+    }
+    if let Some(verification) = config.cli().verify.clone() {
+        return print_report(archive.verify(verification).await?);
+    }
+    let repairs = match config.cli().check.clone() {
+        Some(c) => check(archive.check_consistency(c.from, c.to).await?, &c)?,
+        None => None,
+    };
+    if config.cli().exits_early() {
+        return Ok(());
+    }
+    start(archive.run().await?, config, repairs).await
+}
+
+/// repair the archive and index the blocks given on the command line again, if any
+async fn start(
+    archive: impl Archive<Block>,
+    config: &Config,
+    repairs: Option<ConsistencyReport>,
+) -> Result<()> {
+    if let Some(report) = repairs {
+        archive.repair(&report).await?;
+    }
+    if let Some(reindex) = config.cli().reindex.clone() {
+        archive.reindex(reindex).await?;
+    }
+    Ok(())
+}
+
+/// print the result of verifying the archive
+fn print_report(report: VerificationReport<Block>) -> Result<()> {
+    println!("verified {} blocks", report.verified);
+    for m in report.mismatches.iter() {
+        println!(
+            "block {} ({:?}): expected state root {:?}, computed {:?}",
+            m.block_num, m.hash, m.expected, m.computed
+        );
+        for key in m.keys.iter() {
+            println!(
+                "    0x{}",
+                key.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+            );
+        }
+        for (child, key) in m.child_keys.iter() {
+            println!(
+                "    child 0x{} 0x{}",
+                child
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>(),
+                key.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+            );
+        }
+    }
+    if !report.missing.is_empty() {
+        println!("not archived: {:?}", report.missing);
+    }
+    if report.is_faithful() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} blocks do not match their state root",
+            report.mismatches.len()
+        ))
+    }
+}
+
+/// print a proof, and the values it proves, as JSON
+fn print_proof(
+    prove: &Prove,
+    proof: StorageProof,
+    values: Vec<(ProofKey, Option<Vec<u8>>)>,
+) -> Result<()> {
+    let to_hex = |bytes: &[u8]| format!("0x{}", hex::encode(bytes));
+    let values = values
+        .iter()
+        .map(|(k, v)| {
+            serde_json::json!({
+                "child_key": k.child_key().map(to_hex),
+                "key": to_hex(k.key()),
+                "value": v.as_deref().map(to_hex),
+            })
+        })
+        .collect::<Vec<_>>();
+    let proof = proof.iter_nodes().map(|n| to_hex(&n)).collect::<Vec<_>>();
+    let json = serde_json::json!({
+        "block": format!("{:?}", prove.block),
+        "proof": proof,
+        "values": values,
+    });
+    println!("{}", serde_json::to_string_pretty(&json)?);
+    Ok(())
+}
+
+/// Print the report as JSON.
+/// Returns the report if it should be repaired
+fn check(report: ConsistencyReport, check: &Check) -> Result<Option<ConsistencyReport>> {
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    match (report.is_consistent(), check.repair) {
+        (true, _) => Ok(None),
+        (false, true) => Ok(Some(report)),
+        (false, false) => Err(anyhow!(
+            "{} inconsistencies found",
+            report.inconsistencies.len()
+        )),
+    }
+}
+
 fn get_spec(chain: &str) -> Result<Box<dyn ChainSpec>> {
     match chain.to_ascii_lowercase().as_str() {
         "kusama" | "ksm" => {
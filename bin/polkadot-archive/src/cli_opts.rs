@@ -14,13 +14,51 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-use clap::{load_yaml, App};
+use clap::{load_yaml, value_t, values_t, App, ArgMatches, Error, ErrorKind};
+use polkadot_service::Block;
+use sp_core::H256;
 use std::path::PathBuf;
+use substrate_archive::{BlockId, ProofKey, Reindex, ReindexStage, Verification};
+
+/// A range of blocks to check for consistency
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub from: u64,
+    pub to: u64,
+    /// fetch inconsistent blocks again
+    pub repair: bool,
+}
+
+/// Keys to prove at a block
+#[derive(Debug, Clone)]
+pub struct Prove {
+    pub block: H256,
+    pub keys: Vec<ProofKey>,
+}
+
+/// A runtime API call at a block
+#[derive(Debug, Clone)]
+pub struct Call {
+    pub block: BlockId<Block>,
+    pub method: String,
+    /// SCALE encoded arguments
+    pub args: Vec<u8>,
+}
 
 #[derive(Debug, Clone)]
 pub struct CliOpts {
     pub file: PathBuf,
     pub log_level: log::LevelFilter,
+    /// blocks to index again before indexing the chain
+    pub reindex: Option<Reindex>,
+    /// blocks to verify instead of indexing the chain
+    pub verify: Option<Verification>,
+    /// blocks to check for consistency before indexing the chain
+    pub check: Option<Check>,
+    /// keys to prove instead of indexing the chain
+    pub prove: Option<Prove>,
+    /// runtime API call to make instead of indexing the chain
+    pub call: Option<Call>,
     pub log_num: u64,
     pub chain: String,
 }
@@ -48,8 +86,121 @@ impl CliOpts {
         CliOpts {
             file: PathBuf::from(file),
             log_level,
+            reindex: reindex(&matches),
+            verify: verify(&matches),
+            check: check(&matches),
+            prove: prove(&matches),
+            call: call(&matches),
             log_num,
             chain: chain.to_string(),
         }
     }
+
+    /// whether the command exits once it is done, instead of indexing the chain
+    pub fn exits_early(&self) -> bool {
+        self.verify.is_some()
+            || self.prove.is_some()
+            || self.call.is_some()
+            || self.check.as_ref().map_or(false, |c| !c.repair)
+    }
+}
+
+fn reindex(matches: &ArgMatches) -> Option<Reindex> {
+    let m = matches.subcommand_matches("reindex")?;
+    let stages = if m.is_present("stages") {
+        values_t!(m, "stages", ReindexStage).unwrap_or_else(|e| e.exit())
+    } else {
+        ReindexStage::ALL.to_vec()
+    };
+    Some(Reindex {
+        from: value_t!(m, "from", u64).unwrap_or_else(|e| e.exit()),
+        to: value_t!(m, "to", u64).unwrap_or_else(|e| e.exit()),
+        stages,
+        delete_existing: m.is_present("delete-existing"),
+    })
+}
+
+fn verify(matches: &ArgMatches) -> Option<Verification> {
+    let m = matches.subcommand_matches("verify")?;
+    let sample = if m.is_present("sample") {
+        Some(value_t!(m, "sample", u64).unwrap_or_else(|e| e.exit()))
+    } else {
+        None
+    };
+    Some(Verification {
+        from: value_t!(m, "from", u64).unwrap_or_else(|e| e.exit()),
+        to: value_t!(m, "to", u64).unwrap_or_else(|e| e.exit()),
+        sample,
+    })
+}
+
+fn check(matches: &ArgMatches) -> Option<Check> {
+    let m = matches.subcommand_matches("check")?;
+    Some(Check {
+        from: value_t!(m, "from", u64).unwrap_or_else(|e| e.exit()),
+        to: value_t!(m, "to", u64).unwrap_or_else(|e| e.exit()),
+        repair: m.is_present("repair"),
+    })
+}
+
+fn prove(matches: &ArgMatches) -> Option<Prove> {
+    let m = matches.subcommand_matches("prove")?;
+    let block = from_hex(m.value_of("block").expect("block is a required value"));
+    if block.len() != 32 {
+        invalid_value("block hash must be 32 bytes")
+    }
+    let mut keys = m
+        .values_of("key")
+        .into_iter()
+        .flatten()
+        .map(|k| ProofKey::Top(from_hex(k)))
+        .collect::<Vec<_>>();
+    for k in m.values_of("child-key").into_iter().flatten() {
+        let mut parts = k.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(child_key), Some(key)) => keys.push(ProofKey::Child {
+                child_key: from_hex(child_key),
+                key: from_hex(key),
+            }),
+            _ => invalid_value("child keys must be given as CHILD:KEY"),
+        }
+    }
+    if keys.is_empty() {
+        invalid_value("no keys to prove")
+    }
+    Some(Prove {
+        block: H256::from_slice(&block),
+        keys,
+    })
+}
+
+fn call(matches: &ArgMatches) -> Option<Call> {
+    let m = matches.subcommand_matches("call")?;
+    let block = m.value_of("block").expect("block is a required value");
+    let block = if block.starts_with("0x") {
+        let hash = from_hex(block);
+        if hash.len() != 32 {
+            invalid_value("block hash must be 32 bytes")
+        }
+        BlockId::Hash(H256::from_slice(&hash))
+    } else {
+        BlockId::Number(value_t!(m, "block", u32).unwrap_or_else(|e| e.exit()))
+    };
+    Some(Call {
+        block,
+        method: m
+            .value_of("method")
+            .expect("method is a required value")
+            .to_string(),
+        args: m.value_of("args").map(from_hex).unwrap_or_default(),
+    })
+}
+
+fn from_hex(s: &str) -> Vec<u8> {
+    hex::decode(s.trim_start_matches("0x"))
+        .unwrap_or_else(|e| invalid_value(&format!("invalid hex {}: {}", s, e)))
+}
+
+fn invalid_value(description: &str) -> ! {
+    Error::with_description(description, ErrorKind::InvalidValue).exit()
 }
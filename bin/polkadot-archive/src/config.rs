@@ -17,8 +17,14 @@
 use super::cli_opts::CliOpts;
 use anyhow::Result;
 use serde::Deserialize;
-use std::path::{Path, PathBuf};
-use substrate_archive::MigrationConfig;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use substrate_archive::{
+    uniform_execution_strategies, ExecutionMethod, ExecutionStrategies, ExecutionStrategy,
+    MigrationConfig, RetentionPolicy, SecondaryDir,
+};
 
 #[derive(Debug, Clone, Deserialize)]
 struct TomlConfig {
@@ -27,6 +33,16 @@ struct TomlConfig {
     cache_size: usize,
     block_workers: Option<usize>,
     wasm_pages: Option<u64>,
+    wasm_execution: Option<ExecutionMethod>,
+    always_wasm: Option<bool>,
+    index_balances: Option<bool>,
+    retain_blocks: Option<u32>,
+    retention_snapshot: Option<bool>,
+    retention_interval: Option<u64>,
+    secondary_db_path: Option<PathBuf>,
+    temporary_secondary_db: Option<bool>,
+    catch_up_interval_ms: Option<u64>,
+    trie_cache_size: Option<usize>,
     db_host: Option<String>,
     db_port: Option<String>,
     db_user: Option<String>,
@@ -34,6 +50,7 @@ struct TomlConfig {
     westend_db: Option<String>,
     kusama_db: Option<String>,
     polkadot_db: Option<String>,
+    partition_size: Option<u32>,
 }
 
 impl TomlConfig {
@@ -51,6 +68,7 @@ impl TomlConfig {
             user: self.db_user.clone(),
             pass: self.db_pass.clone(),
             name: name,
+            partition_size: self.partition_size,
         }
     }
 }
@@ -64,6 +82,16 @@ pub struct Config {
     cache_size: usize,
     block_workers: Option<usize>,
     wasm_pages: Option<u64>,
+    wasm_execution: Option<ExecutionMethod>,
+    always_wasm: Option<bool>,
+    index_balances: Option<bool>,
+    retain_blocks: Option<u32>,
+    retention_snapshot: Option<bool>,
+    retention_interval: Option<u64>,
+    secondary_db_path: Option<PathBuf>,
+    temporary_secondary_db: Option<bool>,
+    catch_up_interval_ms: Option<u64>,
+    trie_cache_size: Option<usize>,
 }
 
 impl Config {
@@ -78,6 +106,16 @@ impl Config {
             cache_size: toml_conf.cache_size,
             block_workers: toml_conf.block_workers,
             wasm_pages: toml_conf.wasm_pages,
+            wasm_execution: toml_conf.wasm_execution,
+            always_wasm: toml_conf.always_wasm,
+            index_balances: toml_conf.index_balances,
+            retain_blocks: toml_conf.retain_blocks,
+            retention_snapshot: toml_conf.retention_snapshot,
+            retention_interval: toml_conf.retention_interval,
+            secondary_db_path: toml_conf.secondary_db_path.clone(),
+            temporary_secondary_db: toml_conf.temporary_secondary_db,
+            catch_up_interval_ms: toml_conf.catch_up_interval_ms,
+            trie_cache_size: toml_conf.trie_cache_size,
             rpc_url: toml_conf.rpc_url.clone(),
         })
     }
@@ -114,4 +152,44 @@ impl Config {
     pub fn wasm_pages(&self) -> Option<u64> {
         self.wasm_pages
     }
+
+    pub fn wasm_execution(&self) -> Option<ExecutionMethod> {
+        self.wasm_execution
+    }
+
+    pub fn index_balances(&self) -> bool {
+        self.index_balances.unwrap_or(false)
+    }
+
+    pub fn retention(&self) -> Option<RetentionPolicy> {
+        Some(RetentionPolicy {
+            keep_blocks: self.retain_blocks?,
+            snapshot: self.retention_snapshot.unwrap_or(true),
+            interval: Duration::from_secs(self.retention_interval.unwrap_or(3600)),
+        })
+    }
+
+    pub fn secondary_db(&self) -> Option<SecondaryDir> {
+        if self.temporary_secondary_db.unwrap_or(false) {
+            Some(SecondaryDir::Temporary)
+        } else {
+            self.secondary_db_path.clone().map(SecondaryDir::Path)
+        }
+    }
+
+    pub fn catch_up_interval(&self) -> Option<Duration> {
+        self.catch_up_interval_ms.map(Duration::from_millis)
+    }
+
+    pub fn trie_cache_size(&self) -> Option<usize> {
+        self.trie_cache_size
+    }
+
+    pub fn execution_strategies(&self) -> Option<ExecutionStrategies> {
+        if self.always_wasm.unwrap_or(false) {
+            Some(uniform_execution_strategies(ExecutionStrategy::AlwaysWasm))
+        } else {
+            None
+        }
+    }
 }
@@ -25,7 +25,9 @@ pub async fn main() -> Result<()> {
     substrate_archive::init_logger(config.cli().log_level, log::LevelFilter::Debug);
 
     archive::run_archive(config.clone()).await?;
-    ctrlc().await?;
+    if !config.cli().exits_early() {
+        ctrlc().await?;
+    }
 
     Ok(())
 }
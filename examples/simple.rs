@@ -27,12 +27,20 @@ pub fn main() {
         cache_size: 128,
         block_workers: Some(8),
         wasm_pages: None,
+        wasm_execution: None,
+        execution_strategies: None,
+        index_balances: false,
+        retention: None,
+        secondary_db: None,
+        catch_up_interval: None,
+        trie_cache_size: None,
         psql_conf: MigrationConfig {
             host: None,
             port: None,
             user: Some("archive".to_string()),
             pass: Some("default".to_string()),
             name: Some("kusama-archive".to_string()),
+            partition_size: None,
         },
     };
 
@@ -46,6 +54,10 @@ pub fn main() {
     // start running the archive
     let context = rt.block_on(archive.run()).unwrap();
 
-    // run indefinitely
-    rt.block_on(context.block_until_stopped());
+    context.register_error_handler(|e| log::warn!("archive reported error: {}", e));
+
+    // run until the archive encounters an error it can't recover from
+    if let Err(e) = rt.block_on(context.block_until_stopped()) {
+        log::error!("archive stopped: {}", e);
+    }
 }
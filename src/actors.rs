@@ -17,32 +17,76 @@
 //! Main entrypoint for substrate-archive. `init` will start all actors and begin indexing the
 //! chain defined with the passed-in Client and URL.
 
+mod actor_ext;
 mod actor_pool;
 mod generators;
+mod reindex;
 mod workers;
 
+use self::actor_ext::MinionAddressExt;
+pub(crate) use self::actor_ext::Supervisor;
+use self::actor_pool::ActorPool;
+use self::reindex::Reindexer;
 pub use self::workers::msg;
+use self::workers::{DatabaseActor, Generator, IndexerActor, IndexerRef, Pruner};
 use super::{
-    backend::{ApiAccess, GetRuntimeVersion, ReadOnlyBackend},
+    author::AuthorResolver,
+    backend::{ApiAccess, GetRuntimeVersion, ReadOnlyBackend, RuntimeVersionCache},
+    consistency::{ConsistencyReport, Inconsistency},
+    database::Database,
     error::{ArchiveResult, Error as ArchiveError},
-    threadpools::{BlockFetcher, ThreadedBlockExecutor},
-    types::Archive,
+    indexer::StorageIndexer,
+    queries,
+    sql_block_builder::BlockBuilder,
+    threadpools::{BlockData, BlockFetcher, ExecOutcome, ThreadedBlockExecutor},
+    types::{Archive, Reindex, RetentionPolicy, Storage},
+};
+use futures::{
+    future::{self, Either},
+    Stream, StreamExt,
 };
-use futures::{future::Either, StreamExt};
 use sc_client_api::backend;
 use sp_api::{ApiExt, ConstructRuntimeApi};
 use sp_block_builder::BlockBuilder as BlockBuilderApi;
-use sp_runtime::traits::{Block as BlockT, Header as _, NumberFor};
+use sp_runtime::{
+    traits::{Block as BlockT, Header as _, NumberFor},
+    SaturatedConversion,
+};
 use std::marker::PhantomData;
 use std::sync::Arc;
 pub use workers::Aggregator;
 use xtra::prelude::*;
 
+/// The optional parts of indexing, configured with `ArchiveBuilder`
+#[derive(Clone)]
+pub struct IndexingOptions<Block: BlockT> {
+    /// resolves block authors, if author enrichment is enabled
+    pub author_resolver: Option<Arc<dyn AuthorResolver<Block>>>,
+    /// decode balances from storage into the `balances` table
+    pub index_balances: bool,
+    /// how long storage changes are kept, if they are pruned at all
+    pub retention: Option<RetentionPolicy>,
+    /// plugins that maintain their own tables from storage changes
+    pub indexers: Vec<Arc<dyn StorageIndexer<Block>>>,
+}
+
+impl<Block: BlockT> Default for IndexingOptions<Block> {
+    fn default() -> Self {
+        Self {
+            author_resolver: None,
+            index_balances: false,
+            retention: None,
+            indexers: Vec::new(),
+        }
+    }
+}
+
 /// Context that every actor may use
 #[derive(Clone)]
 pub struct ActorContext<Block: BlockT> {
     backend: Arc<ReadOnlyBackend<Block>>,
-    api: Arc<dyn GetRuntimeVersion<Block>>,
+    versions: Arc<RuntimeVersionCache<Block>>,
+    options: IndexingOptions<Block>,
     rpc_url: String,
     psql_url: String,
 }
@@ -51,13 +95,15 @@ impl<Block: BlockT> ActorContext<Block> {
         backend: Arc<ReadOnlyBackend<Block>>,
         rpc_url: String,
         psql_url: String,
-        api: Arc<dyn GetRuntimeVersion<Block>>,
+        versions: Arc<RuntimeVersionCache<Block>>,
+        options: IndexingOptions<Block>,
     ) -> Self {
         Self {
             backend,
             rpc_url,
             psql_url,
-            api,
+            versions,
+            options,
         }
     }
 
@@ -69,8 +115,29 @@ impl<Block: BlockT> ActorContext<Block> {
         self.rpc_url.as_str()
     }
 
-    pub fn api(&self) -> Arc<dyn GetRuntimeVersion<Block>> {
-        self.api.clone()
+    /// runtime versions and code, cached by the hash of the code
+    pub fn runtime_versions(&self) -> Arc<RuntimeVersionCache<Block>> {
+        self.versions.clone()
+    }
+
+    /// resolves block authors, if author enrichment is enabled
+    pub fn author_resolver(&self) -> Option<Arc<dyn AuthorResolver<Block>>> {
+        self.options.author_resolver.clone()
+    }
+
+    /// whether balances are decoded from storage into the `balances` table
+    pub fn index_balances(&self) -> bool {
+        self.options.index_balances
+    }
+
+    /// how long storage changes are kept, if they are pruned at all
+    pub fn retention(&self) -> Option<&RetentionPolicy> {
+        self.options.retention.as_ref()
+    }
+
+    /// plugins that maintain their own tables from storage changes
+    pub fn indexers(&self) -> &[Arc<dyn StorageIndexer<Block>>] {
+        self.options.indexers.as_slice()
     }
 
     pub fn psql_url(&self) -> &str {
@@ -81,13 +148,19 @@ impl<Block: BlockT> ActorContext<Block> {
 pub struct System<Block, R, C>
 where
     Block: BlockT,
-    NumberFor<Block>: Into<u32>,
 {
     context: ActorContext<Block>,
     // workers: Option<usize>,
     executor: ThreadedBlockExecutor<Block>,
     fetcher: BlockFetcher<Block>,
+    /// set once the system is driven
+    reindexer: Option<Reindexer<Block>>,
+    /// set once the system is driven, if storage is pruned
+    pruner: Option<Address<Pruner<Block>>>,
+    /// storage of the genesis block, which is never executed
+    genesis: Option<Storage<Block>>,
     // api: Arc<C>,
+    supervisor: Supervisor,
     _marker: PhantomData<(R, C)>,
 }
 
@@ -101,8 +174,8 @@ where
         + Sync
         + 'static,
     C: ApiAccess<B, ReadOnlyBackend<B>, R> + GetRuntimeVersion<B> + 'static,
-    NumberFor<B>: Into<u32> + From<u32> + Unpin,
-    B::Hash: From<primitive_types::H256> + Unpin,
+    NumberFor<B>: Unpin,
+    B::Hash: Unpin,
     B::Header: serde::de::DeserializeOwned,
 {
     // TODO: Return a reference to the Db pool.
@@ -115,19 +188,27 @@ where
     /// Optionally accepts a URL to the postgreSQL database. However, this can be defined as the
     /// environment variable `DATABASE_URL` instead.
     pub fn new(
-        // one client per-threadpool. This way we don't have conflicting cache resources
-        // for WASM runtime-instances
-        client_api: (Arc<C>, Arc<C>),
+        api: Arc<C>,
         backend: Arc<ReadOnlyBackend<B>>,
         workers: Option<usize>,
         url: String,
         psql_url: &str,
+        options: IndexingOptions<B>,
     ) -> ArchiveResult<Self> {
-        let (api, blk_client) = client_api;
-        let context = ActorContext::new(backend.clone(), url, psql_url.to_string(), blk_client);
+        // runtime versions are shared between the threadpools,
+        // so each runtime is only instantiated once to get its version
+        let versions = Arc::new(RuntimeVersionCache::new(backend.clone(), api.clone()));
+        let context = ActorContext::new(
+            backend.clone(),
+            url,
+            psql_url.to_string(),
+            versions.clone(),
+            options,
+        );
 
-        let executor = ThreadedBlockExecutor::new(api.clone(), backend, workers)?;
-        let fetcher = BlockFetcher::new(context.clone(), Some(3))?;
+        let executor = ThreadedBlockExecutor::new(api, backend, versions, workers)?;
+        let supervisor = Supervisor::new();
+        let fetcher = BlockFetcher::new(context.clone(), supervisor.clone(), Some(3))?;
 
         Ok(Self {
             context,
@@ -135,10 +216,25 @@ where
             // api,
             executor,
             fetcher,
+            reindexer: None,
+            pruner: None,
+            genesis: None,
+            supervisor,
             _marker: PhantomData,
         })
     }
 
+    /// Index `genesis` as the storage of the genesis block once driven
+    pub fn with_genesis(mut self, genesis: Storage<B>) -> Self {
+        self.genesis = Some(genesis);
+        self
+    }
+
+    /// Register a callback that is invoked with every error the actors report
+    pub fn register_error_handler(&self, handler: impl Fn(&ArchiveError) + Send + Sync + 'static) {
+        self.supervisor.register_error_handler(handler)
+    }
+
     /// Start the actors and begin driving their execution
     pub async fn drive(&mut self) -> ArchiveResult<()> {
         let ctx = self.context.clone();
@@ -146,25 +242,207 @@ where
         let subscription = rpc
             .subscribe_finalized_heads()
             .await?
-            .map(|h| (*h.number()).into());
+            .map(|h| (*h.number()).saturated_into::<u64>());
 
         let (tx_block, tx_num) = (self.executor.sender(), self.fetcher.sender());
-        let ag = Aggregator::new(ctx.clone(), tx_block, tx_num)
-            .await?
-            .spawn();
+        let db = DatabaseActor::new(&ctx).await?;
+        let db_pool = ActorPool::new(db, 4).spawn();
+        if let Some(genesis) = self.genesis.take() {
+            log::info!("indexing {} keys of genesis storage", genesis.changes.len());
+            db_pool.handled_do_send(genesis.into(), &self.supervisor)?;
+        }
+        let mut generator = Generator::new(
+            db_pool.clone(),
+            tx_block.clone(),
+            tx_num,
+            self.executor.queued(),
+        );
+        let indexer = if ctx.indexers().is_empty() {
+            None
+        } else {
+            let indexer =
+                IndexerActor::new(&ctx, self.supervisor.clone(), self.fetcher.reindex_sender())
+                    .await?;
+            // blocks the indexers have not seen yet are executed again, to get their changes
+            generator = generator.replay_from(indexer.next_block());
+            Some(indexer.supervise(ctx.clone()))
+        };
+        generator.start().await?;
+        if let Some(policy) = ctx.retention() {
+            let pruner = Pruner::new(&ctx, policy.clone(), self.supervisor.clone()).await?;
+            self.pruner = Some(pruner.spawn());
+        }
+        self.reindexer = Some(Reindexer::new(
+            ctx.clone(),
+            self.fetcher.reindex_sender(),
+            tx_block.clone(),
+            self.executor.queued(),
+            indexer.clone(),
+        ));
+        let ag = Aggregator::new(
+            ctx.clone(),
+            db_pool.clone(),
+            tx_block.clone(),
+            self.supervisor.clone(),
+            indexer.clone(),
+        )
+        .await?
+        .spawn();
 
-        self.fetcher.attach_stream(subscription);
-        let exec_stream = self.executor.get_stream().map(|c| Either::Left(c));
-        let fetch_stream = self.fetcher.get_stream().map(|b| Either::Right(b));
+        let (fetch_tx, supervisor) = (self.fetcher.sender(), self.supervisor.clone());
+        let backend = ctx.backend().clone();
+        crate::util::spawn(async move {
+            let mut subscription = subscription;
+            while let Some(num) = subscription.next().await {
+                // the node has written the block by the time it is finalized
+                backend.catch_up_with_primary();
+                fetch_tx.send(num)?;
+            }
+            // the subscription only ends if the node goes away
+            supervisor.escalate(ArchiveError::RpcDisconnected);
+            Ok(())
+        });
+        let exec_stream = self.executor.get_stream().filter_map(|o| {
+            future::ready(match o {
+                ExecOutcome::Changes(c) => Some(Ok(Either::Left(c))),
+                ExecOutcome::Genesis => None,
+                ExecOutcome::Failed(f) => Some(Err(f.into())),
+            })
+        });
+        let fetch_stream = self.fetcher.get_stream().map(|b| b.map(Either::Right));
         let comb_stream = futures::stream::select(exec_stream, fetch_stream);
-        ag.attach_stream(comb_stream.map(|d| msg::IncomingData::from(d)));
+        let aggregator = AggregatorSupervisor {
+            addr: ag,
+            ctx,
+            db_pool,
+            tx_block,
+            supervisor: self.supervisor.clone(),
+            indexer,
+        };
+        crate::util::spawn(aggregator.forward(comb_stream.map(|d| msg::IncomingData::from(d))));
+        Ok(())
+    }
+
+    /// Index a range of blocks again
+    pub async fn reindex(&self, request: Reindex) -> ArchiveResult<()> {
+        match &self.reindexer {
+            Some(r) => r.reindex(request).await,
+            None => Err(ArchiveError::NotRunning("reindex")),
+        }
+    }
+
+    /// Repair the inconsistencies in a report from `ArchiveBuilder::check_consistency`.
+    /// Archived blocks that disagree with RocksDB are deleted, and the blocks to repair are fetched again,
+    /// bypassing the fetcher's check for blocks it has already fetched.
+    /// Blocks that are still archived are executed again to restore their storage,
+    /// the others are executed once they have been fetched.
+    pub async fn repair(&self, report: &ConsistencyReport) -> ArchiveResult<()> {
+        if self.reindexer.is_none() {
+            return Err(ArchiveError::NotRunning("repair"));
+        }
+        let bad_rows = report
+            .inconsistencies
+            .iter()
+            .filter_map(Inconsistency::bad_row)
+            .collect::<Vec<_>>();
+        if !bad_rows.is_empty() {
+            let db = Database::new(self.context.psql_url().to_string()).await?;
+            let mut conn = db.conn().await?;
+            for hash in bad_rows.iter() {
+                sqlx::query("DELETE FROM blocks WHERE hash = $1")
+                    .bind(hash.as_slice())
+                    .execute(&mut *conn)
+                    .await?;
+            }
+            log::info!("deleted {} inconsistent blocks", bad_rows.len());
+        }
+        let repairs = report.repairs();
+        let (first, last) = match (repairs.first(), repairs.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return Ok(()),
+        };
+        let db = Database::new(self.context.psql_url().to_string()).await?;
+        let archived = queries::blocks_in_range(&mut *db.conn().await?, first, last).await?;
+        let archived = BlockBuilder::<B>::new()
+            .with_vec(archived)?
+            .into_iter()
+            .filter(|b| {
+                let num = (*b.inner.block.header().number()).saturated_into::<u64>();
+                repairs.binary_search(&num).is_ok()
+            })
+            .collect::<Vec<_>>();
+        log::info!(
+            "fetching {} blocks and executing {} blocks again to repair them",
+            repairs.len(),
+            archived.len()
+        );
+        let fetch = self.fetcher.reindex_sender();
+        for num in repairs.into_iter() {
+            fetch.send(num)?;
+        }
+        if !archived.is_empty() {
+            self.executor.sender().send(BlockData::Reindex(archived))?;
+        }
         Ok(())
     }
 
-    pub async fn block_until_stopped(&self) {
-        loop {
-            timer::Delay::new(std::time::Duration::from_secs(1)).await;
+    /// Blocks until the system encounters an error it cannot recover from
+    pub async fn block_until_stopped(&self) -> ArchiveResult<()> {
+        Err(self.supervisor.fatal().await)
+    }
+}
+
+/// Keeps the aggregator running
+/// The aggregator is restarted with fresh state if it stops
+struct AggregatorSupervisor<B>
+where
+    B: BlockT,
+{
+    addr: Address<Aggregator<B>>,
+    ctx: ActorContext<B>,
+    db_pool: Address<ActorPool<DatabaseActor<B>>>,
+    tx_block: flume::Sender<crate::threadpools::BlockData<B>>,
+    supervisor: Supervisor,
+    indexer: Option<IndexerRef<B>>,
+}
+
+impl<B> AggregatorSupervisor<B>
+where
+    B: BlockT + Unpin,
+{
+    /// forward all data from the threadpools to the aggregator
+    async fn forward(
+        mut self,
+        mut stream: impl Stream<Item = msg::IncomingData<B>> + Send + Unpin + 'static,
+    ) -> ArchiveResult<()> {
+        while let Some(data) = stream.next().await {
+            if !self.addr.is_connected() {
+                if !self.supervisor.restarted("aggregator") {
+                    break;
+                }
+                match Aggregator::new(
+                    self.ctx.clone(),
+                    self.db_pool.clone(),
+                    self.tx_block.clone(),
+                    self.supervisor.clone(),
+                    self.indexer.clone(),
+                )
+                .await
+                {
+                    Ok(ag) => self.addr = ag.spawn(),
+                    Err(e) => {
+                        self.supervisor.escalate(e);
+                        break;
+                    }
+                }
+            }
+            // if the aggregator stops in between checking and sending, the data is dropped.
+            // The generator picks up any blocks missing from the database later on.
+            if self.addr.do_send(data).is_err() {
+                log::warn!("Aggregator disconnected, data dropped");
+            }
         }
+        Ok(())
     }
 }
 
@@ -179,18 +457,22 @@ where
         + Sync
         + 'static,
     C: ApiAccess<B, ReadOnlyBackend<B>, R> + GetRuntimeVersion<B> + 'static,
-    NumberFor<B>: Into<u32> + From<u32> + Unpin,
-    B::Hash: From<primitive_types::H256> + Unpin,
+    NumberFor<B>: Unpin,
+    B::Hash: Unpin,
     B::Header: serde::de::DeserializeOwned,
 {
     async fn drive(&mut self) -> Result<(), ArchiveError> {
         System::drive(self).await
     }
 
-    async fn block_until_stopped(&self) {
+    async fn block_until_stopped(&self) -> Result<(), ArchiveError> {
         System::block_until_stopped(self).await
     }
 
+    fn register_error_handler(&self, handler: impl Fn(&ArchiveError) + Send + Sync + 'static) {
+        System::register_error_handler(self, handler)
+    }
+
     fn shutdown(self) -> Result<(), ArchiveError> {
         Ok(())
     }
@@ -198,12 +480,18 @@ where
     fn context(&self) -> Result<super::actors::ActorContext<B>, ArchiveError> {
         Ok(self.context.clone())
     }
+
+    async fn reindex(&self, request: Reindex) -> Result<(), ArchiveError> {
+        System::reindex(self, request).await
+    }
+
+    async fn repair(&self, report: &ConsistencyReport) -> Result<(), ArchiveError> {
+        System::repair(self, report).await
+    }
 }
 
 /// connect to the substrate RPC
 /// each actor may potentially have their own RPC connections
-async fn connect<Block: BlockT>(url: &str) -> crate::rpc::Rpc<Block> {
-    crate::rpc::Rpc::connect(url)
-        .await
-        .expect("Couldn't connect to rpc")
+async fn connect<Block: BlockT>(url: &str) -> ArchiveResult<crate::rpc::Rpc<Block>> {
+    crate::rpc::Rpc::connect(url).await
 }
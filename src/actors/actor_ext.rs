@@ -14,69 +14,147 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::error::Error;
+//! Supervision for the actors that drive the archive.
+//! Errors returned by actors are reported to a `Supervisor`, which forwards them
+//! to any error handlers the embedding application registered.
+//! Errors the system cannot recover from are escalated to whoever is waiting on the system to stop.
+
+use super::actor_pool::PoolResponse;
+use crate::error::{ArchiveResult, Error as ArchiveError};
+use futures::future::{self, BoxFuture, FutureExt};
+use hashbrown::HashMap;
+use parking_lot::{Mutex, RwLock};
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use xtra::prelude::*;
-use xtra::{Disconnected, MessageResponseFuture};
+use xtra::Disconnected;
+
+/// How many times one kind of actor may be restarted within `RESTART_WINDOW`
+/// before the failure is considered unrecoverable
+pub const MAX_RESTARTS: usize = 10;
+
+/// Restarts older than this are forgotten, so transient crashes spread over
+/// the lifetime of a long-running archive do not add up to a fatal error
+pub const RESTART_WINDOW: Duration = Duration::from_secs(10 * 60);
 
-/// A system in which actors run
-struct System {
-    name: String,
+/// Callback invoked with every error an actor reports
+pub type ErrorHandler = Box<dyn Fn(&ArchiveError) + Send + Sync>;
+
+/// Watches over the actors in a system.
+/// Cloning a supervisor is cheap, and all clones report to the same handlers.
+#[derive(Clone)]
+pub struct Supervisor {
+    handlers: Arc<RwLock<Vec<ErrorHandler>>>,
+    restarts: Arc<Mutex<HashMap<&'static str, VecDeque<Instant>>>>,
+    fatal_tx: flume::Sender<ArchiveError>,
+    fatal_rx: Arc<flume::Receiver<ArchiveError>>,
 }
 
-impl System {
-    pub fn new<T: Into<String>>(name: T) -> Self {
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
+impl Supervisor {
+    pub fn new() -> Self {
+        let (fatal_tx, fatal_rx) = flume::unbounded();
         Self {
-            name: name.into()
+            handlers: Arc::new(RwLock::new(Vec::new())),
+            restarts: Arc::new(Mutex::new(HashMap::new())),
+            fatal_tx,
+            fatal_rx: Arc::new(fatal_rx),
         }
     }
 
-    pub fn register_error_handler(handler: impl Fn(&dyn Error)) {
-        unimplemented!()
+    /// Register a callback that is invoked for every error an actor reports
+    pub fn register_error_handler(&self, handler: impl Fn(&ArchiveError) + Send + Sync + 'static) {
+        self.handlers.write().push(Box::new(handler));
     }
 
-    /// Stop the system
-    pub fn stop(&self) {
-        unimplemented!()
+    /// Report an error that the system can recover from
+    /// If no error handlers are registered, the error is logged
+    pub fn report(&self, err: ArchiveError) {
+        let handlers = self.handlers.read();
+        if handlers.is_empty() {
+            log::error!("{}", err);
+        }
+        for handler in handlers.iter() {
+            handler(&err);
+        }
     }
-}
 
-/// Trait that marks this struct as it's failures being able to be handled by a Supervisor
-pub trait MinionHandler<T, Err, M: Message<Result = Result<T, Err>>>: Actor + Handler<M> 
-where
-    T: Send,
-    Err: Error + Send
-{
-}
+    /// Report an error the system cannot recover from.
+    /// The error is passed on to `fatal`.
+    pub fn escalate(&self, err: ArchiveError) {
+        let err = Arc::new(err);
+        self.report(ArchiveError::Unrecoverable(err.clone()));
+        // handlers only borrow the error, so it is ours again unless one of them kept the `Arc`
+        let err = Arc::try_unwrap(err).unwrap_or_else(ArchiveError::Unrecoverable);
+        // we hold the receiving end ourselves, so this never fails
+        let _ = self.fatal_tx.send(err);
+    }
 
-impl<O, Err, M, T> MinionHandler<O, Err, M> for T 
-where
-    O: Send,
-    Err: Error + Send,
-    M: Message<Result = Result<O, Err>>,
-    T: Handler<M> + Send
-{
-}
+    /// Record that the actor `name` crashed and is being restarted.
+    /// Returns false and escalates if the actor has already been restarted
+    /// `MAX_RESTARTS` times within `RESTART_WINDOW`
+    pub fn restarted(&self, name: &'static str) -> bool {
+        let count = {
+            let mut restarts = self.restarts.lock();
+            let times = restarts.entry(name).or_insert_with(VecDeque::new);
+            record_restart(times, Instant::now())
+        };
+        if count > MAX_RESTARTS {
+            self.escalate(ArchiveError::TooManyRestarts(name.to_string()));
+            false
+        } else {
+            log::warn!(
+                "{} stopped unexpectedly, restarting ({}/{})",
+                name,
+                count,
+                MAX_RESTARTS
+            );
+            true
+        }
+    }
 
+    /// Resolves once an unrecoverable error has been escalated
+    pub async fn fatal(&self) -> ArchiveError {
+        match self.fatal_rx.recv_async().await {
+            Ok(e) => e,
+            Err(_) => ArchiveError::Channel,
+        }
+    }
+}
 
-pub trait MinionAddressExt<A: Actor> {
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    fn handled_do_send<T, E, M>(&self, message: M) -> Result<(), Disconnected>
-    where
-        M: Message<Result = Result<T, E>>,
-        T: Send,
-        E: Error + Send,
-        A: MinionHandler<T, E, M> + Send;
-}
+    #[test]
+    fn should_forget_old_restarts() {
+        let mut times = VecDeque::new();
+        let start = Instant::now();
+        for i in 1..=MAX_RESTARTS {
+            assert_eq!(record_restart(&mut times, start), i);
+        }
+        let later = start + RESTART_WINDOW + Duration::from_secs(1);
+        assert_eq!(record_restart(&mut times, later), 1);
+    }
 
-impl<A: Actor> MinionAddressExt<A> for Address<A> {
-    
-    fn handled_do_send<T, E, M>(&self, message: M) -> Result<(), Disconnected>
-    where
-        M: Message<Result = Result<T, E>>,
-        T: Send,
-        E: Error + Send,
-        A: MinionHandler<T, E, M> + Send {
-            Ok(())
+    #[test]
+    fn should_escalate_the_original_error() {
+        let supervisor = Supervisor::new();
+        for _ in 0..MAX_RESTARTS {
+            assert!(supervisor.restarted("test"));
+        }
+        assert!(!supervisor.restarted("test"));
+        match supervisor.fatal_rx.try_recv() {
+            Ok(ArchiveError::TooManyRestarts(name)) => assert_eq!(name, "test"),
+            r => panic!("expected too many restarts, got {:?}", r),
         }
-}
\ No newline at end of file
+    }
+}
@@ -24,8 +24,6 @@ use std::pin::Pin;
 use xtra::prelude::*;
 use xtra::{Disconnected, WeakAddress};
 
-// TODO: Could restart actors which have panicked
-
 /// A pool of one type of Actor
 /// will distribute work to all actors in the pool
 pub struct ActorPool<A: Actor> {
@@ -86,54 +84,45 @@ impl<A: Actor + Send + Clone> ActorPool<A> {
 
     /// Forward a message to one of the spawned actors
     /// and advance the state of the futures in queue.
-    pub fn forward<M>(
-        &mut self,
-        msg: M,
-    ) -> Pin<Box<dyn Future<Output = M::Result> + Send + 'static>>
+    /// If the actor has stopped (IE, it panicked) it is restarted
+    /// with the state the pool was created with.
+    pub fn forward<M>(&mut self, msg: M) -> PoolResponse<M::Result>
     where
         M: Message,
         A: Handler<M>,
     {
         self.queue.rotate_left(1);
+        if !self.queue[0].is_connected() {
+            log::warn!("A pooled actor has stopped, restarting it with fresh state");
+            self.queue[0] = self.pure_actor.clone().spawn();
+        }
         spawn(self.queue[0].send(msg))
     }
 }
 
-fn spawn<R>(
-    fut: impl Future<Output = Result<R, Disconnected>> + Send + 'static,
-) -> Pin<Box<dyn Future<Output = R> + Send + 'static>>
+/// The response of an actor in the pool
+pub type PoolResponse<R> = Pin<Box<dyn Future<Output = Result<R, Disconnected>> + Send + 'static>>;
+
+fn spawn<R>(fut: impl Future<Output = Result<R, Disconnected>> + Send + 'static) -> PoolResponse<R>
 where
     R: Send + 'static,
 {
     // we create a channel with a capacity of one so that
     // the send does not block the runtime
-    let (tx, mut rx) = flume::bounded(1);
+    let (tx, rx) = flume::bounded(1);
     crate::util::spawn(async move {
-        match fut.await {
-            Ok(v) => {
-                if let Err(e) = tx.try_send(v) {
-                    match e {
-                        flume::TrySendError::Disconnected(_) => {
-                            // Receiver might just want to throw out the value (IE `do_send`)
-                            // we do nothing.
-                        }
-                        flume::TrySendError::Full(_) => {
-                            log::warn!("Oneshot channel full!"); // this should never happen
-                        }
-                    }
-                }
-            }
-            Err(_) => {
-                log::error!(
-                    "One of the pooled db actors has disconnected. could not send message."
-                );
-                panic!("Actor Disconnected");
-            }
-        };
+        let res = fut.await;
+        if res.is_err() {
+            log::error!("One of the pooled actors has disconnected. could not send message.");
+        }
+        if let Err(flume::TrySendError::Full(_)) = tx.try_send(res) {
+            log::warn!("Oneshot channel full!"); // this should never happen
+        }
+        // if the receiver is disconnected it just wants to
+        // throw out the value (IE `do_send`), so we do nothing.
         Ok(())
     });
-    // the expect won't even be called if we call `do_send`
-    async move { rx.recv_async().map(|r| r.expect("One shot")).await }.boxed()
+    async move { rx.recv_async().await.unwrap_or(Err(Disconnected)) }.boxed()
 }
 
 impl<A: Actor> Actor for ActorPool<A> {}
@@ -146,7 +135,7 @@ impl<M> Message for PoolMessage<M>
 where
     M: Message + Send,
 {
-    type Result = Pin<Box<dyn Future<Output = M::Result> + Send + 'static>>;
+    type Result = PoolResponse<M::Result>;
 }
 
 impl<A, M> SyncHandler<PoolMessage<M>> for ActorPool<A>
@@ -154,11 +143,7 @@ where
     A: Actor + Send + Clone + Handler<M>,
     M: Message + Send,
 {
-    fn handle(
-        &mut self,
-        msg: PoolMessage<M>,
-        _: &mut Context<Self>,
-    ) -> Pin<Box<dyn Future<Output = M::Result> + Send + 'static>> {
+    fn handle(&mut self, msg: PoolMessage<M>, _: &mut Context<Self>) -> PoolResponse<M::Result> {
         self.forward(msg.0)
     }
 }
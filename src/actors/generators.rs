@@ -22,18 +22,34 @@ use crate::{
     error::ArchiveResult, queries, sql_block_builder::BlockBuilder, threadpools::BlockData,
 };
 use flume::Sender;
-use sp_runtime::traits::Block as BlockT;
+use sp_runtime::{
+    traits::{Block as BlockT, Header as _},
+    SaturatedConversion,
+};
 use sqlx::{pool::PoolConnection, Postgres};
-use std::sync::Arc;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use xtra::prelude::*;
 
+/// How many blocks are read from Postgres at once when executing blocks again
+pub(super) const REPLAY_PAGE_SIZE: usize = 1000;
+
 #[derive(Clone)]
 pub struct Generator<B: BlockT> {
     // could just use an atomic here
-    last_block_max: Arc<u32>,
+    last_block_max: Arc<u64>,
     addr: Address<ActorPool<DatabaseActor<B>>>,
     tx_block: Sender<BlockData<B>>,
-    tx_num: Sender<u32>,
+    tx_num: Sender<u64>,
+    /// how many blocks the executor has yet to execute
+    exec_queued: Arc<AtomicUsize>,
+    /// execute blocks from this block onwards again, even if their storage is already indexed
+    replay_from: Option<u64>,
 }
 
 type Conn = PoolConnection<Postgres>;
@@ -42,19 +58,28 @@ impl<B: BlockT> Generator<B> {
     pub fn new(
         actor_pool: Address<ActorPool<DatabaseActor<B>>>,
         tx_block: Sender<BlockData<B>>,
-        tx_num: Sender<u32>,
+        tx_num: Sender<u64>,
+        exec_queued: Arc<AtomicUsize>,
     ) -> Self {
         Self {
             last_block_max: Arc::new(0),
             addr: actor_pool,
             tx_block,
             tx_num,
+            exec_queued,
+            replay_from: None,
         }
     }
 
+    /// Execute blocks from `block_num` onwards again once started
+    pub fn replay_from(mut self, block_num: u64) -> Self {
+        self.replay_from = Some(block_num);
+        self
+    }
+
     pub async fn start(self) -> ArchiveResult<()> {
-        let conn0 = self.addr.send(GetState::Conn.into()).await?.await?.conn();
-        let conn1 = self.addr.send(GetState::Conn.into()).await?.await?.conn();
+        let conn0 = self.addr.send(GetState::Conn.into()).await?.await??.conn();
+        let conn1 = self.addr.send(GetState::Conn.into()).await?.await??.conn();
         crate::util::spawn(self.clone().storage(conn0));
         crate::util::spawn(self.missing_blocks(conn1));
         Ok(())
@@ -81,7 +106,7 @@ impl<B: BlockT> Generator<B> {
                     break 'gen;
                 }
             }
-            if let Some(old_max) = std::sync::Arc::<u32>::get_mut(&mut self.last_block_max) {
+            if let Some(old_max) = std::sync::Arc::<u64>::get_mut(&mut self.last_block_max) {
                 log::debug!("new max: {}", max);
                 *old_max = max;
             }
@@ -109,6 +134,37 @@ impl<B: BlockT> Generator<B> {
 
         log::info!("indexing {} blocks of storage ... ", blocks.len());
         self.tx_block.send(BlockData::Batch(blocks))?;
+
+        if let Some(from) = self.replay_from {
+            self.replay(&mut conn, from).await?;
+        }
+        Ok(())
+    }
+
+    /// Execute the blocks from `from` onwards again, a page at a time.
+    /// The next page is only read once the executor has worked through the previous one,
+    /// so replaying a whole chain never holds more than a few pages in memory
+    async fn replay(&self, conn: &mut Conn, mut from: u64) -> ArchiveResult<()> {
+        log::info!("executing blocks from block {} again", from);
+        let mut replayed = 0;
+        loop {
+            let blocks = queries::blocks_from(conn, from, REPLAY_PAGE_SIZE).await?;
+            let blocks = BlockBuilder::<B>::new().with_vec(blocks)?;
+            let last = match blocks.last() {
+                Some(b) => (*b.inner.block.header().number()).saturated_into::<u64>(),
+                None => break,
+            };
+            replayed += blocks.len();
+            self.tx_block.send(BlockData::Batch(blocks))?;
+            from = last + 1;
+            loop {
+                timer::Delay::new(Duration::from_millis(500)).await;
+                if self.exec_queued.load(Ordering::Relaxed) < REPLAY_PAGE_SIZE {
+                    break;
+                }
+            }
+        }
+        log::info!("queued {} blocks to execute again", replayed);
         Ok(())
     }
 }
@@ -0,0 +1,215 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Index a range of blocks again.
+//! Blocks are fetched again by the `BlockFetcher`, and executed again by the `ThreadedBlockExecutor`,
+//! bypassing the checks that normally stop the threadpools from doing the same work twice.
+//! Everything downstream of the threadpools treats the results like any other.
+
+use super::{
+    generators::REPLAY_PAGE_SIZE,
+    workers::{IndexerRef, Rewind},
+    ActorContext,
+};
+use crate::{
+    database::{sql_num, Database},
+    error::{ArchiveResult, Error as ArchiveError},
+    queries,
+    rpc::Rpc,
+    sql_block_builder::BlockBuilder,
+    threadpools::BlockData,
+    types::{Block, Metadata, Reindex, ReindexStage},
+};
+use sp_runtime::{
+    traits::{Block as BlockT, Header as _},
+    SaturatedConversion,
+};
+use sqlx::Connection as _;
+use std::{
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use xtra::prelude::*;
+
+pub struct Reindexer<B: BlockT> {
+    ctx: ActorContext<B>,
+    /// blocks sent here are fetched again
+    fetch: flume::Sender<u64>,
+    /// blocks sent here are executed again
+    exec: flume::Sender<BlockData<B>>,
+    /// how many blocks the executor has yet to execute
+    exec_queued: Arc<AtomicUsize>,
+    indexer: Option<IndexerRef<B>>,
+}
+
+impl<B> Reindexer<B>
+where
+    B: BlockT,
+{
+    pub fn new(
+        ctx: ActorContext<B>,
+        fetch: flume::Sender<u64>,
+        exec: flume::Sender<BlockData<B>>,
+        exec_queued: Arc<AtomicUsize>,
+        indexer: Option<IndexerRef<B>>,
+    ) -> Self {
+        Self {
+            ctx,
+            fetch,
+            exec,
+            exec_queued,
+            indexer,
+        }
+    }
+
+    pub async fn reindex(&self, req: Reindex) -> ArchiveResult<()> {
+        if req.from > req.to {
+            return Err(ArchiveError::InvalidRange {
+                from: req.from,
+                to: req.to,
+            });
+        }
+        // storage is deleted along with the blocks it belongs to
+        let execute = req.has_stage(ReindexStage::Storage)
+            || req.has_stage(ReindexStage::Derived)
+            || (req.has_stage(ReindexStage::Blocks) && req.delete_existing);
+        let db = Database::new(self.ctx.psql_url().to_string()).await?;
+        let mut conn = db.conn().await?;
+
+        // indexers only move forward, so they have to index everything after `from` again
+        let mut exec_to = req.to;
+        if let (true, Some(indexer)) = (req.has_stage(ReindexStage::Derived), &self.indexer) {
+            let rewind = Rewind {
+                from: req.from,
+                delete_existing: req.delete_existing,
+            };
+            if let Some(last) = indexer.addr().send(rewind).await?? {
+                exec_to = exec_to.max(last);
+            }
+        }
+
+        let mut tables = Vec::new();
+        if req.delete_existing {
+            if req.has_stage(ReindexStage::Blocks) {
+                // storage, digest items and balances are deleted along with their blocks
+                tables.extend_from_slice(&["runtime_upgrades", "blocks"]);
+            }
+            if req.has_stage(ReindexStage::Storage) {
+                tables.extend_from_slice(&["storage", "child_storage"]);
+            }
+            if req.has_stage(ReindexStage::Derived) {
+                tables.push("balances");
+            }
+        }
+        let rpc = if req.has_stage(ReindexStage::Metadata) {
+            Some(Rpc::<B>::connect(self.ctx.rpc_url()).await?)
+        } else {
+            None
+        };
+        let read_blocks = execute || rpc.is_some();
+        let last = if execute { exec_to } else { req.to };
+        let mut specs = BTreeSet::new();
+        let (mut from, mut executed) = (req.from, 0);
+
+        // the range is worked through a page at a time, so that executing up to the tip of
+        // the chain for the indexers never holds more than a few pages in memory.
+        // Blocks are executed from what is in the database, so a page is read before
+        // anything in it is deleted
+        loop {
+            let blocks = if read_blocks {
+                let blocks = queries::blocks_from(&mut conn, from, REPLAY_PAGE_SIZE).await?;
+                BlockBuilder::<B>::new()
+                    .with_vec(blocks)?
+                    .into_iter()
+                    .filter(|b| block_num(b) <= last)
+                    .collect::<Vec<_>>()
+            } else {
+                Vec::new()
+            };
+            // a page that is not full covers the rest of the range
+            let page_to = match blocks.last() {
+                Some(b) if blocks.len() == REPLAY_PAGE_SIZE => block_num(b),
+                _ => last,
+            };
+
+            if from <= req.to {
+                let to = page_to.min(req.to);
+                if let Some(rpc) = &rpc {
+                    // one block of each runtime in the range is enough to get its metadata
+                    for b in blocks.iter().filter(|b| block_num(b) <= to) {
+                        if specs.insert(b.spec) {
+                            let meta = rpc.metadata(Some(b.inner.block.hash())).await?;
+                            db.insert(Metadata::new(b.spec, meta)).await?;
+                        }
+                    }
+                }
+                if !tables.is_empty() {
+                    let mut tx = conn.begin().await?;
+                    for table in tables.iter() {
+                        let query =
+                            format!("DELETE FROM {} WHERE block_num BETWEEN $1 AND $2", table);
+                        let deleted = sqlx::query(query.as_str())
+                            .bind(sql_num(from)?)
+                            .bind(sql_num(to)?)
+                            .execute(&mut *tx)
+                            .await?
+                            .rows_affected();
+                        log::info!("deleted {} rows from {}", deleted, table);
+                    }
+                    tx.commit().await?;
+                }
+                if req.has_stage(ReindexStage::Blocks) {
+                    for num in from..=to {
+                        self.fetch.send(num)?;
+                    }
+                }
+            }
+
+            if execute && !blocks.is_empty() {
+                executed += blocks.len();
+                self.exec.send(BlockData::Reindex(blocks))?;
+                // only read the next page once the executor has worked through this one
+                loop {
+                    timer::Delay::new(Duration::from_millis(500)).await;
+                    if self.exec_queued.load(Ordering::Relaxed) < REPLAY_PAGE_SIZE {
+                        break;
+                    }
+                }
+            }
+            if page_to >= last {
+                break;
+            }
+            from = page_to + 1;
+        }
+        if execute {
+            log::info!(
+                "executing {} blocks from {} to {} again",
+                executed,
+                req.from,
+                exec_to
+            );
+        }
+        Ok(())
+    }
+}
+
+fn block_num<B: BlockT>(block: &Block<B>) -> u64 {
+    (*block.inner.block.header().number()).saturated_into()
+}
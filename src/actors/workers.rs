@@ -16,14 +16,18 @@
 
 mod aggregator;
 mod database;
+mod indexer;
 mod metadata;
+mod retention;
 
 pub use self::aggregator::Aggregator;
 pub use self::database::GetState;
+pub use self::indexer::{IndexerActor, IndexerRef, Rewind};
 pub use self::metadata::Metadata;
+pub use self::retention::Pruner;
 
 pub use super::generators::Generator;
-use super::{actor_pool::ActorPool, connect, ActorContext};
+use super::{actor_ext, actor_pool::ActorPool, connect, ActorContext};
 pub use database::DatabaseActor;
 
 /// any messages defined in the workers
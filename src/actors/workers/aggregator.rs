@@ -14,17 +14,20 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::ActorContext;
+use super::{
+    actor_ext::{MinionAddressExt, Supervisor},
+    ActorContext,
+};
 use crate::{
     backend::BlockChanges,
-    error::ArchiveResult,
+    error::{ArchiveResult, Error as ArchiveError},
     threadpools::BlockData,
     types::{BatchBlock, Block, Storage},
 };
 use flume::Sender;
 use futures::future::Either;
 use itertools::{EitherOrBoth, Itertools};
-use sp_runtime::traits::{Block as BlockT, NumberFor};
+use sp_runtime::traits::Block as BlockT;
 use std::{iter::FromIterator, time::Duration};
 use xtra::prelude::*;
 
@@ -39,7 +42,6 @@ pub const SYSTEM_TICK: u64 = 1000;
 pub struct Aggregator<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
     senders: Senders<B>,
     recvs: Option<Receivers<B>>,
@@ -52,12 +54,17 @@ where
     exec: Sender<BlockData<B>>,
     /// just a switch so we know not to print redundant messages
     last_count_was_0: bool,
+    /// context the actors are running in. Used to restart the metadata actor
+    ctx: ActorContext<B>,
+    /// where errors from the actors we send work to are reported
+    supervisor: Supervisor,
+    /// actor which feeds storage changes to the `StorageIndexer`s, if any are registered
+    indexer: Option<super::IndexerRef<B>>,
 }
 
 fn queues<B>() -> (Senders<B>, Receivers<B>)
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
     let (storage_tx, storage_rx) = flume::unbounded();
     let (block_tx, block_rx) = flume::unbounded();
@@ -98,7 +105,6 @@ enum BlockOrStorage<B: BlockT> {
 impl<B> Senders<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
     fn push_back(&self, t: BlockOrStorage<B>) -> ArchiveResult<()> {
         match t {
@@ -117,23 +123,21 @@ where
 impl<B> Aggregator<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
-    NumberFor<B>: From<u32>,
 {
     pub async fn new(
         ctx: ActorContext<B>,
+        db_pool: Address<super::ActorPool<super::DatabaseActor<B>>>,
         tx_block: Sender<BlockData<B>>,
-        tx_num: Sender<u32>,
+        supervisor: Supervisor,
+        indexer: Option<super::IndexerRef<B>>,
     ) -> ArchiveResult<Self> {
-        let (psql_url, rpc_url) = (ctx.psql_url().to_string(), ctx.rpc_url().to_string());
-        let db = super::DatabaseActor::new(psql_url).await?;
-        let db_pool = super::ActorPool::new(db, 4).spawn();
-        let meta_addr = super::Metadata::new(rpc_url, db_pool.clone())
-            .await?
-            .spawn();
-        super::Generator::new(db_pool.clone(), tx_block.clone(), tx_num)
-            .start()
-            .await?;
+        let meta_addr = super::Metadata::new(
+            ctx.rpc_url().to_string(),
+            db_pool.clone(),
+            supervisor.clone(),
+        )
+        .await?
+        .spawn();
         let (senders, recvs) = queues();
 
         Ok(Self {
@@ -143,8 +147,54 @@ where
             meta_addr,
             exec: tx_block,
             last_count_was_0: false,
+            ctx,
+            supervisor,
+            indexer,
         })
     }
+
+    /// Get the address of the metadata actor,
+    /// restarting it with fresh state if it has stopped
+    async fn metadata(&mut self) -> ArchiveResult<&Address<super::Metadata<B>>> {
+        if !self.meta_addr.is_connected() {
+            if !self.supervisor.restarted("metadata") {
+                return Err(ArchiveError::TooManyRestarts("metadata".into()));
+            }
+            self.meta_addr = super::Metadata::new(
+                self.ctx.rpc_url().to_string(),
+                self.db_pool.clone(),
+                self.supervisor.clone(),
+            )
+            .await?
+            .spawn();
+        }
+        Ok(&self.meta_addr)
+    }
+
+    async fn send_blocks(&mut self, blocks: BatchBlock<B>) -> ArchiveResult<()> {
+        let supervisor = self.supervisor.clone();
+        self.metadata()
+            .await?
+            .handled_do_send(blocks, &supervisor)
+            .map_err(Into::into)
+    }
+
+    /// hand a copy of the changes to the indexers
+    fn index(&self, changes: &BlockChanges<B>) -> ArchiveResult<()> {
+        match &self.indexer {
+            Some(indexer) => indexer
+                .addr()
+                .handled_do_send(changes.clone(), &self.supervisor)
+                .map_err(Into::into),
+            None => Ok(()),
+        }
+    }
+
+    fn send_storage(&self, storage: super::msg::VecStorageWrap<B>) -> ArchiveResult<()> {
+        self.db_pool
+            .handled_do_send(storage.into(), &self.supervisor)
+            .map_err(Into::into)
+    }
 }
 
 impl<B: BlockT> Message for BlockChanges<B> {
@@ -154,7 +204,6 @@ impl<B: BlockT> Message for BlockChanges<B> {
 impl<B> Actor for Aggregator<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
     fn started(&mut self, ctx: &mut Context<Self>) {
         if self.recvs.is_none() {
@@ -176,9 +225,9 @@ where
 impl<B> SyncHandler<BlockChanges<B>> for Aggregator<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
     fn handle(&mut self, changes: BlockChanges<B>, _: &mut Context<Self>) -> ArchiveResult<()> {
+        self.index(&changes)?;
         self.senders.push_back(BlockOrStorage::Storage(changes))
     }
 }
@@ -186,32 +235,34 @@ where
 impl<B> SyncHandler<Block<B>> for Aggregator<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
-    fn handle(&mut self, block: Block<B>, c: &mut Context<Self>) {
-        if let Err(_) = self.exec.send(BlockData::Single(block.clone())) {
-            c.stop();
-        }
-        let block = BlockOrStorage::Block(block);
-        if let Err(_) = self.senders.push_back(block) {
+    fn handle(&mut self, block: Block<B>, c: &mut Context<Self>) -> ArchiveResult<()> {
+        let res = self
+            .exec
+            .send(BlockData::Single(block.clone()))
+            .map_err(ArchiveError::from)
+            .and_then(|_| self.senders.push_back(BlockOrStorage::Block(block)));
+        if res.is_err() {
             c.stop();
         }
+        res
     }
 }
 
 impl<B> SyncHandler<BatchBlock<B>> for Aggregator<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
-    fn handle(&mut self, blocks: BatchBlock<B>, c: &mut Context<Self>) {
-        if let Err(_) = self.exec.send(BlockData::Batch(blocks.inner.clone())) {
-            c.stop();
-        }
-        let blocks = BlockOrStorage::BatchBlock(blocks);
-        if let Err(_) = self.senders.push_back(blocks) {
+    fn handle(&mut self, blocks: BatchBlock<B>, c: &mut Context<Self>) -> ArchiveResult<()> {
+        let res = self
+            .exec
+            .send(BlockData::Batch(blocks.inner.clone()))
+            .map_err(ArchiveError::from)
+            .and_then(|_| self.senders.push_back(BlockOrStorage::BatchBlock(blocks)));
+        if res.is_err() {
             c.stop();
         }
+        res
     }
 }
 
@@ -243,47 +294,48 @@ impl<B: BlockT> FromIterator<EitherOrBoth<Storage<B>, Block<B>>> for BlockStorag
 impl<B> Handler<BlockStorageCombo<B>> for Aggregator<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
-    async fn handle(&mut self, data: BlockStorageCombo<B>, _: &mut Context<Self>) {
+    async fn handle(&mut self, data: BlockStorageCombo<B>, c: &mut Context<Self>) {
         let (blocks, storage) = (data.0, data.1);
 
         let (b, s) = (blocks.inner().len(), storage.0.len());
-        match (b, s) {
+        let res = match (b, s) {
             (0, 0) => {
                 if !self.last_count_was_0 {
                     log::info!("Waiting on node, nothing left to index ...");
                     self.last_count_was_0 = true;
                 }
+                Ok(())
             }
             (b, 0) => {
-                self.meta_addr.do_send(blocks).expect("Actor Disconnected");
                 log::info!("Indexing Blocks {} bps", b);
                 self.last_count_was_0 = false;
+                self.send_blocks(blocks).await
             }
             (0, s) => {
-                self.db_pool
-                    .do_send(storage.into())
-                    .expect("Actor Disconnected");
                 log::info!("Indexing Storage {} bps", s);
                 self.last_count_was_0 = false;
+                self.send_storage(storage)
             }
             (b, s) => {
-                self.db_pool
-                    .do_send(storage.into())
-                    .expect("Actor Disconnected");
-                self.meta_addr.do_send(blocks).expect("Actor Disconnected");
                 log::info!("Indexing Blocks {} bps, Indexing Storage {} bps", b, s);
                 self.last_count_was_0 = false;
+                let res = self.send_storage(storage);
+                res.and(self.send_blocks(blocks).await)
             }
         };
+        if let Err(e) = res {
+            // the system restarts the aggregator with fresh state once it stops
+            self.supervisor.report(e);
+            c.stop();
+        }
     }
 }
 
-pub struct IncomingData<B: BlockT>(Either<BlockChanges<B>, Block<B>>);
+pub struct IncomingData<B: BlockT>(ArchiveResult<Either<BlockChanges<B>, Block<B>>>);
 
-impl<B: BlockT> From<Either<BlockChanges<B>, Block<B>>> for IncomingData<B> {
-    fn from(e: Either<BlockChanges<B>, Block<B>>) -> IncomingData<B> {
+impl<B: BlockT> From<ArchiveResult<Either<BlockChanges<B>, Block<B>>>> for IncomingData<B> {
+    fn from(e: ArchiveResult<Either<BlockChanges<B>, Block<B>>>) -> IncomingData<B> {
         IncomingData(e)
     }
 }
@@ -295,19 +347,31 @@ impl<B: BlockT> Message for IncomingData<B> {
 impl<B> SyncHandler<IncomingData<B>> for Aggregator<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
     fn handle(&mut self, data: IncomingData<B>, c: &mut Context<Self>) {
+        // a failure in the threadpools only affects that one block,
+        // so we report it and keep going
+        let data = match data.0 {
+            Ok(d) => d,
+            Err(e) => {
+                self.supervisor.report(e);
+                return;
+            }
+        };
         let r = || -> ArchiveResult<()> {
-            match data.0 {
-                Either::Left(changes) => self.senders.push_back(BlockOrStorage::Storage(changes)),
+            match data {
+                Either::Left(changes) => {
+                    self.index(&changes)?;
+                    self.senders.push_back(BlockOrStorage::Storage(changes))
+                }
                 Either::Right(block) => {
                     self.exec.send(BlockData::Single(block.clone()))?;
                     self.senders.push_back(BlockOrStorage::Block(block))
                 }
             }
         };
-        if let Err(_) = r() {
+        if let Err(e) = r() {
+            self.supervisor.report(e);
             c.stop()
         }
     }
@@ -14,53 +14,80 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::database::{models::StorageModel, Database, DbConn};
+use crate::actors::ActorContext;
+use crate::backend::RuntimeVersionCache;
+use crate::database::{
+    models::{BalanceModel, ChildStorageModel, StorageModel},
+    Database, DbConn, Partitions,
+};
 use crate::error::ArchiveResult;
 use crate::queries;
 use crate::types::*;
-use sp_runtime::traits::{Block as BlockT, NumberFor};
-use std::marker::PhantomData;
+use sp_runtime::{
+    generic::BlockId,
+    traits::{Block as BlockT, Header as _},
+    SaturatedConversion,
+};
+use std::{marker::PhantomData, sync::Arc};
 use xtra::prelude::*;
 
 #[derive(Clone)]
 pub struct DatabaseActor<B: BlockT> {
     db: Database,
+    /// decode balances from storage into the `balances` table
+    index_balances: bool,
+    /// creates partitions for new blocks, if `blocks` and `storage` are partitioned
+    partitions: Option<Partitions>,
+    /// reads the runtime code of upgrades
+    versions: Arc<RuntimeVersionCache<B>>,
     _marker: PhantomData<B>
 }
 
 impl<B: BlockT> DatabaseActor<B> {
-    pub async fn new(url: String) -> ArchiveResult<Self> {
+    pub async fn new(ctx: &ActorContext<B>) -> ArchiveResult<Self> {
+        let db = Database::new(ctx.psql_url().to_string()).await?;
+        let partitions = Partitions::load(&mut *db.conn().await?).await?;
         Ok(Self {
-            db: Database::new(url).await?,
+            db,
+            index_balances: ctx.index_balances(),
+            partitions,
+            versions: ctx.runtime_versions(),
             _marker: PhantomData,
         })
     }
 
-    #[allow(unused)]
-    pub fn with_db(db: Database) -> Self {
-        Self {
-            db,
-            _marker: PhantomData
-        }     
+    /// write an upgrade, along with the runtime code it enacted
+    async fn upgrade_handler(&self, upgrade: RuntimeUpgrade<B>) -> ArchiveResult<()> {
+        let code = self.versions.code(&BlockId::Hash(upgrade.block_hash))?;
+        self.db
+            .insert(RuntimeCode::<B> {
+                code_hash: upgrade.code_hash,
+                code,
+            })
+            .await?;
+        self.db.insert(upgrade).await?;
+        Ok(())
     }
 
-    async fn block_handler(&self, blk: Block<B>) -> ArchiveResult<()> 
-    where
-        NumberFor<B>: Into<u32>,
-    {
+    async fn block_handler(&mut self, blk: Block<B>) -> ArchiveResult<()> {
         let mut conn = self.db.conn().await?;
         while !queries::check_if_meta_exists(blk.spec, &mut conn).await? {
             timer::Delay::new(std::time::Duration::from_millis(20)).await;
         }
+        if let Some(partitions) = self.partitions.as_mut() {
+            let block_num = (*blk.inner.block.header().number()).saturated_into();
+            partitions.ensure(&mut conn, std::iter::once(block_num)).await?;
+        }
         std::mem::drop(conn);
+        let upgrade = blk.upgrade.clone();
         self.db.insert(blk).await?;
+        if let Some(upgrade) = upgrade {
+            self.upgrade_handler(upgrade).await?;
+        }
         Ok(())
     }
 
-    async fn batch_block_handler(&self, mut blks: BatchBlock<B>) -> ArchiveResult<()> 
-    where
-        NumberFor<B>: Into<u32>,
-    {
+    async fn batch_block_handler(&mut self, mut blks: BatchBlock<B>) -> ArchiveResult<()> {
         let specs = blks.mut_inner();
         specs.sort_by_key(|b| b.spec);
         let mut specs = specs.iter_mut().map(|b| b.spec).collect::<Vec<u32>>();
@@ -73,8 +100,20 @@ impl<B: BlockT> DatabaseActor<B> {
             }
             timer::Delay::new(std::time::Duration::from_millis(50)).await;
         }
+        if let Some(partitions) = self.partitions.as_mut() {
+            let block_nums = blks.inner().iter().map(|b| (*b.inner.block.header().number()).saturated_into());
+            partitions.ensure(&mut conn, block_nums).await?;
+        }
         std::mem::drop(conn);
+        let upgrades = blks
+            .inner()
+            .iter()
+            .filter_map(|b| b.upgrade.clone())
+            .collect::<Vec<_>>();
         self.db.insert(blks).await?;
+        for upgrade in upgrades.into_iter() {
+            self.upgrade_handler(upgrade).await?;
+        }
         Ok(())
     }
 
@@ -83,23 +122,50 @@ impl<B: BlockT> DatabaseActor<B> {
         while !queries::contains_block::<B>(*storage.hash(), &mut conn).await? {
             timer::Delay::new(std::time::Duration::from_millis(10)).await;
         }
+        let balances = self.balances(std::slice::from_ref(&storage));
+        let children = ChildStorageModel::from_storage(&storage);
         let storage = Vec::<StorageModel<B>>::from(storage);
         std::mem::drop(conn);
         self.db.insert(storage).await?;
+        if !children.is_empty() {
+            self.db.insert(children).await?;
+        }
+        if !balances.is_empty() {
+            self.db.insert(balances).await?;
+        }
         Ok(())
     }
 
     async fn batch_storage_handler(&self, storage: Vec<Storage<B>>) -> ArchiveResult<()> {
         let mut conn = self.db.conn().await?;
-        let block_nums: Vec<u32> = storage.iter().map(|s| s.block_num()).collect();
+        let block_nums: Vec<u64> = storage.iter().map(|s| s.block_num()).collect();
         while !queries::contains_blocks::<B>(block_nums.as_slice(), &mut conn).await? {
             timer::Delay::new(std::time::Duration::from_millis(50)).await;
         }
+        let balances = self.balances(storage.as_slice());
+        let children = storage
+            .iter()
+            .flat_map(ChildStorageModel::from_storage)
+            .collect::<Vec<_>>();
         let storage = Vec::<StorageModel<B>>::from(VecStorageWrap(storage));
         std::mem::drop(conn);
         self.db.insert(storage).await?;
+        if !children.is_empty() {
+            self.db.insert(children).await?;
+        }
+        if !balances.is_empty() {
+            self.db.insert(balances).await?;
+        }
         Ok(())
     }
+
+    /// balances changed in `storage`, if balances are indexed
+    fn balances(&self, storage: &[Storage<B>]) -> Vec<BalanceModel<B>> {
+        if !self.index_balances {
+            return Vec::new();
+        }
+        storage.iter().flat_map(BalanceModel::from_storage).collect()
+    }
 }
 
 // Returns true if all versions are in database
@@ -123,12 +189,9 @@ impl<B: BlockT> Actor for DatabaseActor<B> {}
 impl<B> Handler<Block<B>> for DatabaseActor<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
-    async fn handle(&mut self, blk: Block<B>, _: &mut Context<Self>) {
-        if let Err(e) = self.block_handler(blk).await {
-            log::error!("{}", e.to_string())
-        }
+    async fn handle(&mut self, blk: Block<B>, _: &mut Context<Self>) -> ArchiveResult<()> {
+        self.block_handler(blk).await
     }
 }
 
@@ -136,38 +199,33 @@ where
 impl<B> Handler<BatchBlock<B>> for DatabaseActor<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
-    async fn handle(&mut self, blks: BatchBlock<B>, _: &mut Context<Self>) {
+    async fn handle(&mut self, blks: BatchBlock<B>, _: &mut Context<Self>) -> ArchiveResult<()> {
         let now = std::time::Instant::now();
-        if let Err(e) = self.batch_block_handler(blks).await {
-            log::error!("{}", e.to_string());
-        }
+        self.batch_block_handler(blks).await?;
         log::debug!("TOOK {:?} to insert blocks", now.elapsed());
+        Ok(())
     }
 }
 
 #[async_trait::async_trait]
 impl<B: BlockT> Handler<Metadata> for DatabaseActor<B> {
-    async fn handle(&mut self, meta: Metadata, _ctx: &mut Context<Self>) {
-        if let Err(e) = self.db.insert(meta).await {
-            log::error!("{}", e.to_string());
-        }
+    async fn handle(&mut self, meta: Metadata, _ctx: &mut Context<Self>) -> ArchiveResult<()> {
+        self.db.insert(meta).await?;
+        Ok(())
     }
 }
 
 #[async_trait::async_trait]
 impl<B: BlockT> Handler<Storage<B>> for DatabaseActor<B> {
-    async fn handle(&mut self, storage: Storage<B>, _ctx: &mut Context<Self>) {
-        if let Err(e) = self.storage_handler(storage).await {
-            log::error!("{}", e.to_string())
-        }
+    async fn handle(&mut self, storage: Storage<B>, _ctx: &mut Context<Self>) -> ArchiveResult<()> {
+        self.storage_handler(storage).await
     }
 }
 pub struct VecStorageWrap<B: BlockT>(pub Vec<Storage<B>>);
 
 impl<B: BlockT> Message for VecStorageWrap<B> {
-    type Result = ();
+    type Result = ArchiveResult<()>;
 }
 
 #[async_trait::async_trait]
@@ -176,12 +234,11 @@ impl<B: BlockT> Handler<VecStorageWrap<B>> for DatabaseActor<B> {
         &mut self,
         storage: VecStorageWrap<B>,
         _ctx: &mut Context<Self>,
-    ) {
+    ) -> ArchiveResult<()> {
         let now = std::time::Instant::now();
-        if let Err(e) = self.batch_storage_handler(storage.0).await {
-            log::error!("{}", e.to_string());
-        }
+        self.batch_storage_handler(storage.0).await?;
         log::debug!("took {:?} to insert storage", now.elapsed());
+        Ok(())
     }
 }
 
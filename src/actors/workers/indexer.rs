@@ -0,0 +1,358 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Feeds the storage changes of blocks to the registered `StorageIndexer`s.
+//! Blocks are executed out of order, so changes are held back until
+//! every block before them has been indexed. At most `MAX_PENDING` blocks are held back;
+//! the changes of blocks beyond that are dropped, and their blocks are executed again
+//! once the indexers catch up with them.
+
+use crate::{
+    actors::{ActorContext, Supervisor},
+    backend::{BlockChanges, ReadOnlyBackend},
+    database::{sql_num, Database},
+    error::{ArchiveResult, Error as ArchiveError},
+    indexer::StorageIndexer,
+    queries,
+};
+use parking_lot::RwLock;
+use sp_runtime::{generic::BlockId, traits::Block as BlockT, SaturatedConversion};
+use sqlx::Connection as _;
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use xtra::prelude::*;
+
+/// How many blocks may wait for the blocks before them to be indexed
+const MAX_PENDING: usize = 512;
+/// How long to wait for a block to be inserted before its changes are indexed
+const BLOCK_INSERT_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Checkpointed<B: BlockT> {
+    indexer: Arc<dyn StorageIndexer<B>>,
+    /// the last block this indexer has indexed
+    checkpoint: Option<u64>,
+}
+
+impl<B: BlockT> Checkpointed<B> {
+    /// the genesis block is never executed, so indexing starts at block 1
+    fn next_block(&self) -> u64 {
+        self.checkpoint.map(|c| c + 1).unwrap_or(1)
+    }
+}
+
+pub struct IndexerActor<B: BlockT> {
+    db: Database,
+    backend: Arc<ReadOnlyBackend<B>>,
+    indexers: Vec<Checkpointed<B>>,
+    /// changes of blocks that can't be indexed until the blocks before them are
+    pending: BTreeMap<u64, BlockChanges<B>>,
+    /// first and last block whose changes were dropped because too many blocks were pending
+    dropped: Option<(u64, u64)>,
+    /// the block whose changes were last requested again
+    requested: Option<u64>,
+    /// blocks sent here are fetched and executed again
+    reindex: flume::Sender<u64>,
+    supervisor: Supervisor,
+}
+
+impl<B> IndexerActor<B>
+where
+    B: BlockT,
+{
+    /// Runs the migrations of every indexer and loads their checkpoints
+    pub async fn new(
+        ctx: &ActorContext<B>,
+        supervisor: Supervisor,
+        reindex: flume::Sender<u64>,
+    ) -> ArchiveResult<Self> {
+        let db = Database::new(ctx.psql_url().to_string()).await?;
+        let mut conn = db.conn().await?;
+        let mut indexers = Vec::new();
+        for indexer in ctx.indexers().iter() {
+            for migration in indexer.migrations() {
+                sqlx::query(migration.as_str()).execute(&mut *conn).await?;
+            }
+            let checkpoint = queries::indexer_checkpoint(indexer.name(), &mut conn).await?;
+            log::info!(
+                "indexer {} resuming after block {:?}",
+                indexer.name(),
+                checkpoint
+            );
+            indexers.push(Checkpointed {
+                indexer: indexer.clone(),
+                checkpoint,
+            });
+        }
+        std::mem::drop(conn);
+        Ok(Self {
+            db,
+            backend: ctx.backend().clone(),
+            indexers,
+            pending: BTreeMap::new(),
+            dropped: None,
+            requested: None,
+            reindex,
+            supervisor,
+        })
+    }
+
+    /// Spawn the actor, and restart it with fresh state whenever it stops.
+    /// The returned reference always points to the running actor
+    pub fn supervise(self, ctx: ActorContext<B>) -> IndexerRef<B> {
+        let (supervisor, reindex) = (self.supervisor.clone(), self.reindex.clone());
+        let addr = Arc::new(RwLock::new(self.spawn()));
+        let indexer = IndexerRef { addr: addr.clone() };
+        crate::util::spawn(async move {
+            loop {
+                timer::Delay::new(Duration::from_secs(1)).await;
+                if addr.read().is_connected() {
+                    continue;
+                }
+                if !supervisor.restarted("indexer") {
+                    break;
+                }
+                match IndexerActor::new(&ctx, supervisor.clone(), reindex.clone()).await {
+                    Ok(indexer) => {
+                        // changes sent to the stopped actor are lost, so execute its next block
+                        // again to notice the gap once newer changes arrive
+                        let _ = reindex.send(indexer.next_block());
+                        *addr.write() = indexer.spawn();
+                    }
+                    Err(e) => {
+                        supervisor.escalate(e);
+                        break;
+                    }
+                }
+            }
+            Ok(())
+        });
+        indexer
+    }
+
+    /// the first block that any of the indexers still has to index
+    pub fn next_block(&self) -> u64 {
+        self.indexers
+            .iter()
+            .map(Checkpointed::next_block)
+            .min()
+            .unwrap_or(1)
+    }
+
+    /// index every pending block that directly follows the blocks already indexed
+    async fn index_pending(&mut self) -> ArchiveResult<()> {
+        loop {
+            let next = self.next_block();
+            // a block may be executed more than once
+            self.pending = self.pending.split_off(&next);
+            let changes = match self.pending.remove(&next) {
+                Some(c) => c,
+                None => break,
+            };
+            if let Err(e) = self.index(next, &changes).await {
+                // try again when the next changes come in
+                self.pending.insert(next, changes);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Hold back the changes of `block_num` until the blocks before it are indexed.
+    /// If too many blocks are held back, the changes of the last one are dropped
+    fn hold_back(&mut self, block_num: u64, changes: BlockChanges<B>) {
+        if block_num < self.next_block() {
+            return;
+        }
+        self.pending.insert(block_num, changes);
+        if self.pending.len() > MAX_PENDING {
+            let last = *self
+                .pending
+                .keys()
+                .next_back()
+                .expect("more than MAX_PENDING; qed");
+            self.pending.remove(&last);
+            self.dropped = Some(match self.dropped {
+                Some((from, to)) => (from.min(last), to.max(last)),
+                None => (last, last),
+            });
+        }
+    }
+
+    /// Execute the next block again if the indexers cannot move on without it.
+    /// That is the case if its changes were dropped, or if they never arrived
+    /// although `MAX_PENDING` blocks after it did.
+    fn request_missing(&mut self) -> ArchiveResult<()> {
+        let next = self.next_block();
+        let stalled = self.pending.len() >= MAX_PENDING;
+        let was_dropped = self.dropped.map_or(false, |(from, _)| from <= next);
+        if self.pending.contains_key(&next) || self.requested == Some(next) {
+            return Ok(());
+        }
+        if !stalled && !was_dropped {
+            return Ok(());
+        }
+        // request the blocks up to the first pending block, but never more than fit in `pending`
+        let mut end = next.saturating_add(MAX_PENDING as u64);
+        if let Some(first) = self.pending.keys().next() {
+            end = end.min(*first);
+        }
+        if let Some((_, to)) = self.dropped {
+            if self.pending.is_empty() {
+                end = end.min(to + 1);
+            }
+        }
+        for block_num in next..end {
+            self.reindex.send(block_num)?;
+        }
+        self.requested = Some(next);
+        self.dropped = self
+            .dropped
+            .filter(|(_, to)| *to >= end)
+            .map(|(from, to)| (from.max(end), to));
+        if stalled {
+            self.supervisor
+                .report(ArchiveError::IndexerStalled { block_num: next });
+        } else {
+            log::info!("executing blocks {}..{} again for the indexers", next, end);
+        }
+        Ok(())
+    }
+
+    async fn index(&mut self, block_num: u64, changes: &BlockChanges<B>) -> ArchiveResult<()> {
+        let block = self
+            .backend
+            .block(&BlockId::Hash(changes.block_hash))
+            .ok_or_else(|| ArchiveError::BlockNotFound(format!("{:?}", changes.block_hash)))?;
+        let mut conn = self.db.conn().await?;
+        // indexers may reference the blocks table.
+        // The changes stay pending if the block is not inserted in time, and are indexed later
+        let deadline = Instant::now() + BLOCK_INSERT_TIMEOUT;
+        while !queries::contains_block::<B>(changes.block_hash, &mut conn).await? {
+            if Instant::now() >= deadline {
+                return Err(ArchiveError::BlockNotArchived { block_num });
+            }
+            timer::Delay::new(Duration::from_millis(10)).await;
+        }
+        for c in self.indexers.iter_mut() {
+            if c.next_block() != block_num {
+                continue;
+            }
+            let mut tx = conn.begin().await?;
+            c.indexer.index(&block, changes, &mut tx).await?;
+            sqlx::query(
+                r#"
+                INSERT INTO indexer_checkpoints (name, block_num) VALUES ($1, $2)
+                ON CONFLICT (name) DO UPDATE SET block_num = EXCLUDED.block_num
+                "#,
+            )
+            .bind(c.indexer.name())
+            .bind(sql_num(block_num)?)
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            c.checkpoint = Some(block_num);
+        }
+        Ok(())
+    }
+}
+
+impl<B: BlockT> Actor for IndexerActor<B> {}
+
+/// Index blocks again from `from` onwards.
+/// Responds with the last block any indexer had indexed before rewinding.
+pub struct Rewind {
+    pub from: u64,
+    /// delete what the indexers have indexed from `from` onwards
+    pub delete_existing: bool,
+}
+
+impl Message for Rewind {
+    type Result = ArchiveResult<Option<u64>>;
+}
+
+#[async_trait::async_trait]
+impl<B> Handler<Rewind> for IndexerActor<B>
+where
+    B: BlockT,
+{
+    async fn handle(
+        &mut self,
+        rewind: Rewind,
+        _: &mut Context<Self>,
+    ) -> ArchiveResult<Option<u64>> {
+        let last = self.indexers.iter().filter_map(|c| c.checkpoint).max();
+        let checkpoint = rewind.from.saturating_sub(1);
+        let mut conn = self.db.conn().await?;
+        for c in self.indexers.iter_mut() {
+            if c.next_block() <= rewind.from {
+                continue;
+            }
+            let mut tx = conn.begin().await?;
+            if rewind.delete_existing {
+                c.indexer.delete_from(rewind.from, &mut tx).await?;
+            }
+            sqlx::query("UPDATE indexer_checkpoints SET block_num = $2 WHERE name = $1")
+                .bind(c.indexer.name())
+                .bind(sql_num(checkpoint)?)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            c.checkpoint = Some(checkpoint);
+        }
+        Ok(last)
+    }
+}
+
+#[async_trait::async_trait]
+impl<B> Handler<BlockChanges<B>> for IndexerActor<B>
+where
+    B: BlockT,
+{
+    async fn handle(
+        &mut self,
+        changes: BlockChanges<B>,
+        _: &mut Context<Self>,
+    ) -> ArchiveResult<()> {
+        let block_num: u64 = changes.block_num.saturated_into();
+        self.hold_back(block_num, changes);
+        let indexed = self.index_pending().await;
+        self.request_missing()?;
+        indexed
+    }
+}
+
+/// Address of the indexer actor, which is replaced whenever the actor is restarted
+pub struct IndexerRef<B: BlockT> {
+    addr: Arc<RwLock<Address<IndexerActor<B>>>>,
+}
+
+impl<B: BlockT> Clone for IndexerRef<B> {
+    fn clone(&self) -> Self {
+        Self {
+            addr: self.addr.clone(),
+        }
+    }
+}
+
+impl<B: BlockT> IndexerRef<B> {
+    /// address of the running actor
+    pub fn addr(&self) -> Address<IndexerActor<B>> {
+        self.addr.read().clone()
+    }
+}
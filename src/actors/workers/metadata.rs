@@ -13,7 +13,11 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::{database::GetState, ActorPool};
+use super::{
+    actor_ext::{MinionAddressExt, Supervisor},
+    database::GetState,
+    ActorPool,
+};
 use crate::{
     database::DbConn,
     error::ArchiveResult,
@@ -22,7 +26,7 @@ use crate::{
     types::{BatchBlock, Block, Metadata as MetadataT},
 };
 use itertools::Itertools;
-use sp_runtime::traits::{Block as BlockT, Header as _, NumberFor};
+use sp_runtime::traits::{Block as BlockT, Header as _};
 use xtra::prelude::*;
 
 /// Actor to fetch metadata about a block/blocks from RPC
@@ -31,16 +35,23 @@ pub struct Metadata<B: BlockT> {
     addr: Address<ActorPool<super::DatabaseActor<B>>>,
     conn: DbConn,
     rpc: Rpc<B>,
+    supervisor: Supervisor,
 }
 
 impl<B: BlockT> Metadata<B> {
     pub async fn new(
         url: String,
         addr: Address<ActorPool<super::DatabaseActor<B>>>,
+        supervisor: Supervisor,
     ) -> ArchiveResult<Self> {
-        let rpc = super::connect::<B>(url.as_str()).await;
-        let conn = addr.send(GetState::Conn.into()).await?.await?.conn();
-        Ok(Self { conn, addr, rpc })
+        let rpc = super::connect::<B>(url.as_str()).await?;
+        let conn = addr.send(GetState::Conn.into()).await?.await??.conn();
+        Ok(Self {
+            conn,
+            addr,
+            rpc,
+            supervisor,
+        })
     }
 
     // checks if the metadata exists in the database
@@ -50,25 +61,19 @@ impl<B: BlockT> Metadata<B> {
         if !queries::check_if_meta_exists(ver, &mut self.conn).await? {
             let meta = rpc.metadata(Some(hash)).await?;
             let meta = MetadataT::new(ver, meta);
-            self.addr.do_send(meta.into())?;
+            self.addr.handled_do_send(meta.into(), &self.supervisor)?;
         }
         Ok(())
     }
 
-    async fn block_handler(&mut self, blk: Block<B>) -> ArchiveResult<()> 
-    where
-        NumberFor<B>: Into<u32> 
-    {
+    async fn block_handler(&mut self, blk: Block<B>) -> ArchiveResult<()> {
         let hash = blk.inner.block.header().hash();
         self.meta_checker(blk.spec, hash).await?;
-        self.addr.do_send(blk.into())?;
+        self.addr.handled_do_send(blk.into(), &self.supervisor)?;
         Ok(())
     }
 
-    async fn batch_block_handler(&mut self, blks: BatchBlock<B>) -> ArchiveResult<()> 
-    where
-        NumberFor<B>: Into<u32>
-    {
+    async fn batch_block_handler(&mut self, blks: BatchBlock<B>) -> ArchiveResult<()> {
         let versions = blks
             .inner()
             .iter()
@@ -78,7 +83,7 @@ impl<B: BlockT> Metadata<B> {
         for b in versions.iter() {
            self.meta_checker(b.spec, b.inner.block.hash()).await?;
         }
-        self.addr.do_send(blks.into())?;
+        self.addr.handled_do_send(blks.into(), &self.supervisor)?;
         Ok(())
     }
 }
@@ -89,12 +94,9 @@ impl<B: BlockT> Actor for Metadata<B> {}
 impl<B> Handler<Block<B>> for Metadata<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
-    async fn handle(&mut self, blk: Block<B>, _: &mut Context<Self>) {
-        if let Err(e) = self.block_handler(blk).await {
-            log::error!("{}", e.to_string());
-        }
+    async fn handle(&mut self, blk: Block<B>, _: &mut Context<Self>) -> ArchiveResult<()> {
+        self.block_handler(blk).await
     }
 }
 
@@ -102,12 +104,8 @@ where
 impl<B> Handler<BatchBlock<B>> for Metadata<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
-    async fn handle(&mut self, blks: BatchBlock<B>, _: &mut Context<Self>) {
-        if let Err(e) = self.batch_block_handler(blks).await {
-            log::error!("{}", e.to_string());
-        }
-      
+    async fn handle(&mut self, blks: BatchBlock<B>, _: &mut Context<Self>) -> ArchiveResult<()> {
+        self.batch_block_handler(blks).await
     }
 }
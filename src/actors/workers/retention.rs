@@ -0,0 +1,257 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Enforces the `RetentionPolicy` of the archive.
+//! Storage changes of blocks older than the horizon are deleted,
+//! and metadata that no block refers to anymore is dropped.
+//! If the policy writes snapshots, the horizon moves in steps of `keep_blocks`,
+//! so that a snapshot is written at most once every `keep_blocks` blocks.
+
+use crate::{
+    actors::{ActorContext, Supervisor},
+    backend::ReadOnlyBackend,
+    database::{
+        models::{ChildStorageModel, StorageModel},
+        sql_num, Database, DbConn, Insert,
+    },
+    error::{ArchiveResult, Error as ArchiveError},
+    queries,
+    types::RetentionPolicy,
+};
+use sc_client_api::backend::{Backend, StateBackend};
+use sp_blockchain::HeaderBackend;
+use sp_runtime::{generic::BlockId, traits::Block as BlockT, SaturatedConversion};
+use sp_storage::{well_known_keys, ChildInfo, StorageData, StorageKey};
+use sqlx::Connection as _;
+use std::sync::Arc;
+use xtra::prelude::*;
+
+/// How many keys of a snapshot are read from the state and inserted at once
+const SNAPSHOT_CHUNK_SIZE: usize = 10_000;
+
+pub struct Pruner<B: BlockT> {
+    db: Database,
+    backend: Arc<ReadOnlyBackend<B>>,
+    policy: RetentionPolicy,
+    /// storage of blocks before this one has been pruned
+    horizon: Option<u64>,
+    supervisor: Supervisor,
+}
+
+impl<B> Pruner<B>
+where
+    B: BlockT,
+{
+    pub async fn new(
+        ctx: &ActorContext<B>,
+        policy: RetentionPolicy,
+        supervisor: Supervisor,
+    ) -> ArchiveResult<Self> {
+        let db = Database::new(ctx.psql_url().to_string()).await?;
+        let mut conn = db.conn().await?;
+        let horizon = queries::retention_horizon(&mut conn).await?;
+        std::mem::drop(conn);
+        Ok(Self {
+            db,
+            backend: ctx.backend().clone(),
+            policy,
+            horizon,
+            supervisor,
+        })
+    }
+
+    async fn prune(&mut self) -> ArchiveResult<()> {
+        let mut conn = self.db.conn().await?;
+        let latest: (Option<i64>,) = sqlx::query_as("SELECT MAX(block_num) FROM storage")
+            .fetch_one(&mut *conn)
+            .await?;
+        let keep_blocks = u64::from(self.policy.keep_blocks);
+        let mut horizon = match latest.0 {
+            Some(latest) => (latest as u64).saturating_sub(keep_blocks),
+            None => return Ok(()),
+        };
+        if self.policy.snapshot {
+            horizon -= horizon % keep_blocks.max(1);
+        }
+        if horizon == 0 || self.horizon.map_or(false, |h| h >= horizon) {
+            return Ok(());
+        }
+
+        if self.policy.snapshot {
+            self.snapshot(horizon).await?;
+        }
+        let mut tx = conn.begin().await?;
+        let deleted = sqlx::query("DELETE FROM storage WHERE block_num < $1")
+            .bind(sql_num(horizon)?)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        sqlx::query("DELETE FROM child_storage WHERE block_num < $1")
+            .bind(sql_num(horizon)?)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO retention_horizon (block_num) VALUES ($1)
+            ON CONFLICT (id) DO UPDATE SET block_num = EXCLUDED.block_num
+            "#,
+        )
+        .bind(sql_num(horizon)?)
+        .execute(&mut *tx)
+        .await?;
+        // metadata of the latest runtime may be inserted before any of its blocks
+        let orphans = sqlx::query(
+            r#"
+            DELETE FROM metadata
+            WHERE version < (SELECT MAX(spec) FROM blocks)
+            AND NOT EXISTS (SELECT 1 FROM blocks WHERE blocks.spec = metadata.version)
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        tx.commit().await?;
+        self.horizon = Some(horizon);
+        log::info!(
+            "pruned {} storage changes before block {} and {} unused metadata versions",
+            deleted,
+            horizon,
+            orphans
+        );
+        Ok(())
+    }
+
+    /// Write the full state at block `block_num`, including the state of every child trie.
+    /// The state is walked key by key and inserted in chunks of `SNAPSHOT_CHUNK_SIZE`,
+    /// so it is never held in memory at once
+    async fn snapshot(&self, block_num: u64) -> ArchiveResult<()> {
+        let hash = HeaderBackend::hash(&*self.backend, block_num.saturated_into())?
+            .ok_or_else(|| ArchiveError::BlockNotFound(block_num.to_string()))?;
+        let state = Backend::state_at(&*self.backend, BlockId::Hash(hash))
+            .map_err(|e| ArchiveError::state_unavailable(hash, e))?;
+        let mut conn = self.db.conn().await?;
+        let prefix = well_known_keys::DEFAULT_CHILD_STORAGE_KEY_PREFIX;
+        let (mut child_keys, mut key) = (Vec::new(), Vec::new());
+        let written = insert_chunked(&mut conn, || {
+            key = match state
+                .next_storage_key(&key)
+                .map_err(ArchiveError::Blockchain)?
+            {
+                Some(k) => k,
+                None => return Ok(None),
+            };
+            // the roots of child tries are stored in the top trie under their prefixed key
+            if key.starts_with(prefix) {
+                child_keys.push(key[prefix.len()..].to_vec());
+            }
+            let value = state.storage(&key).map_err(ArchiveError::Blockchain)?;
+            Ok(Some(StorageModel::<B>::new(
+                hash,
+                block_num,
+                true,
+                StorageKey(key.clone()),
+                value.map(StorageData),
+            )))
+        })
+        .await?;
+
+        let mut child_written = 0;
+        for child_key in child_keys.iter() {
+            let child_info = ChildInfo::new_default(child_key);
+            let mut key = Vec::new();
+            child_written += insert_chunked(&mut conn, || {
+                key = match state
+                    .next_child_storage_key(&child_info, &key)
+                    .map_err(ArchiveError::Blockchain)?
+                {
+                    Some(k) => k,
+                    None => return Ok(None),
+                };
+                let value = state
+                    .child_storage(&child_info, &key)
+                    .map_err(ArchiveError::Blockchain)?;
+                Ok(Some(ChildStorageModel::<B>::new(
+                    hash,
+                    block_num,
+                    true,
+                    StorageKey(child_key.clone()),
+                    StorageKey(key.clone()),
+                    value.map(StorageData),
+                )))
+            })
+            .await?;
+        }
+        log::info!(
+            "wrote {} keys of the state and {} keys of {} child tries at block {}",
+            written,
+            child_written,
+            child_keys.len(),
+            block_num
+        );
+        Ok(())
+    }
+}
+
+/// Insert what `next` yields in chunks of `SNAPSHOT_CHUNK_SIZE`, until it yields `None`.
+/// Returns how many items were inserted
+async fn insert_chunked<T, F>(conn: &mut DbConn, mut next: F) -> ArchiveResult<usize>
+where
+    Vec<T>: Insert + Send,
+    F: FnMut() -> ArchiveResult<Option<T>> + Send,
+{
+    let mut written = 0;
+    loop {
+        let mut chunk = Vec::with_capacity(SNAPSHOT_CHUNK_SIZE);
+        while chunk.len() < SNAPSHOT_CHUNK_SIZE {
+            match next()? {
+                Some(item) => chunk.push(item),
+                None => break,
+            }
+        }
+        let done = chunk.len() < SNAPSHOT_CHUNK_SIZE;
+        written += chunk.len();
+        chunk.insert(conn).await?;
+        if done {
+            return Ok(written);
+        }
+    }
+}
+
+impl<B: BlockT> Actor for Pruner<B> {
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        ctx.notify_interval(self.policy.interval, || Prune);
+    }
+}
+
+#[derive(Clone)]
+pub struct Prune;
+
+impl Message for Prune {
+    type Result = ();
+}
+
+#[async_trait::async_trait]
+impl<B> Handler<Prune> for Pruner<B>
+where
+    B: BlockT,
+{
+    async fn handle(&mut self, _: Prune, _: &mut Context<Self>) {
+        if let Err(e) = self.prune().await {
+            // the policy is enforced again next interval
+            self.supervisor.report(e);
+        }
+    }
+}
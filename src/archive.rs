@@ -15,26 +15,43 @@
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::{
-    actors::System,
-    backend::{self, frontend::TArchiveClient, ApiAccess, ReadOnlyBackend, ReadOnlyDatabase},
+    actors::{IndexingOptions, System},
+    author::AuthorResolver,
+    backend::{
+        self,
+        frontend::{self, TArchiveClient},
+        ApiAccess, ExecutionMethod, ReadOnlyBackend, ReadOnlyDatabase, SecondaryDir, TrieNodeCache,
+    },
+    chain_info::ChainInfo,
+    consistency::ConsistencyReport,
     error::{ArchiveResult, Error as ArchiveError},
+    indexer::StorageIndexer,
     migrations::MigrationConfig,
+    proof::ProofKey,
+    queries,
     rpc::Rpc,
-    types,
+    types::{self, RetentionPolicy},
+    verify::{Verification, VerificationReport},
 };
 
+use codec::{Decode, Encode};
+use parking_lot::Mutex;
 use sc_chain_spec::ChainSpec;
-use sc_client_api::backend as api_backend;
+use sc_client_api::{
+    backend as api_backend, execution_extensions::ExecutionStrategies, CallExecutor,
+};
 use sc_executor::NativeExecutionDispatch;
 use sp_api::{ApiExt, ConstructRuntimeApi};
 use sp_block_builder::BlockBuilder as BlockBuilderApi;
+use sp_blockchain::HeaderBackend;
 use sp_runtime::{
     generic::BlockId,
-    traits::{BlakeTwo256, Block as BlockT, NumberFor},
-    RuntimeString,
+    traits::{BlakeTwo256, Block as BlockT, Hash as _, HashFor, Header as _, NumberFor},
 };
-use sp_version::RuntimeVersion;
-use std::{marker::PhantomData, sync::Arc};
+use sp_state_machine::StorageProof;
+use sp_storage::{StorageData, StorageKey};
+use sqlx::{Connection as _, PgConnection};
+use std::{marker::PhantomData, sync::Arc, time::Duration};
 
 /// Main entrypoint for substrate-archive.
 /// Deals with starting, stopping and manipulating the Actors
@@ -51,12 +68,20 @@ use std::{marker::PhantomData, sync::Arc};
 ///     cache_size: 1024,
 ///     block_workers: None,
 ///     wasm_pages: None,
+///     wasm_execution: None,
+///     execution_strategies: None,
+///     index_balances: false,
+///     retention: None,
+///     secondary_db: None,
+///     catch_up_interval: None,
+///     trie_cache_size: None,
 ///     psql_conf: MigrationConfig {
 ///         host: None,
 ///         port: None,
 ///         user: Some("archive".to_string()),
 ///         pass: Some("default".to_string()),
 ///         name: Some("kusama-archive".to_string()),
+///         partition_size: None,
 ///     },
 /// };
 ///
@@ -64,16 +89,27 @@ use std::{marker::PhantomData, sync::Arc};
 /// let archive = Archive::<Block, RApi, KExec>::new(conf, Box::new(spec)).unwrap();
 /// let archive = archive.run().unwrap();
 ///
-/// archive.block_until_stopped();
+/// archive.block_until_stopped().unwrap();
 ///
 /// ```
-pub struct ArchiveBuilder<Block, Runtime, Dispatch> {
+pub struct ArchiveBuilder<Block: BlockT, Runtime, Dispatch> {
     rpc_url: String,
     psql_url: String,
     db: Arc<ReadOnlyDatabase>,
-    // spec: Box<dyn ChainSpec>,
+    chain: ChainInfo,
+    /// genesis storage built from the chain spec
+    genesis: sp_storage::Storage,
     block_workers: Option<usize>,
     wasm_pages: Option<u64>,
+    wasm_execution: ExecutionMethod,
+    execution_strategies: ExecutionStrategies,
+    options: IndexingOptions<Block>,
+    partition_size: Option<u32>,
+    catch_up_interval: Duration,
+    /// trie nodes, shared by the backends that execute blocks
+    trie_cache: Option<Arc<TrieNodeCache>>,
+    /// client of `call_at`, created by the first call
+    call_client: Mutex<Option<Arc<TArchiveClient<Block, Runtime, Dispatch>>>>,
     _marker: PhantomData<(Block, Runtime, Dispatch)>,
 }
 
@@ -90,6 +126,52 @@ pub struct ArchiveConfig {
     pub block_workers: Option<usize>,
     /// Number of 64KB Heap pages to allocate for wasm execution
     pub wasm_pages: Option<u64>,
+    /// how to execute the wasm runtime. Defaults to `ExecutionMethod::Interpreted`
+    pub wasm_execution: Option<ExecutionMethod>,
+    /// which runtime (native or wasm) to use in each execution context.
+    /// Defaults to `default_execution_strategies`
+    pub execution_strategies: Option<ExecutionStrategies>,
+    /// decode account balances from storage into the `balances` table
+    pub index_balances: bool,
+    /// prune old storage changes from Postgres. Everything is kept if this is `None`
+    pub retention: Option<RetentionPolicy>,
+    /// where the secondary RocksDB instance keeps its files. Defaults to `SecondaryDir::Default`
+    pub secondary_db: Option<SecondaryDir>,
+    /// how often RocksDB catches up with the blocks written by the node. Defaults to 1 second
+    pub catch_up_interval: Option<Duration>,
+    /// how much memory (MB) the cache of trie nodes used for block execution may take.
+    /// Defaults to 256MB, `Some(0)` disables the cache
+    pub trie_cache_size: Option<usize>,
+}
+
+/// State root of the genesis storage, the way the node computes it for block 0:
+/// the roots of the child tries are part of the top trie
+fn genesis_state_root<B: BlockT>(genesis: &sp_storage::Storage) -> B::Hash {
+    let child_roots = genesis.children_default.values().map(|child| {
+        let root = HashFor::<B>::trie_root(child.data.clone().into_iter().collect());
+        (
+            child.child_info.prefixed_storage_key().into_inner(),
+            root.encode(),
+        )
+    });
+    HashFor::<B>::trie_root(genesis.top.clone().into_iter().chain(child_roots).collect())
+}
+
+/// Fails if the genesis storage does not produce the state root of block 0 in RocksDB
+fn check_genesis_state<B: BlockT>(
+    genesis: &sp_storage::Storage,
+    backend: &ReadOnlyBackend<B>,
+) -> ArchiveResult<()> {
+    let header = HeaderBackend::header(backend, BlockId::Number(0.into()))?
+        .ok_or_else(|| ArchiveError::BlockNotFound("0".to_string()))?;
+    let computed = genesis_state_root::<B>(genesis);
+    if computed != *header.state_root() {
+        return Err(ArchiveError::GenesisStateMismatch {
+            expected: format!("{:?}", header.state_root()),
+            computed: format!("{:?}", computed),
+        });
+    }
+    Ok(())
 }
 
 fn migrate(conf: MigrationConfig) -> Result<String, ArchiveError> {
@@ -120,12 +202,13 @@ where
         + 'static,
     D: NativeExecutionDispatch + 'static,
     <R::RuntimeApi as sp_api::ApiExt<B>>::StateBackend: sp_api::StateBackend<BlakeTwo256>,
-    NumberFor<B>: Into<u32> + From<u32> + Unpin,
-    B::Hash: From<primitive_types::H256> + Unpin,
+    NumberFor<B>: Unpin,
+    B::Hash: Unpin,
     B::Header: serde::de::DeserializeOwned,
 {
     /// Create a new instance of the Archive DB
     /// and run Postgres Migrations
+    /// Fails if the genesis storage of `spec` is not the genesis state of the RocksDB database
     /// Should not be run within a futures runtime
     pub fn new(conf: ArchiveConfig, spec: Box<dyn ChainSpec>) -> Result<Self, ArchiveError> {
         let psql_url = migrate(conf.psql_conf.clone())?;
@@ -133,20 +216,67 @@ where
         let db = Arc::new(backend::util::open_database(
             conf.db_url.as_str(),
             conf.cache_size,
+            &conf.secondary_db.unwrap_or_default(),
             spec.name(),
             spec.id(),
         )?);
+        let node = ReadOnlyBackend::<B>::new(db.clone(), true);
+        node.check_archive_node()?;
+        let genesis_hash = backend::util::read_genesis_hash::<B::Hash>(&db)?
+            .ok_or_else(|| ArchiveError::BlockNotFound("genesis".to_string()))?;
+        let chain = ChainInfo::new(genesis_hash.as_ref(), spec.as_ref());
+        // the genesis block is never executed, so its storage can only come from the chain spec.
+        // Its state root tells apart chains that share a chain spec id, IE Kusama and a Kusama testnet,
+        // before anything is recorded in Postgres
+        let genesis = spec
+            .as_storage_builder()
+            .build_storage()
+            .map_err(ArchiveError::GenesisStorage)?;
+        check_genesis_state(&genesis, &node)?;
         Ok(Self {
             db,
+            chain,
             psql_url,
-            // spec,
+            genesis,
             rpc_url: conf.rpc_url,
             block_workers: conf.block_workers,
             wasm_pages: conf.wasm_pages,
+            wasm_execution: conf.wasm_execution.unwrap_or_default(),
+            execution_strategies: conf
+                .execution_strategies
+                .unwrap_or_else(frontend::default_execution_strategies),
+            options: IndexingOptions {
+                index_balances: conf.index_balances,
+                retention: conf.retention,
+                ..Default::default()
+            },
+            partition_size: conf.psql_conf.partition_size,
+            catch_up_interval: conf
+                .catch_up_interval
+                .unwrap_or_else(|| Duration::from_secs(1)),
+            trie_cache: match conf.trie_cache_size.unwrap_or(256) {
+                0 => None,
+                mb => Some(Arc::new(TrieNodeCache::new(mb * 1024 * 1024))),
+            },
+            call_client: Mutex::new(None),
             _marker: PhantomData,
         })
     }
 
+    /// Resolve the author of every block that is indexed,
+    /// and store it in the `author` column of the `blocks` table
+    pub fn with_author_resolver(mut self, resolver: impl AuthorResolver<B> + 'static) -> Self {
+        self.options.author_resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Register a plugin that maintains its own tables from the storage changes of every block.
+    /// Indexers are called in the order they are registered.
+    pub fn with_indexer(mut self, indexer: impl StorageIndexer<B> + 'static) -> Self {
+        self.options.indexers.push(Arc::new(indexer));
+        self
+    }
+
     /// Create a new Substrate Client with a ReadOnlyBackend
     pub fn api_client(
         &self,
@@ -158,6 +288,9 @@ where
             self.db.clone(),
             block_workers.unwrap_or(cpus),
             wasm_pages.map(|v| v as u64).unwrap_or(2048 as u64),
+            self.wasm_execution,
+            self.execution_strategies.clone(),
+            self.trie_cache.clone(),
         )?;
         Ok(Arc::new(client))
     }
@@ -166,50 +299,167 @@ where
     /// in which the archive is running.
     pub async fn run(&self) -> Result<impl types::Archive<B>, ArchiveError> {
         let cpus = num_cpus::get();
-        let client0 = Arc::new(
+        let client = Arc::new(
             backend::runtime_api::<B, R, D>(
                 self.db.clone(),
                 self.block_workers.unwrap_or(cpus),
                 self.wasm_pages.unwrap_or(512),
+                self.wasm_execution,
+                self.execution_strategies.clone(),
+                self.trie_cache.clone(),
             )
             .map_err(ArchiveError::from)?,
         );
-        let client1 = Arc::new(
-            backend::runtime_api::<B, R, D>(self.db.clone(), 3, 64).map_err(ArchiveError::from)?,
-        );
 
-        let rt = client1.runtime_version_at(&BlockId::Number(0.into()))?;
-        self.verify_same_chain(rt)?;
-        let backend = Arc::new(ReadOnlyBackend::new(self.db.clone(), true));
+        self.verify_chain().await?;
+        self.db.catch_up_in_background(self.catch_up_interval)?;
+        let backend = Arc::new(self.backend());
+        let mut conn = PgConnection::connect(self.psql_url.as_str()).await?;
+        crate::database::ensure_storage_index(&mut conn).await?;
+        if let Some(size) = self.partition_size {
+            crate::database::partition(&mut conn, size).await?;
+        }
+        std::mem::drop(conn);
 
+        let genesis = self.genesis_storage(&backend).await?;
         let mut ctx = System::<_, R, _>::new(
-            (client0, client1),
+            client,
             backend,
             self.block_workers,
             self.rpc_url.clone(),
             self.psql_url.as_str(),
+            self.options.clone(),
         )?;
+        if let Some(genesis) = genesis {
+            ctx = ctx.with_genesis(genesis);
+        }
         ctx.drive().await?;
         Ok(ctx)
     }
 
-    /// Internal function to verify the running chain and the Runtime that was passed to us
-    /// are the same
-    fn verify_same_chain(&self, rt: RuntimeVersion) -> ArchiveResult<()> {
-        let rpc = futures::executor::block_on(Rpc::<B>::connect(self.rpc_url.as_str()))?;
-        let node_runtime = futures::executor::block_on(rpc.version(None))?;
-        let (rpc_rstr, backend_rstr) = match (node_runtime.spec_name, rt.spec_name) {
-            (RuntimeString::Borrowed(s0), RuntimeString::Borrowed(s1)) => {
-                (s0.to_string(), s1.to_string())
+    /// The genesis storage as a full snapshot of block 0,
+    /// unless it has already been archived or pruned.
+    /// The storage was checked against the state root of block 0 when the builder was created
+    async fn genesis_storage(
+        &self,
+        backend: &ReadOnlyBackend<B>,
+    ) -> ArchiveResult<Option<types::Storage<B>>> {
+        let genesis = &self.genesis;
+        let mut conn = PgConnection::connect(self.psql_url.as_str()).await?;
+        if queries::contains_full_storage(0, &mut conn).await?
+            || queries::retention_horizon(&mut conn).await?.unwrap_or(0) > 0
+        {
+            return Ok(None);
+        }
+        let hash = HeaderBackend::hash(backend, 0.into())?
+            .ok_or_else(|| ArchiveError::BlockNotFound("0".to_string()))?;
+        let top = genesis
+            .top
+            .iter()
+            .map(|(k, v)| (StorageKey(k.clone()), Some(StorageData(v.clone()))))
+            .collect();
+        let children = genesis
+            .children_default
+            .iter()
+            .map(|(child_key, child)| {
+                let data = child
+                    .data
+                    .iter()
+                    .map(|(k, v)| (StorageKey(k.clone()), Some(StorageData(v.clone()))))
+                    .collect();
+                (StorageKey(child_key.clone()), data)
+            })
+            .collect();
+        Ok(Some(
+            types::Storage::new(hash, 0, true, top).with_child_changes(children),
+        ))
+    }
+
+    /// Recompute the state roots of blocks from the storage archived in Postgres,
+    /// to check that the archive is faithful to the chain
+    pub async fn verify(&self, verification: Verification) -> ArchiveResult<VerificationReport<B>> {
+        let backend = self.backend();
+        let mut conn = PgConnection::connect(self.psql_url.as_str()).await?;
+        self.chain.check(&mut conn).await?;
+        crate::verify::verify_state_roots(&backend, &mut conn, &verification).await
+    }
+
+    /// Check the blocks in Postgres from `from` to `to` (inclusive) against the headers in RocksDB.
+    /// Inconsistencies can be repaired by passing the report to `Archive::repair`
+    pub async fn check_consistency(&self, from: u64, to: u64) -> ArchiveResult<ConsistencyReport> {
+        let backend = ReadOnlyBackend::new(self.db.clone(), true);
+        let mut conn = PgConnection::connect(self.psql_url.as_str()).await?;
+        self.chain.check(&mut conn).await?;
+        crate::consistency::check_consistency(&backend, &mut conn, from, to).await
+    }
+
+    /// Prove the values of `keys` in the state of the block `hash`
+    pub fn prove_read(&self, hash: B::Hash, keys: &[ProofKey]) -> ArchiveResult<StorageProof> {
+        crate::proof::prove_read(&self.backend(), hash, keys)
+    }
+
+    /// Check a proof against the state root archived in Postgres for the block `hash`,
+    /// returning the proven value of every key
+    pub async fn verify_read_proof(
+        &self,
+        hash: B::Hash,
+        proof: &StorageProof,
+        keys: &[ProofKey],
+    ) -> ArchiveResult<Vec<(ProofKey, Option<Vec<u8>>)>> {
+        let mut conn = PgConnection::connect(self.psql_url.as_str()).await?;
+        let state_root = queries::state_root(hash.as_ref(), &mut conn)
+            .await?
+            .ok_or_else(|| ArchiveError::BlockNotFound(format!("{:?}", hash)))?;
+        let state_root = B::Hash::decode(&mut state_root.as_slice())?;
+        crate::proof::verify_read_proof::<B>(state_root, proof, keys)
+    }
+
+    /// Call the runtime `method` with the SCALE encoded `args` in the state of `block`,
+    /// like the `state_call` RPC of a node, and return the SCALE encoded result.
+    /// The client that executes calls is created by the first call, and reused by later calls.
+    pub fn call_at(&self, block: BlockId<B>, method: &str, args: &[u8]) -> ArchiveResult<Vec<u8>> {
+        let client = {
+            let mut client = self.call_client.lock();
+            match client.as_ref() {
+                Some(c) => c.clone(),
+                None => {
+                    let c = Arc::new(backend::runtime_api::<B, R, D>(
+                        self.db.clone(),
+                        1,
+                        self.wasm_pages.unwrap_or(512),
+                        self.wasm_execution,
+                        self.execution_strategies.clone(),
+                        self.trie_cache.clone(),
+                    )?);
+                    *client = Some(c.clone());
+                    c
+                }
             }
-            (RuntimeString::Owned(s0), RuntimeString::Owned(s1)) => (s0, s1),
-            (RuntimeString::Borrowed(s0), RuntimeString::Owned(s1)) => (s0.to_string(), s1),
-            (RuntimeString::Owned(s0), RuntimeString::Borrowed(s1)) => (s0, s1.to_string()),
         };
-        if rpc_rstr.to_ascii_lowercase().as_str() != backend_rstr.to_ascii_lowercase().as_str() {
-            Err(ArchiveError::MismatchedChains(backend_rstr, rpc_rstr))
-        } else {
-            Ok(())
+        let strategy = self.execution_strategies.other;
+        Ok(client
+            .executor()
+            .call(&block, method, args, strategy, None)?)
+    }
+
+    /// backend that reads trie nodes through the shared cache
+    fn backend(&self) -> ReadOnlyBackend<B> {
+        match &self.trie_cache {
+            Some(cache) => ReadOnlyBackend::with_trie_cache(self.db.clone(), true, cache.clone()),
+            None => ReadOnlyBackend::new(self.db.clone(), true),
         }
     }
+
+    /// Refuse to run against the RPC node or Postgres database of another chain
+    async fn verify_chain(&self) -> ArchiveResult<()> {
+        let rpc = Rpc::<B>::connect(self.rpc_url.as_str()).await?;
+        let node_genesis = rpc
+            .block_hash(0)
+            .await?
+            .ok_or_else(|| ArchiveError::BlockNotFound("genesis".to_string()))?;
+        self.chain
+            .check_genesis("rpc node", node_genesis.as_ref())?;
+        let mut conn = PgConnection::connect(self.psql_url.as_str()).await?;
+        self.chain.ensure(&mut conn).await
+    }
 }
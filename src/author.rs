@@ -0,0 +1,232 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Find out who authored a block.
+//! Consensus engines record the slot or authority index of the author in a pre-runtime digest.
+//! Authorities are ordered like the validators of the session, so the index is mapped through
+//! `Session::Validators` to the account of the validator that authored the block.
+//! Chains without the session pallet have no validator accounts, and their authors are
+//! identified by the session key the engine knows them by.
+
+use crate::{
+    backend::ReadOnlyBackend,
+    error::{ArchiveResult, Error as ArchiveError},
+};
+use codec::{Decode, Encode};
+use sp_core::{crypto::AccountId32, hashing::twox_128};
+use sp_runtime::{
+    traits::{Block as BlockT, Header as HeaderT},
+    ConsensusEngineId,
+};
+use std::marker::PhantomData;
+
+const BABE_ENGINE_ID: ConsensusEngineId = *b"BABE";
+const AURA_ENGINE_ID: ConsensusEngineId = *b"aura";
+
+/// Resolves the author of a block.
+/// Implement this for chains that don't use BABE or Aura.
+pub trait AuthorResolver<B: BlockT>: Send + Sync {
+    /// The SCALE-encoded account of the block author,
+    /// or `None` if the block does not say who authored it (IE, genesis)
+    fn author(
+        &self,
+        header: &B::Header,
+        backend: &ReadOnlyBackend<B>,
+    ) -> ArchiveResult<Option<Vec<u8>>>;
+}
+
+/// Resolves authors from the BABE pre-runtime digest.
+/// The authority index in the digest is an index into the validators of the session.
+/// The first block of an epoch rotates the session, so its validators are read in its own state.
+/// Without a session pallet, the index is into `Babe::Authorities`,
+/// or into `Babe::NextAuthorities` for the first block of an epoch, which enacts them.
+pub struct BabeAuthorResolver<AccountId = AccountId32> {
+    _marker: PhantomData<AccountId>,
+}
+
+impl<AccountId> BabeAuthorResolver<AccountId> {
+    pub fn new() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<AccountId> Default for BabeAuthorResolver<AccountId> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B, AccountId> AuthorResolver<B> for BabeAuthorResolver<AccountId>
+where
+    B: BlockT,
+    AccountId: Encode + Decode + Send + Sync,
+{
+    fn author(
+        &self,
+        header: &B::Header,
+        backend: &ReadOnlyBackend<B>,
+    ) -> ArchiveResult<Option<Vec<u8>>> {
+        let mut digest = match pre_runtime_digest::<B>(header, BABE_ENGINE_ID) {
+            Some(d) => d,
+            None => return Ok(None),
+        };
+        // every variant of the BABE pre-digest starts with the authority index
+        let (_variant, authority_index): (u8, u32) =
+            Decode::decode(&mut digest).map_err(|e| ArchiveError::Decode {
+                context: "babe pre-runtime digest",
+                source: e,
+            })?;
+        let index = authority_index as usize;
+        let parent = *header.parent_hash();
+        let starts_epoch = starts_epoch::<B>(header);
+        let session_at = if starts_epoch { header.hash() } else { parent };
+        if let Some(validators) = validators::<B, AccountId>(session_at, backend)? {
+            return Ok(validators.get(index).map(Encode::encode));
+        }
+        // the first epoch is enacted from `Authorities`, since there is no next epoch at genesis
+        let next = if starts_epoch {
+            storage::<B, Vec<(AccountId, u64)>>(parent, backend, b"Babe", b"NextAuthorities")?
+                .filter(|a| !a.is_empty())
+        } else {
+            None
+        };
+        let authorities = match next {
+            Some(a) => a,
+            None => storage::<B, Vec<(AccountId, u64)>>(parent, backend, b"Babe", b"Authorities")?
+                .ok_or_else(|| missing::<B>(parent, "Babe::Authorities"))?,
+        };
+        Ok(authorities
+            .get(index)
+            .map(|(author, _weight)| author.encode()))
+    }
+}
+
+/// Resolves authors from the Aura pre-runtime digest.
+/// Aura authors take turns, so the slot is an index into `Aura::Authorities`,
+/// which is mapped to the validators of the session if the chain has a session pallet
+pub struct AuraAuthorResolver<AccountId = AccountId32> {
+    _marker: PhantomData<AccountId>,
+}
+
+impl<AccountId> AuraAuthorResolver<AccountId> {
+    pub fn new() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<AccountId> Default for AuraAuthorResolver<AccountId> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B, AccountId> AuthorResolver<B> for AuraAuthorResolver<AccountId>
+where
+    B: BlockT,
+    AccountId: Encode + Decode + Send + Sync,
+{
+    fn author(
+        &self,
+        header: &B::Header,
+        backend: &ReadOnlyBackend<B>,
+    ) -> ArchiveResult<Option<Vec<u8>>> {
+        let mut digest = match pre_runtime_digest::<B>(header, AURA_ENGINE_ID) {
+            Some(d) => d,
+            None => return Ok(None),
+        };
+        let slot: u64 = Decode::decode(&mut digest).map_err(|e| ArchiveError::Decode {
+            context: "aura pre-runtime digest",
+            source: e,
+        })?;
+        let parent = *header.parent_hash();
+        let authorities: Vec<AccountId> =
+            storage::<B, _>(parent, backend, b"Aura", b"Authorities")?
+                .ok_or_else(|| missing::<B>(parent, "Aura::Authorities"))?;
+        if authorities.is_empty() {
+            return Ok(None);
+        }
+        let index = (slot % authorities.len() as u64) as usize;
+        match validators::<B, AccountId>(parent, backend)? {
+            Some(validators) => Ok(validators.get(index).map(Encode::encode)),
+            None => Ok(authorities.get(index).map(Encode::encode)),
+        }
+    }
+}
+
+/// data of the first pre-runtime digest item of `engine`
+fn pre_runtime_digest<B: BlockT>(header: &B::Header, engine: ConsensusEngineId) -> Option<&[u8]> {
+    header
+        .digest()
+        .logs()
+        .iter()
+        .find_map(|d| d.as_pre_runtime().filter(|(id, _)| *id == engine))
+        .map(|(_, data)| data)
+}
+
+/// Whether `header` is the first block of a BABE epoch.
+/// Such blocks announce the next epoch with a `NextEpochData` consensus digest
+fn starts_epoch<B: BlockT>(header: &B::Header) -> bool {
+    // `NextEpochData` is the first variant of BABE's `ConsensusLog`
+    header.digest().logs().iter().any(|d| {
+        d.as_consensus().map_or(false, |(id, data)| {
+            id == BABE_ENGINE_ID && data.first() == Some(&1)
+        })
+    })
+}
+
+/// The validator accounts of the session at block `at`,
+/// or `None` if the chain does not have a session pallet
+fn validators<B, AccountId>(
+    at: B::Hash,
+    backend: &ReadOnlyBackend<B>,
+) -> ArchiveResult<Option<Vec<AccountId>>>
+where
+    B: BlockT,
+    AccountId: Decode,
+{
+    storage::<B, _>(at, backend, b"Session", b"Validators")
+}
+
+/// The storage value `pallet::item` at block `at`, if it is in storage
+fn storage<B, T>(
+    at: B::Hash,
+    backend: &ReadOnlyBackend<B>,
+    pallet: &[u8],
+    item: &[u8],
+) -> ArchiveResult<Option<T>>
+where
+    B: BlockT,
+    T: Decode,
+{
+    let key = [twox_128(pallet), twox_128(item)].concat();
+    backend
+        .storage(at, key.as_slice())?
+        .map(|v| {
+            Decode::decode(&mut v.as_slice()).map_err(|e| ArchiveError::Decode {
+                context: "consensus storage",
+                source: e,
+            })
+        })
+        .transpose()
+}
+
+fn missing<B: BlockT>(at: B::Hash, item: &str) -> ArchiveError {
+    ArchiveError::MissingState(format!("no {} at {:?}", item, at))
+}
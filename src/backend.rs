@@ -20,15 +20,25 @@ mod block_exec;
 mod database;
 pub mod frontend;
 mod read_only_backend;
+mod runtime_version_cache;
+mod secondary;
 #[cfg(test)]
 pub mod test_util;
+mod trie_cache;
 pub mod util;
 
 // re-exports
 pub use self::block_exec::{BlockChanges, BlockExecutor};
-pub use self::frontend::{GetRuntimeVersion, TArchiveClient};
+pub use self::frontend::{ExecutionMethod, GetRuntimeVersion, TArchiveClient};
 pub use self::read_only_backend::{ReadOnlyBackend, TrieState};
-pub use self::{database::ReadOnlyDatabase, frontend::runtime_api, util::open_database};
+pub use self::runtime_version_cache::RuntimeVersionCache;
+pub use self::secondary::{SecondaryDir, SecondaryLock};
+pub use self::trie_cache::{TrieCacheStats, TrieNodeCache};
+pub use self::{
+    database::{CatchUpStats, ReadOnlyDatabase},
+    frontend::runtime_api,
+    util::open_database,
+};
 
 use sc_client_api::Backend as BackendT;
 use sp_api::{CallApiAt, ConstructRuntimeApi, ProvideRuntimeApi};
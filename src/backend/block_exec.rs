@@ -14,13 +14,17 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::{error::ArchiveResult, types::Storage};
+use crate::{
+    error::{ArchiveResult, Error as ArchiveError},
+    types::Storage,
+};
 use sc_client_api::backend;
 use sp_api::{ApiExt, ApiRef};
 use sp_block_builder::BlockBuilder as BlockBuilderApi;
 use sp_runtime::{
     generic::BlockId,
     traits::{Block as BlockT, Header, NumberFor},
+    SaturatedConversion,
 };
 use sp_storage::{StorageData, StorageKey as StorageKeyWrapper};
 use std::sync::Arc;
@@ -45,11 +49,21 @@ pub struct BlockChanges<Block: BlockT> {
 impl<Block> From<BlockChanges<Block>> for Storage<Block>
 where
     Block: BlockT,
-    NumberFor<Block>: Into<u32>,
 {
     fn from(changes: BlockChanges<Block>) -> Storage<Block> {
         let hash = changes.block_hash;
-        let num: u32 = changes.block_num.into();
+        let num: u64 = changes.block_num.saturated_into();
+        let child_changes = changes
+            .child_storage
+            .into_iter()
+            .map(|(child, child_changes)| {
+                let child_changes = child_changes
+                    .into_iter()
+                    .map(|s| (StorageKeyWrapper(s.0), s.1.map(StorageData)))
+                    .collect();
+                (StorageKeyWrapper(child), child_changes)
+            })
+            .collect();
 
         Storage::new(
             hash,
@@ -61,6 +75,7 @@ where
                 .map(|s| (StorageKeyWrapper(s.0), s.1.map(StorageData)))
                 .collect::<Vec<(StorageKeyWrapper, Option<StorageData>)>>(),
         )
+        .with_child_changes(child_changes)
     }
 }
 
@@ -104,19 +119,38 @@ where
         let hash = header.hash();
         let num = *header.number();
 
-        let state = self.backend.state_at(self.id)?;
+        let state = self
+            .backend
+            .state_at(self.id)
+            .map_err(|e| ArchiveError::state_unavailable(parent_hash, e))?;
 
-        // FIXME: For some reason, wasm runtime calculates a different number of digest items
-        // then what we have in the block
-        // We don't do anything with consensus
-        // so digest isn't very important (we don't currently index digest items anyway)
-        // popping a digest item has no effect on storage changes afaik
+        // The block author seals the block after it was executed,
+        // so the runtime never sees the seal. Like substrate's block import,
+        // strip it before executing the block, or the runtime rejects the digest
         let (mut header, ext) = self.block.deconstruct();
-        header.digest_mut().pop();
+        if header
+            .digest()
+            .logs()
+            .last()
+            .map(|d| d.as_seal().is_some())
+            .unwrap_or(false)
+        {
+            header.digest_mut().pop();
+        }
         let block = Block::new(header, ext);
 
-        self.api.execute_block(&self.id, block)?;
-        let storage_changes = self.api.into_storage_changes(&state, None, parent_hash)?;
+        let failed = |reason: String| ArchiveError::BlockExecution {
+            block_num: num.saturated_into(),
+            hash: format!("{:?}", hash),
+            reason,
+        };
+        self.api
+            .execute_block(&self.id, block)
+            .map_err(|e| failed(e.to_string()))?;
+        let storage_changes = self
+            .api
+            .into_storage_changes(&state, None, parent_hash)
+            .map_err(failed)?;
 
         Ok(BlockChanges {
             storage_changes: storage_changes.main_storage_changes,
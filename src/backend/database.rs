@@ -15,19 +15,36 @@
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
 //! Custom Read-Only Database Instance using RocksDB Secondary features
-//! Will try catching up with primary database on every `get()`
+//! Reads see the database as of the last time it caught up with the primary,
+//! which happens on an interval in the background and whenever a new block is finalized
 
+use super::secondary::SecondaryLock;
 use kvdb::{DBTransaction, DBValue, KeyValueDB};
 use kvdb_rocksdb::{Database, DatabaseConfig};
 use parity_util_mem::MallocSizeOf;
-use sp_database::{ChangeRef, ColumnId, Database as DatabaseTrait, Transaction};
-use std::io;
+use serde::Serialize;
+use sp_database::{
+    error::DatabaseError, ChangeRef, ColumnId, Database as DatabaseTrait, Transaction,
+};
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 pub type KeyValuePair = (Box<[u8]>, Box<[u8]>);
 
 #[derive(MallocSizeOf)]
 pub struct ReadOnlyDatabase {
     inner: Database,
+    #[ignore_malloc_size_of = "counters"]
+    catch_ups: CatchUpCounters,
+    /// dropped after `inner`, so the database is closed before its directory is unlocked
+    #[ignore_malloc_size_of = "file lock"]
+    secondary: Option<SecondaryLock>,
 }
 
 impl std::fmt::Debug for ReadOnlyDatabase {
@@ -40,96 +57,170 @@ impl std::fmt::Debug for ReadOnlyDatabase {
 impl ReadOnlyDatabase {
     pub fn open(config: &DatabaseConfig, path: &str) -> io::Result<Self> {
         let inner = Database::open(config, path)?;
-        Ok(Self { inner })
+        Ok(Self {
+            inner,
+            catch_ups: CatchUpCounters::default(),
+            secondary: None,
+        })
+    }
+
+    /// Open a secondary instance in the directory held by `lock`
+    pub fn open_secondary(
+        config: &DatabaseConfig,
+        path: &str,
+        lock: SecondaryLock,
+    ) -> io::Result<Self> {
+        let config = DatabaseConfig {
+            secondary: Some(lock.path().to_string_lossy().into_owned()),
+            ..config.clone()
+        };
+        let inner = Database::open(&config, path)?;
+        Ok(Self {
+            inner,
+            catch_ups: CatchUpCounters::default(),
+            secondary: Some(lock),
+        })
     }
 
     pub fn get(&self, col: ColumnId, key: &[u8]) -> Option<Vec<u8>> {
-        let val = match self.inner.get(col, key) {
+        match self.inner.get(col, key) {
             Ok(v) => v,
             Err(e) => {
-                log::warn!("{:?}, Catching up with primary and trying again...", e);
+                log::error!("{:?}", e);
                 None
             }
-        };
-        if val.is_none() {
-            self.try_catch_up_with_primary()?;
-            match self.inner.get(col, key) {
-                Ok(v) => v,
-                Err(e) => {
-                    log::error!("{:?}", e);
-                    None
+        }
+    }
+
+    /// Catch up with the writes of the primary instance
+    pub fn catch_up_with_primary(&self) -> io::Result<()> {
+        let now = Instant::now();
+        let res = self.inner.try_catch_up_with_primary();
+        self.catch_ups.record(now.elapsed(), res.is_ok());
+        res
+    }
+
+    /// Catch up with the primary instance every `interval` on a background thread,
+    /// for as long as the database is open
+    pub fn catch_up_in_background(self: &Arc<Self>, interval: Duration) -> io::Result<()> {
+        let db = Arc::downgrade(self);
+        std::thread::Builder::new()
+            .name("rocksdb-catch-up".into())
+            .spawn(move || loop {
+                std::thread::sleep(interval);
+                match db.upgrade() {
+                    Some(db) => {
+                        if let Err(e) = db.catch_up_with_primary() {
+                            log::warn!("could not catch up with the primary database: {}", e);
+                        }
+                    }
+                    None => break,
                 }
-            }
+            })?;
+        Ok(())
+    }
+
+    /// how often catching up with the primary instance happened, and how long it took
+    pub fn catch_up_stats(&self) -> CatchUpStats {
+        self.catch_ups.stats()
+    }
+}
+
+/// Statistics of catching up with the primary RocksDB instance
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CatchUpStats {
+    pub catch_ups: u64,
+    pub failures: u64,
+    /// time spent catching up, altogether
+    pub total: Duration,
+    /// the longest a single catch up took
+    pub longest: Duration,
+}
+
+impl CatchUpStats {
+    pub fn mean(&self) -> Duration {
+        if self.catch_ups == 0 {
+            Duration::default()
         } else {
-            val
+            Duration::from_micros(self.total.as_micros() as u64 / self.catch_ups)
+        }
+    }
+}
+
+#[derive(Default)]
+struct CatchUpCounters {
+    catch_ups: AtomicU64,
+    failures: AtomicU64,
+    total_micros: AtomicU64,
+    longest_micros: AtomicU64,
+}
+
+impl CatchUpCounters {
+    fn record(&self, took: Duration, ok: bool) {
+        let micros = took.as_micros() as u64;
+        self.catch_ups.fetch_add(1, Ordering::Relaxed);
+        if !ok {
+            self.failures.fetch_add(1, Ordering::Relaxed);
         }
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.longest_micros.fetch_max(micros, Ordering::Relaxed);
     }
 
-    pub fn try_catch_up_with_primary(&self) -> Option<()> {
-        self.inner.try_catch_up_with_primary().ok()?;
-        Some(())
+    fn stats(&self) -> CatchUpStats {
+        CatchUpStats {
+            catch_ups: self.catch_ups.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            total: Duration::from_micros(self.total_micros.load(Ordering::Relaxed)),
+            longest: Duration::from_micros(self.longest_micros.load(Ordering::Relaxed)),
+        }
     }
 }
 
-type DBError = Result<(), sp_database::error::DatabaseError>;
-//TODO: Remove panics with a warning that database has not been written to / is read-only
+type DBError = Result<(), DatabaseError>;
+
+fn read_only_error() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "read only database")
+}
+
 /// Preliminary trait for ReadOnlyDatabase
+/// Writes fail, since the database belongs to the node
 impl<H: Clone> DatabaseTrait<H> for ReadOnlyDatabase {
     fn commit(&self, _transaction: Transaction<H>) -> DBError {
-        panic!("Read only db")
+        Err(DatabaseError(Box::new(read_only_error())))
     }
 
     fn commit_ref<'a>(&self, _transaction: &mut dyn Iterator<Item = ChangeRef<'a, H>>) -> DBError {
-        panic!("Read only db")
+        Err(DatabaseError(Box::new(read_only_error())))
     }
 
     fn get(&self, col: ColumnId, key: &[u8]) -> Option<Vec<u8>> {
-        self.inner.try_catch_up_with_primary().ok()?;
-        match self.inner.get(col, key) {
-            Ok(v) => v,
-            Err(e) => {
-                log::error!("{:?}", e);
-                None
-            }
-        }
+        ReadOnlyDatabase::get(self, col, key)
     }
     // with_get -> default is fine
 
-    fn remove(&self, _col: ColumnId, _key: &[u8]) -> Result<(), sp_database::error::DatabaseError> {
-        panic!("Read only db")
+    fn remove(&self, _col: ColumnId, _key: &[u8]) -> DBError {
+        Err(DatabaseError(Box::new(read_only_error())))
     }
 
+    /// Substrate only stores preimages by hash in ParityDB.
+    /// A RocksDB node never writes them, so there is nothing to find.
     fn lookup(&self, _hash: &H) -> Option<Vec<u8>> {
-        unimplemented!();
+        None
     }
-
     // with_lookup -> default
-    /*
-        fn store(&self, _hash: , _preimage: _) {
-            panic!("Read only db")
-        }
-    */
 }
 
 impl KeyValueDB for ReadOnlyDatabase {
     fn get(&self, col: u32, key: &[u8]) -> io::Result<Option<DBValue>> {
-        match self.inner.try_catch_up_with_primary() {
-            Ok(_) => (),
-            Err(e) => log::error!("Could not catch up {:?}", e),
-        };
         self.inner.get(col, key)
     }
 
     fn get_by_prefix(&self, col: u32, prefix: &[u8]) -> Option<Box<[u8]>> {
-        match self.inner.try_catch_up_with_primary() {
-            Ok(_) => (),
-            Err(e) => log::error!("Could not catch up {:?}", e),
-        };
         self.inner.get_by_prefix(col, prefix)
     }
 
     fn write(&self, _transaction: DBTransaction) -> io::Result<()> {
-        panic!("Read only database")
+        Err(read_only_error())
     }
 
     fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = KeyValuePair> + 'a> {
@@ -30,8 +30,37 @@ use sp_core::traits::SpawnNamed;
 use sp_runtime::traits::{BlakeTwo256, Block as BlockT};
 use std::sync::Arc;
 use futures::{Future, task::SpawnExt};
+use serde::{Deserialize, Serialize};
+
+use super::{ReadOnlyBackend, RuntimeApiCollection, TrieNodeCache};
+
+/// How the wasm runtime is executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecutionMethod {
+    /// Interpret the wasm blob with wasmi
+    Interpreted,
+    /// Compile the wasm blob with wasmtime.
+    /// Requires the `wasmtime` feature
+    #[cfg(feature = "wasmtime")]
+    Compiled,
+}
 
-use super::{ReadOnlyBackend, RuntimeApiCollection};
+impl Default for ExecutionMethod {
+    fn default() -> Self {
+        ExecutionMethod::Interpreted
+    }
+}
+
+impl From<ExecutionMethod> for WasmExecutionMethod {
+    fn from(method: ExecutionMethod) -> WasmExecutionMethod {
+        match method {
+            ExecutionMethod::Interpreted => WasmExecutionMethod::Interpreted,
+            #[cfg(feature = "wasmtime")]
+            ExecutionMethod::Compiled => WasmExecutionMethod::Compiled,
+        }
+    }
+}
 
 /// Archive Client Condensed Type
 pub type TArchiveClient<TBl, TRtApi, TExecDisp> =
@@ -45,6 +74,9 @@ pub fn runtime_api<Block, Runtime, Dispatch>(
     db: Arc<ReadOnlyDatabase>,
     block_workers: usize,
     wasm_pages: u64,
+    method: ExecutionMethod,
+    strategies: ExecutionStrategies,
+    trie_cache: Option<Arc<TrieNodeCache>>,
 ) -> Result<TArchiveClient<Block, Runtime, Dispatch>, ArchiveError>
 where
     Block: BlockT,
@@ -61,10 +93,13 @@ where
     Dispatch: NativeExecutionDispatch + 'static,
     <Runtime::RuntimeApi as sp_api::ApiExt<Block>>::StateBackend: sp_api::StateBackend<BlakeTwo256>,
 {
-    let backend = Arc::new(ReadOnlyBackend::new(db, true));
+    let backend = Arc::new(match trie_cache {
+        Some(cache) => ReadOnlyBackend::with_trie_cache(db, true, cache),
+        None => ReadOnlyBackend::new(db, true),
+    });
 
     let executor = NativeExecutor::<Dispatch>::new(
-        WasmExecutionMethod::Interpreted,
+        method.into(),
         Some(wasm_pages),
         block_workers as usize,
     );
@@ -74,7 +109,7 @@ where
     let client = Client::new(
         backend,
         executor,
-        ExecutionExtensions::new(execution_strategies(), None),
+        ExecutionExtensions::new(strategies, None),
     )?;
     Ok(client)
 }
@@ -121,7 +156,9 @@ impl futures::task::Spawn for TaskExecutor {
     }
 }
 
-fn execution_strategies() -> ExecutionStrategies {
+/// The execution strategies used when none are configured.
+/// Prefers the native runtime where it is available
+pub fn default_execution_strategies() -> ExecutionStrategies {
     ExecutionStrategies {
         syncing: ExecutionStrategy::NativeElseWasm,
         importing: ExecutionStrategy::NativeElseWasm,
@@ -130,3 +167,15 @@ fn execution_strategies() -> ExecutionStrategies {
         other: ExecutionStrategy::AlwaysWasm,
     }
 }
+
+/// Use the same execution strategy in every context.
+/// `ExecutionStrategy::AlwaysWasm` rules out any divergence between the native and wasm runtimes
+pub fn uniform_execution_strategies(strategy: ExecutionStrategy) -> ExecutionStrategies {
+    ExecutionStrategies {
+        syncing: strategy,
+        importing: strategy,
+        block_construction: strategy,
+        offchain_worker: strategy,
+        other: strategy,
+    }
+}
@@ -30,21 +30,30 @@ mod state_backend;
 
 pub use self::state_backend::TrieState;
 use self::state_backend::{DbState, StateVault};
-use super::database::ReadOnlyDatabase;
-use super::util::columns;
+use super::database::{CatchUpStats, ReadOnlyDatabase};
+use super::trie_cache::{TrieCacheStats, TrieNodeCache};
+use super::util::{self, columns, meta_keys};
+use crate::error::{ArchiveResult, Error as ArchiveError};
 use hash_db::Prefix;
 use kvdb::DBValue; // need
+use parking_lot::RwLock;
 use sc_client_api::backend::StateBackend;
 use sp_blockchain::{Backend as _, HeaderBackend as _};
 use sp_runtime::{
     generic::{BlockId, SignedBlock},
     traits::{Block as BlockT, HashFor, Header},
+    SaturatedConversion,
 };
+use sp_storage::well_known_keys;
 use std::sync::Arc;
 
+/// Nodes that prune keep the state of this many blocks by default
+const PRUNING_WINDOW: u64 = 256;
+
 pub struct ReadOnlyBackend<Block: BlockT> {
     db: Arc<ReadOnlyDatabase>,
     storage: Arc<StateVault<Block>>,
+    import_lock: RwLock<()>,
 }
 
 impl<Block> ReadOnlyBackend<Block>
@@ -52,68 +61,139 @@ where
     Block: BlockT,
 {
     pub fn new(db: Arc<ReadOnlyDatabase>, prefix_keys: bool) -> Self {
-        let vault = Arc::new(StateVault::new(db.clone(), prefix_keys));
-        Self { db, storage: vault }
+        let vault = Arc::new(StateVault::new(db.clone(), prefix_keys, None));
+        Self {
+            db,
+            storage: vault,
+            import_lock: RwLock::new(()),
+        }
+    }
+
+    /// Read trie nodes through `cache`, which may be shared with other backends
+    pub fn with_trie_cache(
+        db: Arc<ReadOnlyDatabase>,
+        prefix_keys: bool,
+        cache: Arc<TrieNodeCache>,
+    ) -> Self {
+        let vault = Arc::new(StateVault::new(db.clone(), prefix_keys, Some(cache)));
+        Self {
+            db,
+            storage: vault,
+            import_lock: RwLock::new(()),
+        }
     }
 
-    fn state_at(&self, hash: Block::Hash) -> Option<TrieState<Block>> {
+    /// hit rate and size of the trie node cache, if there is one
+    pub fn trie_cache_stats(&self) -> Option<TrieCacheStats> {
+        self.storage.cache().map(|c| c.stats())
+    }
+
+    fn state_at(&self, hash: Block::Hash) -> ArchiveResult<Option<TrieState<Block>>> {
         // genesis
         if hash == Default::default() {
             let genesis_storage = DbGenesisStorage::<Block>(Block::Hash::default());
             let root = Block::Hash::default();
             let state = DbState::<Block>::new(Arc::new(genesis_storage), root);
-            Some(TrieState::<Block>::new(
+            Ok(Some(TrieState::<Block>::new(
                 state,
                 self.storage.clone(),
                 Some(Block::Hash::default()),
-            ))
-        } else if let Some(state_root) = self.state_root(hash) {
+            )))
+        } else if let Some(state_root) = self.state_root(hash)? {
             let state = DbState::<Block>::new(self.storage.clone(), state_root);
-            Some(TrieState::<Block>::new(
+            Ok(Some(TrieState::<Block>::new(
                 state,
                 self.storage.clone(),
                 Some(hash),
-            ))
+            )))
         } else {
-            None
+            Ok(None)
         }
     }
 
-    /// get the state root for a block
-    fn state_root(&self, hash: Block::Hash) -> Option<Block::Hash> {
-        // db / root / key
-        // flesh it out
-        let header = super::util::read_header::<Block>(
+    /// get the state root for a block, `None` if the block is unknown
+    fn state_root(&self, hash: Block::Hash) -> ArchiveResult<Option<Block::Hash>> {
+        let header = util::read_header::<Block>(
             &self.db,
             columns::KEY_LOOKUP,
             columns::HEADER,
             BlockId::Hash(hash),
-        )
-        .expect("Header metadata lookup failed");
-        header.map(|h| *h.state_root())
+        )?;
+        Ok(header.map(|h| *h.state_root()))
     }
 
-    pub fn storage(&self, hash: Block::Hash, key: &[u8]) -> Option<Vec<u8>> {
-        match self.state_at(hash) {
+    /// The value of `key` in the state of the block `hash`.
+    /// `None` if the key is not set, or the block is unknown
+    pub fn storage(&self, hash: Block::Hash, key: &[u8]) -> ArchiveResult<Option<Vec<u8>>> {
+        match self.state_at(hash)? {
+            // fails if the node pruned the state of the block
             Some(state) => state
                 .storage(key)
-                .unwrap_or_else(|_| panic!("No storage found for {:?}", hash)),
-            None => None,
+                .map_err(|e| ArchiveError::StateUnavailable {
+                    hash: format!("{:?}", hash),
+                    source: Box::new(ArchiveError::Blockchain(e)),
+                }),
+            None => Ok(None),
         }
     }
 
-    /// get storage keys for a prefix at a block in time
-    pub fn storage_keys(&self, hash: Block::Hash, prefix: &[u8]) -> Option<Vec<Vec<u8>>> {
-        match self.state_at(hash) {
-            Some(state) => Some(state.keys(prefix)),
-            None => None,
+    /// Catch up with the blocks the node has written since the last catch up
+    pub fn catch_up_with_primary(&self) {
+        if let Err(e) = self.db.catch_up_with_primary() {
+            log::warn!("could not catch up with the primary database: {}", e);
+        }
+    }
+
+    /// how often the database caught up with the node, and how long it took
+    pub fn catch_up_stats(&self) -> CatchUpStats {
+        self.db.catch_up_stats()
+    }
+
+    /// Make sure the node database can be archived.
+    /// Light client databases have no state or block bodies,
+    /// and pruned databases only keep the state of the most recent blocks.
+    pub fn check_archive_node(&self) -> ArchiveResult<()> {
+        if let Some(db_type) = self.db.get(columns::META, meta_keys::TYPE) {
+            check_db_type(&db_type)?;
         }
+        let finalized: u64 = util::read_meta::<Block>(&self.db, columns::HEADER)?
+            .finalized_number
+            .saturated_into();
+        // the state of the oldest blocks is pruned first
+        let mut probes = vec![
+            1,
+            finalized / 4,
+            finalized / 2,
+            finalized.saturating_sub(PRUNING_WINDOW * 2),
+        ];
+        probes.sort();
+        probes.dedup();
+        for num in probes.into_iter().filter(|n| *n > 0) {
+            let hash = match self.hash(num.saturated_into())? {
+                Some(h) => h,
+                None => continue,
+            };
+            let has_state = self.state_at(hash)?.map_or(false, |s| {
+                matches!(s.storage(well_known_keys::CODE), Ok(Some(_)))
+            });
+            if !has_state {
+                return Err(ArchiveError::PrunedDatabase { block_num: num });
+            }
+        }
+        Ok(())
+    }
+
+    /// get storage keys for a prefix at a block in time
+    pub fn storage_keys(
+        &self,
+        hash: Block::Hash,
+        prefix: &[u8],
+    ) -> ArchiveResult<Option<Vec<Vec<u8>>>> {
+        Ok(self.state_at(hash)?.map(|state| state.keys(prefix)))
     }
 
     /// Get a block from the canon chain
-    /// This also tries to catch up with the primary rocksdb instance
     pub fn block(&self, id: &BlockId<Block>) -> Option<SignedBlock<Block>> {
-        self.db.try_catch_up_with_primary();
         match (
             self.header(*id).ok()?,
             self.body(*id).ok()?,
@@ -128,6 +208,16 @@ where
     }
 }
 
+fn check_db_type(db_type: &[u8]) -> ArchiveResult<()> {
+    if db_type == b"full" {
+        Ok(())
+    } else {
+        Err(ArchiveError::LightDatabase(
+            String::from_utf8_lossy(db_type).into_owned(),
+        ))
+    }
+}
+
 struct DbGenesisStorage<Block: BlockT>(pub Block::Hash);
 
 impl<Block: BlockT> sp_state_machine::Storage<HashFor<Block>> for DbGenesisStorage<Block> {
@@ -182,7 +272,7 @@ mod tests {
         harness(DB, |db| {
             let db = ReadOnlyBackend::<Block>::new(db, true);
             let time = Instant::now(); // FIXME: bootleg benchmark.
-            let val = db.storage(hash, key1.as_slice()).unwrap();
+            let val = db.storage(hash, key1.as_slice()).unwrap().unwrap();
             let elapsed = time.elapsed();
             println!(
                 "Took {} seconds, {} milli-seconds, {} nano-seconds",
@@ -192,7 +282,7 @@ mod tests {
             );
             let val: u128 = Decode::decode(&mut val.as_slice()).unwrap();
             assert_eq!(10379170000000000, val);
-            let val = db.storage(hash, key2.as_slice()).unwrap();
+            let val = db.storage(hash, key2.as_slice()).unwrap().unwrap();
             let val: u128 = Decode::decode(&mut val.as_slice()).unwrap();
             assert_eq!(226880000000000, val);
         });
@@ -208,7 +298,7 @@ mod tests {
         harness(DB, |db| {
             let db = ReadOnlyBackend::<Block>::new(db, true);
             let time = Instant::now();
-            let keys = db.storage_keys(hash, key.0.as_slice());
+            let keys = db.storage_keys(hash, key.0.as_slice()).unwrap();
             let elapsed = time.elapsed();
             println!(
                 "Took {} seconds, {} milli-seconds, {} nano-seconds",
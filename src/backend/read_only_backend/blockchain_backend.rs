@@ -17,15 +17,15 @@
 //! Implements Blockchain Backend (and required associated traits) for ReadOnlyBackend type
 
 use super::ReadOnlyBackend;
-use crate::backend::util::{self, columns};
-use codec::Decode;
+use crate::backend::util::{self, columns, meta_keys};
+use codec::{Decode, Encode};
 use sp_blockchain::{
     Backend as BlockchainBackend, BlockStatus, Cache, CachedHeaderMetadata,
     Error as BlockchainError, HeaderBackend, HeaderMetadata, Info,
 };
 use sp_runtime::{
     generic::BlockId,
-    traits::{Block as BlockT, Header as HeaderT, NumberFor},
+    traits::{Block as BlockT, Header as HeaderT, NumberFor, Zero},
     Justification,
 };
 use std::sync::Arc;
@@ -79,12 +79,27 @@ impl<Block: BlockT> BlockchainBackend<Block> for ReadOnlyBackend<Block> {
     /// in other words, that have no children, are chain heads.
     /// Results must be ordered best (longest, highest) chain first.
     fn leaves(&self) -> ChainResult<Vec<Block::Hash>> {
-        unimplemented!()
+        let leaves = match self.db.get(columns::META, meta_keys::LEAF_PREFIX) {
+            Some(l) => l,
+            None => return Ok(Vec::new()),
+        };
+        // the node writes its leaf set as one list of (number, hashes)
+        let mut leaves: Vec<(NumberFor<Block>, Vec<Block::Hash>)> =
+            Decode::decode(&mut leaves.as_slice())
+                .map_err(|e| BlockchainError::Backend(format!("error decoding leaves: {}", e)))?;
+        leaves.sort_by(|a, b| b.0.cmp(&a.0));
+        Ok(leaves.into_iter().flat_map(|(_, hashes)| hashes).collect())
     }
 
     /// Return hashes of all blocks that are children of the block with `parent_hash`.
-    fn children(&self, _parent_hash: Block::Hash) -> ChainResult<Vec<Block::Hash>> {
-        unimplemented!()
+    fn children(&self, parent_hash: Block::Hash) -> ChainResult<Vec<Block::Hash>> {
+        let mut key = meta_keys::CHILDREN_PREFIX.to_vec();
+        parent_hash.using_encoded(|h| key.extend_from_slice(h));
+        match self.db.get(columns::META, &key) {
+            Some(children) => Decode::decode(&mut children.as_slice())
+                .map_err(|e| BlockchainError::Backend(format!("error decoding children: {}", e))),
+            None => Ok(Vec::new()),
+        }
     }
 
     fn best_containing(
@@ -104,23 +119,39 @@ impl<Block: BlockT> HeaderBackend<Block> for ReadOnlyBackend<Block> {
     }
 
     fn info(&self) -> Info<Block> {
-        // TODO: Remove expect
-        let meta = util::read_meta::<Block>(&self.db, columns::HEADER)
-            .expect("Metadata could not be read");
-        log::warn!("Leaves are not counted on the Read Only Backend!");
-        Info {
-            best_hash: meta.best_hash,
-            best_number: meta.best_number,
-            genesis_hash: meta.genesis_hash,
-            finalized_hash: meta.finalized_hash,
-            finalized_number: meta.finalized_number,
-            number_leaves: 0,
+        let number_leaves = self.leaves().map(|l| l.len()).unwrap_or_else(|e| {
+            log::error!("{}", e);
+            0
+        });
+        match util::read_meta::<Block>(&self.db, columns::HEADER) {
+            Ok(meta) => Info {
+                best_hash: meta.best_hash,
+                best_number: meta.best_number,
+                genesis_hash: meta.genesis_hash,
+                finalized_hash: meta.finalized_hash,
+                finalized_number: meta.finalized_number,
+                number_leaves,
+            },
+            Err(e) => {
+                log::error!("metadata could not be read: {}", e);
+                Info {
+                    best_hash: Default::default(),
+                    best_number: Zero::zero(),
+                    genesis_hash: Default::default(),
+                    finalized_hash: Default::default(),
+                    finalized_number: Zero::zero(),
+                    number_leaves,
+                }
+            }
         }
     }
 
-    fn status(&self, _id: BlockId<Block>) -> ChainResult<BlockStatus> {
-        log::warn!("Read Only Backend does not track Block Status!");
-        Ok(BlockStatus::Unknown)
+    fn status(&self, id: BlockId<Block>) -> ChainResult<BlockStatus> {
+        if self.header(id)?.is_some() {
+            Ok(BlockStatus::InChain)
+        } else {
+            Ok(BlockStatus::Unknown)
+        }
     }
 
     fn number(
@@ -162,4 +193,155 @@ impl<Block: BlockT> HeaderMetadata<Block> for ReadOnlyBackend<Block> {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use crate::backend::{database::ReadOnlyDatabase, util::NUM_COLUMNS};
+    use kvdb::KeyValueDB;
+    use kvdb_rocksdb::{Database, DatabaseConfig};
+    use sc_client_api::backend::{AuxStore, Backend as _, BlockImportOperation as _};
+    use sp_core::H256;
+    use sp_database::Database as _;
+    use sp_runtime::testing::{Block as TestBlock, ExtrinsicWrapper, Header};
+
+    type Block = TestBlock<ExtrinsicWrapper<u64>>;
+
+    fn header(number: u64, parent_hash: H256, salt: u8) -> Header {
+        Header::new(
+            number,
+            Default::default(),
+            H256::repeat_byte(salt),
+            parent_hash,
+            Default::default(),
+        )
+    }
+
+    /// A node database with genesis, two competing children of genesis,
+    /// and one block on top of the first of them
+    struct Fixture {
+        backend: ReadOnlyBackend<Block>,
+        db: Arc<ReadOnlyDatabase>,
+        blocks: Vec<Header>,
+        _dirs: (tempfile::TempDir, tempfile::TempDir),
+    }
+
+    fn fixture() -> Fixture {
+        let genesis = header(0, Default::default(), 0);
+        let one_a = header(1, genesis.hash(), 1);
+        let one_b = header(1, genesis.hash(), 2);
+        let two = header(2, one_a.hash(), 3);
+        let blocks = vec![genesis, one_a, one_b, two];
+
+        let primary = tempfile::tempdir().unwrap();
+        let secondary = tempfile::tempdir().unwrap();
+        {
+            let db = Database::open(
+                &DatabaseConfig::with_columns(NUM_COLUMNS),
+                primary.path().to_str().unwrap(),
+            )
+            .unwrap();
+            let mut tx = db.transaction();
+            for h in blocks.iter() {
+                let mut lookup_key = util::number_index_key(*h.number()).unwrap().to_vec();
+                lookup_key.extend_from_slice(h.hash().as_ref());
+                tx.put(columns::KEY_LOOKUP, h.hash().as_ref(), &lookup_key);
+                tx.put(columns::HEADER, &lookup_key, &h.encode());
+                // the canonical chain is indexed by number
+                if h.hash() != blocks[2].hash() {
+                    tx.put(
+                        columns::KEY_LOOKUP,
+                        &util::number_index_key(*h.number()).unwrap(),
+                        &lookup_key,
+                    );
+                }
+            }
+            tx.put(columns::META, meta_keys::TYPE, b"full");
+            tx.put(
+                columns::META,
+                meta_keys::GENESIS_HASH,
+                &blocks[0].hash().encode(),
+            );
+            let leaves: Vec<(u64, Vec<H256>)> =
+                vec![(2, vec![blocks[3].hash()]), (1, vec![blocks[2].hash()])];
+            tx.put(columns::META, meta_keys::LEAF_PREFIX, &leaves.encode());
+            let mut children_key = meta_keys::CHILDREN_PREFIX.to_vec();
+            children_key.extend_from_slice(&blocks[0].hash().encode());
+            let children = vec![blocks[1].hash(), blocks[2].hash()];
+            tx.put(columns::META, &children_key, &children.encode());
+            tx.put(columns::AUX, b"grandpa_voters", b"voters");
+            db.write(tx).unwrap();
+        }
+
+        let conf = DatabaseConfig {
+            secondary: Some(secondary.path().to_str().unwrap().to_string()),
+            ..DatabaseConfig::with_columns(NUM_COLUMNS)
+        };
+        let db = Arc::new(ReadOnlyDatabase::open(&conf, primary.path().to_str().unwrap()).unwrap());
+        Fixture {
+            backend: ReadOnlyBackend::new(db.clone(), true),
+            db,
+            blocks,
+            _dirs: (primary, secondary),
+        }
+    }
+
+    #[test]
+    fn should_read_leaves_and_children() {
+        let f = fixture();
+        let hashes = f.blocks.iter().map(|h| h.hash()).collect::<Vec<_>>();
+        assert_eq!(f.backend.leaves().unwrap(), vec![hashes[3], hashes[2]]);
+        assert_eq!(f.backend.info().number_leaves, 2);
+        assert_eq!(
+            f.backend.children(hashes[0]).unwrap(),
+            vec![hashes[1], hashes[2]]
+        );
+        assert!(f.backend.children(hashes[3]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_read_status_and_aux() {
+        let f = fixture();
+        let hash = f.blocks[2].hash();
+        assert_eq!(
+            f.backend.status(BlockId::Hash(hash)).unwrap(),
+            BlockStatus::InChain
+        );
+        assert_eq!(
+            f.backend.status(BlockId::Number(2)).unwrap(),
+            BlockStatus::InChain
+        );
+        assert_eq!(
+            f.backend.status(BlockId::Number(3)).unwrap(),
+            BlockStatus::Unknown
+        );
+        assert_eq!(
+            f.backend.get_aux(b"grandpa_voters").unwrap(),
+            Some(b"voters".to_vec())
+        );
+        assert_eq!(f.backend.get_aux(b"babe_epochs").unwrap(), None);
+    }
+
+    #[test]
+    fn should_refuse_writes() {
+        let f = fixture();
+        assert!(KeyValueDB::write(&*f.db, f.db.transaction()).is_err());
+        let tx = sp_database::Transaction::<H256>::new();
+        assert!(f.db.commit(tx).is_err());
+        assert_eq!(f.db.lookup(&f.blocks[0].hash()), None);
+
+        let mut op = f.backend.begin_operation().unwrap();
+        assert!(f
+            .backend
+            .begin_state_operation(&mut op, BlockId::Number(0))
+            .is_err());
+        assert!(op.insert_aux(vec![(b"key".to_vec(), None)]).is_err());
+        assert!(f.backend.commit_operation(op).is_err());
+        assert!(f.backend.finalize_block(BlockId::Number(1), None).is_err());
+        assert!(AuxStore::insert_aux(
+            &f.backend,
+            &[(&b"key"[..], &b"value"[..])],
+            std::iter::empty()
+        )
+        .is_err());
+        assert_eq!(f.backend.get_aux(b"key").unwrap(), None);
+    }
+}
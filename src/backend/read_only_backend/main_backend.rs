@@ -47,15 +47,15 @@ impl<Block: BlockT> Backend<Block> for ReadOnlyBackend<Block> {
         _operation: &mut Self::BlockImportOperation,
         _block: BlockId<Block>,
     ) -> ChainResult<()> {
-        log::warn!("State operations not supported on a read-only backend. Operation not begun.");
-        Ok(())
+        Err(BlockchainError::Backend(
+            "read only backend, state operations are not supported".into(),
+        ))
     }
 
     fn commit_operation(&self, _transaction: Self::BlockImportOperation) -> ChainResult<()> {
-        log::warn!(
-            "State operations not supported on a read-only backend. Operation not committed"
-        );
-        Ok(())
+        Err(BlockchainError::Backend(
+            "read only backend, operations can not be committed".into(),
+        ))
     }
 
     fn finalize_block(
@@ -63,8 +63,9 @@ impl<Block: BlockT> Backend<Block> for ReadOnlyBackend<Block> {
         _block: BlockId<Block>,
         _justification: Option<Justification>,
     ) -> ChainResult<()> {
-        log::warn!("State operations not supported on a read-only backend. Block not finalized.");
-        Ok(())
+        Err(BlockchainError::Backend(
+            "read only backend, blocks can not be finalized".into(),
+        ))
     }
 
     fn blockchain(&self) -> &Self::Blockchain {
@@ -105,11 +106,12 @@ impl<Block: BlockT> Backend<Block> for ReadOnlyBackend<Block> {
         };
 
         match self.state_at(hash) {
-            Some(v) => Ok(v),
-            None => Err(BlockchainError::Msg(format!(
+            Ok(Some(v)) => Ok(v),
+            Ok(None) => Err(BlockchainError::Msg(format!(
                 "No state found for block {:?}",
                 hash
             ))),
+            Err(e) => Err(BlockchainError::Backend(e.to_string())),
         }
     }
 
@@ -124,7 +126,8 @@ impl<Block: BlockT> Backend<Block> for ReadOnlyBackend<Block> {
         ))
     }
 
+    /// nothing is ever imported, so the lock is never contended
     fn get_import_lock(&self) -> &parking_lot::RwLock<()> {
-        panic!("No lock exists for read only backend!")
+        &self.import_lock
     }
 }
@@ -95,8 +95,9 @@ impl<Block: BlockT> BlockImportOperation<Block> for RealBlockImportOperation {
     where
         I: IntoIterator<Item = (Vec<u8>, Option<Vec<u8>>)>,
     {
-        log::warn!("Cannot modify storage of a read only backend. Aux not inserted.");
-        Ok(())
+        Err(BlockchainError::Backend(
+            "read only backend, aux data can not be inserted".into(),
+        ))
     }
 
     fn mark_finalized(
@@ -155,8 +156,9 @@ impl<Block: BlockT> AuxStore for ReadOnlyBackend<Block> {
         _insert: I,
         _delete: D,
     ) -> ChainResult<()> {
-        log::warn!("Insert operations not supported for Read Only Backend");
-        Ok(())
+        Err(BlockchainError::Backend(
+            "read only backend, aux data can not be inserted".into(),
+        ))
     }
 
     fn get_aux(&self, key: &[u8]) -> ChainResult<Option<Vec<u8>>> {
@@ -16,7 +16,7 @@
 
 //! State Backend Interface
 
-use crate::backend::database::ReadOnlyDatabase;
+use crate::backend::{database::ReadOnlyDatabase, trie_cache::TrieNodeCache};
 use hash_db::Prefix;
 use kvdb::DBValue;
 use sc_client_api::backend::StateBackend;
@@ -35,27 +35,50 @@ pub struct StateVault<Block: BlockT> {
     /// disk backend
     pub db: Arc<ReadOnlyDatabase>,
     prefix_keys: bool,
+    /// trie nodes, shared by every backend that executes blocks
+    cache: Option<Arc<TrieNodeCache>>,
     _marker: PhantomData<Block>,
 }
 
 impl<Block: BlockT> StateVault<Block> {
-    pub fn new(db: Arc<ReadOnlyDatabase>, prefix_keys: bool) -> Self {
+    pub fn new(
+        db: Arc<ReadOnlyDatabase>,
+        prefix_keys: bool,
+        cache: Option<Arc<TrieNodeCache>>,
+    ) -> Self {
         Self {
             db,
             prefix_keys,
+            cache,
             _marker: PhantomData,
         }
     }
+
+    pub fn cache(&self) -> Option<&Arc<TrieNodeCache>> {
+        self.cache.as_ref()
+    }
 }
 
 impl<Block: BlockT> sp_state_machine::Storage<HashFor<Block>> for StateVault<Block> {
     fn get(&self, key: &Block::Hash, prefix: Prefix) -> Result<Option<DBValue>, String> {
-        if self.prefix_keys {
-            let key = sp_trie::prefixed_key::<HashFor<Block>>(key, prefix);
-            Ok(self.db.get(super::columns::STATE, &key))
+        let key = if self.prefix_keys {
+            sp_trie::prefixed_key::<HashFor<Block>>(key, prefix)
         } else {
-            Ok(self.db.get(super::columns::STATE, key.as_ref()))
+            key.as_ref().to_vec()
+        };
+        let cache = match &self.cache {
+            Some(c) => c,
+            None => return Ok(self.db.get(super::columns::STATE, &key)),
+        };
+        if let Some(node) = cache.get(&key) {
+            return Ok(Some(node));
+        }
+        // missing nodes are not cached, the node may write them later
+        let node = self.db.get(super::columns::STATE, &key);
+        if let Some(node) = &node {
+            cache.insert(key, node.clone());
         }
+        Ok(node)
     }
 }
 
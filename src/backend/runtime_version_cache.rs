@@ -0,0 +1,107 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Caches runtime versions by the hash of the runtime code.
+//! Getting the version of a runtime means instantiating the runtime from state,
+//! but the runtime only changes when the chain is upgraded. Looking up the hash of `:code`
+//! in the trie is much cheaper, so we only ask the runtime for its version when the code changes.
+//! The hash of `:code` is remembered for recently seen blocks too, since a block and its parent
+//! are usually looked up one after the other.
+
+use super::{frontend::GetRuntimeVersion, ReadOnlyBackend, TrieState};
+use crate::error::{ArchiveResult, Error as ArchiveError};
+use hashbrown::HashMap;
+use lru::LruCache;
+use parking_lot::{Mutex, RwLock};
+use sc_client_api::backend::{Backend, StateBackend as _};
+use sp_runtime::{generic::BlockId, traits::Block as BlockT};
+use sp_storage::well_known_keys;
+use sp_version::RuntimeVersion;
+use std::sync::Arc;
+
+/// number of blocks whose code hash is remembered
+const BLOCK_CACHE_SIZE: usize = 4096;
+
+pub struct RuntimeVersionCache<B: BlockT> {
+    /// runtime versions keyed by the hash of `:code`
+    versions: RwLock<HashMap<B::Hash, RuntimeVersion>>,
+    /// hash of `:code` keyed by the hash of recently seen blocks
+    blocks: Mutex<LruCache<B::Hash, B::Hash>>,
+    backend: Arc<ReadOnlyBackend<B>>,
+    /// used to get the version of runtimes that are not in the cache
+    api: Arc<dyn GetRuntimeVersion<B>>,
+}
+
+impl<B: BlockT> RuntimeVersionCache<B> {
+    pub fn new(backend: Arc<ReadOnlyBackend<B>>, api: Arc<dyn GetRuntimeVersion<B>>) -> Self {
+        Self {
+            versions: RwLock::new(HashMap::new()),
+            blocks: Mutex::new(LruCache::new(BLOCK_CACHE_SIZE)),
+            backend,
+            api,
+        }
+    }
+
+    /// hash of the runtime code at the block `at`
+    pub fn code_hash(&self, at: &BlockId<B>) -> ArchiveResult<B::Hash> {
+        let block = match at {
+            BlockId::Hash(h) => Some(*h),
+            BlockId::Number(_) => None,
+        };
+        if let Some(code_hash) = block.and_then(|h| self.blocks.lock().get(&h).copied()) {
+            return Ok(code_hash);
+        }
+        let code_hash = self
+            .state_at(at)?
+            .storage_hash(well_known_keys::CODE)
+            .map_err(ArchiveError::Blockchain)?
+            .ok_or_else(|| ArchiveError::MissingState(format!("no runtime code at {}", at)))?;
+        if let Some(h) = block {
+            self.blocks.lock().put(h, code_hash);
+        }
+        Ok(code_hash)
+    }
+
+    /// the runtime wasm blob at the block `at`
+    pub fn code(&self, at: &BlockId<B>) -> ArchiveResult<Vec<u8>> {
+        self.state_at(at)?
+            .storage(well_known_keys::CODE)
+            .map_err(ArchiveError::Blockchain)?
+            .ok_or_else(|| ArchiveError::MissingState(format!("no runtime code at {}", at)))
+    }
+
+    fn state_at(&self, at: &BlockId<B>) -> ArchiveResult<TrieState<B>> {
+        // `ReadOnlyBackend` has an inherent `state_at` which only accepts hashes
+        Backend::state_at(&*self.backend, *at).map_err(|e| ArchiveError::state_unavailable(at, e))
+    }
+}
+
+impl<B: BlockT> GetRuntimeVersion<B> for RuntimeVersionCache<B> {
+    fn runtime_version(&self, at: &BlockId<B>) -> ArchiveResult<RuntimeVersion> {
+        let code_hash = self.code_hash(at)?;
+        if let Some(version) = self.versions.read().get(&code_hash) {
+            return Ok(version.clone());
+        }
+        let version = self.api.runtime_version(at)?;
+        log::debug!(
+            "found runtime {} with spec version {}",
+            version.spec_name,
+            version.spec_version
+        );
+        self.versions.write().insert(code_hash, version.clone());
+        Ok(version)
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The directory of the secondary RocksDB instance.
+//! A secondary instance keeps its own MANIFEST and logs there,
+//! so two archives must never share one. Every directory is guarded by an exclusive
+//! lock on a file inside it, which is held for as long as the database is open.
+
+use crate::error::{ArchiveResult, Error as ArchiveError};
+use fs2::FileExt;
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+};
+
+const LOCK_FILE: &str = "archive.lock";
+
+/// Where the secondary RocksDB instance keeps its files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecondaryDir {
+    /// `<local data dir>/substrate_archive/rocksdb_secondary/<chain>/<id>`
+    Default,
+    /// a directory of your choosing
+    Path(PathBuf),
+    /// A new directory for this process only, removed when the database is closed.
+    /// Lets several archives of the same chain run side by side.
+    Temporary,
+}
+
+impl Default for SecondaryDir {
+    fn default() -> Self {
+        SecondaryDir::Default
+    }
+}
+
+/// Exclusive lock on a secondary directory
+#[derive(Debug)]
+pub struct SecondaryLock {
+    path: PathBuf,
+    file: File,
+    temporary: bool,
+}
+
+impl SecondaryLock {
+    /// lock the directory at `path`, creating it if it does not exist
+    fn acquire(path: PathBuf, temporary: bool) -> ArchiveResult<Self> {
+        fs::create_dir_all(&path)?;
+        match try_lock(&path)? {
+            Some(file) => Ok(Self {
+                path,
+                file,
+                temporary,
+            }),
+            None => Err(ArchiveError::SecondaryDirInUse(path.display().to_string())),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+}
+
+impl Drop for SecondaryLock {
+    fn drop(&mut self) {
+        if self.temporary {
+            if let Err(e) = fs::remove_dir_all(&self.path) {
+                log::warn!(
+                    "could not remove secondary directory {}: {}",
+                    self.path.display(),
+                    e
+                );
+            }
+        }
+        let _ = self.file.unlock();
+    }
+}
+
+/// Lock the secondary directory for the database of `chain`
+pub fn lock_secondary_dir(
+    dir: &SecondaryDir,
+    chain: &str,
+    id: &str,
+) -> ArchiveResult<SecondaryLock> {
+    match dir {
+        SecondaryDir::Default => SecondaryLock::acquire(chain_dir(chain)?.join(id), false),
+        SecondaryDir::Path(path) => SecondaryLock::acquire(path.clone(), false),
+        SecondaryDir::Temporary => {
+            let base = chain_dir(chain)?;
+            remove_stale(&base, id);
+            let name = format!("{}.{}", id, std::process::id());
+            SecondaryLock::acquire(base.join(name), true)
+        }
+    }
+}
+
+fn chain_dir(chain: &str) -> ArchiveResult<PathBuf> {
+    let base_dirs = dirs::BaseDirs::new().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "could not find the local data directory",
+        )
+    })?;
+    let mut path = base_dirs.data_local_dir().to_path_buf();
+    path.push("substrate_archive");
+    path.push("rocksdb_secondary");
+    path.push(chain);
+    Ok(path)
+}
+
+/// `None` if the lock is held by someone else
+fn try_lock(dir: &Path) -> io::Result<Option<File>> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(dir.join(LOCK_FILE))?;
+    match file.try_lock_exclusive() {
+        Ok(()) => Ok(Some(file)),
+        Err(e) if e.kind() == fs2::lock_contended_error().kind() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Remove the temporary directories of archives that did not shut down cleanly.
+/// The lock of a directory is released when its process exits, however it exited.
+fn remove_stale(base: &Path, id: &str) {
+    let entries = match fs::read_dir(base) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let name = entry.file_name();
+        if !is_temporary(&name.to_string_lossy(), id) {
+            continue;
+        }
+        let path = entry.path();
+        if let Ok(Some(lock)) = try_lock(&path) {
+            log::info!("removing stale secondary directory {}", path.display());
+            if let Err(e) = fs::remove_dir_all(&path) {
+                log::warn!("could not remove {}: {}", path.display(), e);
+            }
+            let _ = lock.unlock();
+        }
+    }
+}
+
+/// temporary directories are named `<id>.<pid>`
+fn is_temporary(name: &str, id: &str) -> bool {
+    name.strip_prefix(id)
+        .and_then(|rest| rest.strip_prefix('.'))
+        .map_or(false, |pid| pid.parse::<u32>().is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_lock_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = SecondaryDir::Path(dir.path().join("secondary"));
+        let lock = lock_secondary_dir(&dir, "ksmcc3", "ksmcc3").unwrap();
+        match lock_secondary_dir(&dir, "ksmcc3", "ksmcc3") {
+            Err(ArchiveError::SecondaryDirInUse(_)) => (),
+            r => panic!("expected the directory to be in use, got {:?}", r),
+        }
+        std::mem::drop(lock);
+        assert!(lock_secondary_dir(&dir, "ksmcc3", "ksmcc3").is_ok());
+    }
+
+    #[test]
+    fn should_recognize_temporary_dirs() {
+        assert!(is_temporary("ksmcc3.4242", "ksmcc3"));
+        assert!(!is_temporary("ksmcc3", "ksmcc3"));
+        assert!(!is_temporary("ksmcc3.old", "ksmcc3"));
+        assert!(!is_temporary("polkadot.4242", "ksmcc3"));
+    }
+}
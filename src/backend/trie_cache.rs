@@ -0,0 +1,216 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! LRU cache of trie nodes read from the `STATE` column.
+//! Consecutive blocks share most of their trie, so executing blocks in order
+//! mostly reads nodes that the blocks before them already read.
+//! Trie nodes are keyed by their hash, so a cached node can never be stale.
+//!
+//! The cache is shared by every thread that executes blocks.
+//! It is split into shards with a lock and LRU each, so that threads reading different nodes
+//! rarely wait on each other. Keys end with the hash of the node, which picks the shard.
+
+use kvdb::DBValue;
+use lru::LruCache;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Bytes a cached node costs on top of its key and value
+const ENTRY_OVERHEAD: usize = 64;
+
+/// Number of independently locked parts of the cache
+const SHARDS: usize = 16;
+
+/// Trie nodes that were read recently, bounded by their size in memory
+pub struct TrieNodeCache {
+    shards: Vec<Mutex<Nodes>>,
+    /// maximum size of the cached nodes of each shard in bytes
+    shard_capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct Nodes {
+    lru: LruCache<Vec<u8>, DBValue>,
+    size: usize,
+}
+
+impl TrieNodeCache {
+    /// Cache at most `capacity` bytes of trie nodes
+    pub fn new(capacity: usize) -> Self {
+        Self::with_shards(capacity, SHARDS)
+    }
+
+    fn with_shards(capacity: usize, shards: usize) -> Self {
+        Self {
+            shards: (0..shards)
+                .map(|_| {
+                    Mutex::new(Nodes {
+                        lru: LruCache::unbounded(),
+                        size: 0,
+                    })
+                })
+                .collect(),
+            shard_capacity: capacity / shards,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn shard(&self, key: &[u8]) -> &Mutex<Nodes> {
+        let byte = key.last().copied().unwrap_or(0);
+        &self.shards[usize::from(byte) % self.shards.len()]
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<DBValue> {
+        let node = self.shard(key).lock().lru.get(key).cloned();
+        let counter = if node.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        node
+    }
+
+    pub fn insert(&self, key: Vec<u8>, node: DBValue) {
+        let cost = entry_cost(&key, &node);
+        if cost > self.shard_capacity {
+            return;
+        }
+        let key_len = key.len();
+        let mut nodes = self.shard(&key).lock();
+        nodes.size += cost;
+        if let Some(old) = nodes.lru.put(key, node) {
+            nodes.size -= key_len + old.len() + ENTRY_OVERHEAD;
+        }
+        while nodes.size > self.shard_capacity {
+            match nodes.lru.pop_lru() {
+                Some((k, v)) => nodes.size -= entry_cost(&k, &v),
+                None => break,
+            }
+        }
+    }
+
+    pub fn stats(&self) -> TrieCacheStats {
+        let mut stats = TrieCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            ..Default::default()
+        };
+        for shard in self.shards.iter() {
+            let nodes = shard.lock();
+            stats.nodes += nodes.lru.len();
+            stats.size += nodes.size;
+        }
+        stats
+    }
+}
+
+fn entry_cost(key: &[u8], node: &[u8]) -> usize {
+    key.len() + node.len() + ENTRY_OVERHEAD
+}
+
+/// Statistics of the trie node cache
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct TrieCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// number of cached nodes
+    pub nodes: usize,
+    /// size of the cached nodes in bytes
+    pub size: usize,
+}
+
+impl TrieCacheStats {
+    /// share of reads that were answered from the cache
+    pub fn hit_rate(&self) -> f64 {
+        let reads = self.hits + self.misses;
+        if reads == 0 {
+            0.0
+        } else {
+            self.hits as f64 / reads as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{sync::Arc, thread, time::Instant};
+
+    #[test]
+    fn should_evict_least_recently_used() {
+        let cache = TrieNodeCache::new(SHARDS * 3 * entry_cost(&[0], &[0; 32]));
+        // keys that are a multiple of `SHARDS` all go into the first shard
+        let key = |i: u8| vec![i * SHARDS as u8];
+        for i in 0..3u8 {
+            cache.insert(key(i), vec![i; 32]);
+        }
+        // touch node 0, so node 1 is the least recently used
+        assert_eq!(cache.get(&key(0)), Some(vec![0; 32]));
+        cache.insert(key(3), vec![3; 32]);
+        assert_eq!(cache.get(&key(1)), None);
+        assert_eq!(cache.get(&key(3)), Some(vec![3; 32]));
+
+        let stats = cache.stats();
+        assert_eq!(stats.nodes, 3);
+        assert!(stats.size <= 3 * entry_cost(&[0], &[0; 32]));
+        assert_eq!((stats.hits, stats.misses), (2, 1));
+        assert!((stats.hit_rate() - 2.0 / 3.0).abs() < f64::EPSILON);
+    }
+
+    /// Compare concurrent reads from a cache behind a single lock with reads from a sharded cache.
+    /// Run with `cargo test --release measure_concurrent_reads -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn measure_concurrent_reads() {
+        const NODES: usize = 100_000;
+        const READS: usize = 2_000_000;
+        let threads = num_cpus::get().max(2);
+        let key = |i: usize| (i as u64).to_be_bytes().repeat(4);
+        for shards in [1, SHARDS].iter() {
+            let capacity = 2 * NODES * entry_cost(&key(0), &[0; 128]);
+            let cache = Arc::new(TrieNodeCache::with_shards(capacity, *shards));
+            for i in 0..NODES {
+                cache.insert(key(i), vec![0; 128]);
+            }
+            let start = Instant::now();
+            let handles = (0..threads)
+                .map(|t| {
+                    let cache = cache.clone();
+                    thread::spawn(move || {
+                        for i in 0..READS / threads {
+                            assert!(cache.get(&key((t * 7919 + i) % NODES)).is_some());
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            for handle in handles.into_iter() {
+                handle.join().unwrap();
+            }
+            println!(
+                "{} shards, {} threads: {} reads in {:?}",
+                shards,
+                threads,
+                READS,
+                start.elapsed()
+            );
+        }
+    }
+}
@@ -17,7 +17,10 @@
 //! various utilities that make interfacing with substrate easier
 
 use crate::{
-    backend::database::ReadOnlyDatabase,
+    backend::{
+        database::ReadOnlyDatabase,
+        secondary::{self, SecondaryDir},
+    },
     error::{ArchiveResult, Error as ArchiveError},
 };
 use codec::Decode;
@@ -38,16 +41,12 @@ pub type NumberIndexKey = [u8; 4];
 pub fn open_database(
     path: &str,
     cache_size: usize,
+    secondary_dir: &SecondaryDir,
     chain: &str,
     id: &str,
-) -> sp_blockchain::Result<ReadOnlyDatabase> {
-    let db_path = crate::util::create_secondary_db_dir(chain, id);
-    // need to make sure this is `Some` to open secondary instance
-    let db_path = db_path.as_path().to_str().expect("Creating db path failed");
-    let mut db_config = DatabaseConfig {
-        secondary: Some(db_path.to_string()),
-        ..DatabaseConfig::with_columns(NUM_COLUMNS)
-    };
+) -> ArchiveResult<ReadOnlyDatabase> {
+    let lock = secondary::lock_secondary_dir(secondary_dir, chain, id)?;
+    let mut db_config = DatabaseConfig::with_columns(NUM_COLUMNS);
     let state_col_budget = (cache_size as f64 * 0.9) as usize;
     let other_col_budget = (cache_size - state_col_budget) / (NUM_COLUMNS as usize - 1);
     let mut memory_budget = std::collections::HashMap::new();
@@ -62,14 +61,15 @@ pub fn open_database(
     db_config.memory_budget = memory_budget;
     log::info!(
         target: "db",
-        "Open RocksDB at {}, state column budget: {} MiB, others({}) column cache: {} MiB",
+        "Open RocksDB at {} (secondary {}), state column budget: {} MiB, others({}) column cache: {} MiB",
         path,
+        lock.path().display(),
         state_col_budget,
         NUM_COLUMNS,
         other_col_budget,
     );
-    super::database::ReadOnlyDatabase::open(&db_config, &path)
-        .map_err(|err| sp_blockchain::Error::Backend(format!("{}", err)))
+    ReadOnlyDatabase::open_secondary(&db_config, &path, lock)
+        .map_err(|err| ArchiveError::Blockchain(err.to_string()))
 }
 
 #[allow(unused)]
@@ -118,7 +118,10 @@ pub fn read_header<Block: BlockT>(
     match read_db(db, col_index, col, id)? {
         Some(header) => match Block::Header::decode(&mut &header[..]) {
             Ok(header) => Ok(Some(header)),
-            Err(_) => Err(ArchiveError::from("Error decoding header")),
+            Err(e) => Err(ArchiveError::Decode {
+                context: "header",
+                source: e,
+            }),
         },
         None => Ok(None),
     }
@@ -154,10 +157,13 @@ where
     })
 }
 
+/// Key of a block number in the `KEY_LOOKUP` column.
+/// Substrate writes these keys as 4 bytes regardless of the block number type of the chain,
+/// so numbers past `u32::MAX` can not be looked up.
 pub fn number_index_key<N: TryInto<u32>>(n: N) -> ArchiveResult<NumberIndexKey> {
     let n = n
         .try_into()
-        .map_err(|_| ArchiveError::from("Block num cannot be converted to u32"))?;
+        .map_err(|_| ArchiveError::BlockNumberConversion)?;
 
     Ok([
         (n >> 24) as u8,
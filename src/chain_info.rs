@@ -0,0 +1,155 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The identity of the chain an archive belongs to.
+//! It is recorded in the `chain_info` table the first time the archive runs,
+//! and RocksDB, the RPC node and Postgres must agree on it from then on.
+
+use crate::error::{ArchiveResult, Error as ArchiveError};
+use sc_chain_spec::ChainSpec;
+use sqlx::PgConnection;
+
+/// Identity of a chain, from its genesis hash and chain spec
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainInfo {
+    pub genesis_hash: Vec<u8>,
+    /// id of the chain spec
+    pub spec_id: String,
+    pub ss58_format: Option<u16>,
+    pub token_symbol: Option<String>,
+    pub token_decimals: Option<u8>,
+}
+
+impl ChainInfo {
+    pub fn new(genesis_hash: &[u8], spec: &dyn ChainSpec) -> Self {
+        let properties = spec.properties();
+        let number = |key: &str| properties.get(key).and_then(|v| v.as_u64());
+        Self {
+            genesis_hash: genesis_hash.to_vec(),
+            spec_id: spec.id().to_string(),
+            ss58_format: number("ss58Format").map(|n| n as u16),
+            token_symbol: properties
+                .get("tokenSymbol")
+                .and_then(|v| v.as_str())
+                .map(String::from),
+            token_decimals: number("tokenDecimals").map(|n| n as u8),
+        }
+    }
+
+    /// the identity recorded in Postgres, if the archive has run before
+    pub async fn load(conn: &mut PgConnection) -> ArchiveResult<Option<Self>> {
+        let row: Option<(Vec<u8>, String, Option<i32>, Option<String>, Option<i32>)> =
+            sqlx::query_as(
+                "SELECT genesis_hash, spec_id, ss58_format, token_symbol, token_decimals FROM chain_info",
+            )
+            .fetch_optional(conn)
+            .await?;
+        Ok(row.map(|r| Self {
+            genesis_hash: r.0,
+            spec_id: r.1,
+            ss58_format: r.2.map(|n| n as u16),
+            token_symbol: r.3,
+            token_decimals: r.4.map(|n| n as u8),
+        }))
+    }
+
+    /// Make sure the archive in Postgres belongs to this chain, without writing anything.
+    /// Archives that have not recorded their identity yet are checked against their genesis block, if it is archived.
+    /// Returns whether the identity is recorded
+    pub async fn check(&self, conn: &mut PgConnection) -> ArchiveResult<bool> {
+        match Self::load(conn).await? {
+            Some(archived) => {
+                self.check_genesis("postgres database", &archived.genesis_hash)?;
+                if archived.spec_id != self.spec_id {
+                    return Err(ArchiveError::MismatchedChains {
+                        origin: "postgres database",
+                        expected: format!("chain spec {}", self.spec_id),
+                        found: format!("chain spec {}", archived.spec_id),
+                    });
+                }
+                Ok(true)
+            }
+            None => {
+                let genesis: Option<(Vec<u8>,)> =
+                    sqlx::query_as("SELECT hash FROM blocks WHERE block_num = 0")
+                        .fetch_optional(conn)
+                        .await?;
+                if let Some((genesis,)) = genesis {
+                    self.check_genesis("postgres database", &genesis)?;
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    /// Record this identity in Postgres on the first run,
+    /// and make sure the archive belongs to this chain on later runs
+    pub async fn ensure(&self, conn: &mut PgConnection) -> ArchiveResult<()> {
+        if !self.check(&mut *conn).await? {
+            sqlx::query(
+                r#"
+                INSERT INTO chain_info (genesis_hash, spec_id, ss58_format, token_symbol, token_decimals)
+                VALUES ($1, $2, $3, $4, $5)
+                "#,
+            )
+            .bind(self.genesis_hash.as_slice())
+            .bind(self.spec_id.as_str())
+            .bind(self.ss58_format.map(i32::from))
+            .bind(self.token_symbol.as_deref())
+            .bind(self.token_decimals.map(i32::from))
+            .execute(conn)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// fails if `genesis_hash`, read from `origin`, is not the genesis of this chain
+    pub fn check_genesis(&self, origin: &'static str, genesis_hash: &[u8]) -> ArchiveResult<()> {
+        if genesis_hash == self.genesis_hash.as_slice() {
+            Ok(())
+        } else {
+            Err(ArchiveError::MismatchedChains {
+                origin,
+                expected: format!("0x{}", hex::encode(&self.genesis_hash)),
+                found: format!("0x{}", hex::encode(genesis_hash)),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_reject_other_genesis() {
+        let info = ChainInfo {
+            genesis_hash: vec![1, 2, 3],
+            spec_id: "ksmcc3".into(),
+            ss58_format: Some(2),
+            token_symbol: Some("KSM".into()),
+            token_decimals: Some(12),
+        };
+        assert!(info.check_genesis("rpc node", &[1, 2, 3]).is_ok());
+        match info.check_genesis("rpc node", &[3, 2, 1]) {
+            Err(ArchiveError::MismatchedChains { origin, found, .. }) => {
+                assert_eq!(origin, "rpc node");
+                assert_eq!(found, "0x030201");
+            }
+            r => panic!("expected mismatched chains, got {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,281 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A cheap audit of the blocks in Postgres against the headers in RocksDB.
+//! Unlike verifying state roots, nothing is executed or read from the state,
+//! so whole chains can be checked in a reasonable amount of time.
+
+use crate::{
+    backend::ReadOnlyBackend,
+    error::{ArchiveResult, Error as ArchiveError},
+    queries,
+};
+use hashbrown::{HashMap, HashSet};
+use serde::Serialize;
+use sp_blockchain::HeaderBackend;
+use sp_runtime::{
+    generic::BlockId,
+    traits::{Block as BlockT, Header as _},
+    SaturatedConversion,
+};
+use sqlx::PgConnection;
+
+/// Something in Postgres that does not agree with RocksDB
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Inconsistency {
+    /// the block is not in RocksDB, so it could not be checked
+    NotInBackend { block_num: u64 },
+    /// the canonical block is not archived
+    MissingBlock { block_num: u64, hash: String },
+    /// a column of the archived block differs from its header
+    HeaderMismatch {
+        block_num: u64,
+        hash: String,
+        column: &'static str,
+        archived: String,
+        expected: String,
+    },
+    /// the parent of the block is not archived at the block number before it
+    BrokenLink {
+        block_num: u64,
+        hash: String,
+        parent_hash: String,
+    },
+    /// the block changed the state, but none of its storage is archived
+    MissingStorage { block_num: u64, hash: String },
+}
+
+impl Inconsistency {
+    pub fn block_num(&self) -> u64 {
+        match self {
+            Inconsistency::NotInBackend { block_num }
+            | Inconsistency::MissingBlock { block_num, .. }
+            | Inconsistency::HeaderMismatch { block_num, .. }
+            | Inconsistency::BrokenLink { block_num, .. }
+            | Inconsistency::MissingStorage { block_num, .. } => *block_num,
+        }
+    }
+
+    /// Hash of an archived row that has to be deleted before the block can be archived again.
+    /// Blocks are inserted with `ON CONFLICT DO NOTHING`, so fetching them again does not fix a bad row.
+    pub(crate) fn bad_row(&self) -> Option<Vec<u8>> {
+        match self {
+            Inconsistency::HeaderMismatch { hash, .. } => {
+                hex::decode(hash.trim_start_matches("0x")).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// the block that has to be fetched again to repair this
+    fn repair(&self) -> Option<u64> {
+        match self {
+            Inconsistency::NotInBackend { .. } => None,
+            // the block itself agrees with RocksDB, its parent is what is missing
+            Inconsistency::BrokenLink { block_num, .. } => Some(block_num - 1),
+            i => Some(i.block_num()),
+        }
+    }
+}
+
+/// The result of checking a range of blocks
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsistencyReport {
+    pub from: u64,
+    pub to: u64,
+    /// how many blocks were checked
+    pub checked: usize,
+    pub inconsistencies: Vec<Inconsistency>,
+}
+
+impl ConsistencyReport {
+    pub fn is_consistent(&self) -> bool {
+        self.inconsistencies.is_empty()
+    }
+
+    /// the blocks that have to be fetched again to repair the archive
+    pub fn repairs(&self) -> Vec<u64> {
+        let mut nums = self
+            .inconsistencies
+            .iter()
+            .filter_map(Inconsistency::repair)
+            .collect::<Vec<_>>();
+        nums.sort();
+        nums.dedup();
+        nums
+    }
+}
+
+/// Check the archived blocks from `from` to `to` (inclusive) against the headers in RocksDB
+pub async fn check_consistency<B>(
+    backend: &ReadOnlyBackend<B>,
+    conn: &mut PgConnection,
+    from: u64,
+    to: u64,
+) -> ArchiveResult<ConsistencyReport>
+where
+    B: BlockT,
+{
+    if from > to {
+        return Err(ArchiveError::InvalidRange { from, to });
+    }
+    // the block before `from` is needed to check the link of `from`
+    let first = from.saturating_sub(1);
+    // there may be more than one archived block at a height if the chain forked
+    let mut archived: HashMap<u64, Vec<(Vec<u8>, Vec<u8>, Vec<u8>)>> = HashMap::new();
+    for (num, hash, parent_hash, extrinsics_root) in
+        queries::block_links_in_range(conn, first, to).await?
+    {
+        archived
+            .entry(num as u64)
+            .or_default()
+            .push((hash, parent_hash, extrinsics_root));
+    }
+    let with_storage = queries::blocks_with_storage(conn, from, to)
+        .await?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    // storage before the retention horizon has been pruned on purpose
+    let horizon = queries::retention_horizon(conn).await?.unwrap_or(0);
+
+    let mut report = ConsistencyReport {
+        from,
+        to,
+        checked: 0,
+        inconsistencies: Vec::new(),
+    };
+    let mut parent_state_root = None;
+    if from > 0 {
+        parent_state_root = backend
+            .header(BlockId::Number(first.saturated_into()))?
+            .map(|h| *h.state_root());
+    }
+    for num in from..=to {
+        let header = match backend.header(BlockId::Number(num.saturated_into()))? {
+            Some(h) => h,
+            None => {
+                report
+                    .inconsistencies
+                    .push(Inconsistency::NotInBackend { block_num: num });
+                parent_state_root = None;
+                continue;
+            }
+        };
+        report.checked += 1;
+        let hash = header.hash();
+        let state_root = *header.state_root();
+        // a block that does not change the state has no storage to archive
+        let changes_state =
+            num > 0 && num >= horizon && parent_state_root.map_or(true, |r| r != state_root);
+        parent_state_root = Some(state_root);
+
+        let row = archived
+            .get(&num)
+            .and_then(|rows| rows.iter().find(|r| r.0.as_slice() == hash.as_ref()));
+        let (_, parent_hash, extrinsics_root) = match row {
+            Some(r) => r,
+            None => {
+                report.inconsistencies.push(Inconsistency::MissingBlock {
+                    block_num: num,
+                    hash: to_hex(hash.as_ref()),
+                });
+                continue;
+            }
+        };
+
+        let columns = [
+            ("parent_hash", parent_hash, header.parent_hash().as_ref()),
+            (
+                "extrinsics_root",
+                extrinsics_root,
+                header.extrinsics_root().as_ref(),
+            ),
+        ];
+        for (column, value, expected) in columns.iter() {
+            if value.as_slice() != *expected {
+                report.inconsistencies.push(Inconsistency::HeaderMismatch {
+                    block_num: num,
+                    hash: to_hex(hash.as_ref()),
+                    column: *column,
+                    archived: to_hex(value),
+                    expected: to_hex(expected),
+                });
+            }
+        }
+
+        let linked = num == 0
+            || archived.get(&(num - 1)).map_or(false, |rows| {
+                rows.iter()
+                    .any(|r| r.0.as_slice() == parent_hash.as_slice())
+            });
+        if !linked {
+            report.inconsistencies.push(Inconsistency::BrokenLink {
+                block_num: num,
+                hash: to_hex(hash.as_ref()),
+                parent_hash: to_hex(parent_hash),
+            });
+        }
+
+        if changes_state && !with_storage.contains(hash.as_ref()) {
+            report.inconsistencies.push(Inconsistency::MissingStorage {
+                block_num: num,
+                hash: to_hex(hash.as_ref()),
+            });
+        }
+    }
+    Ok(report)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_list_repairs_once() {
+        let report = ConsistencyReport {
+            from: 1,
+            to: 5,
+            checked: 4,
+            inconsistencies: vec![
+                Inconsistency::NotInBackend { block_num: 5 },
+                Inconsistency::MissingStorage {
+                    block_num: 3,
+                    hash: "0x03".into(),
+                },
+                Inconsistency::BrokenLink {
+                    block_num: 3,
+                    hash: "0x03".into(),
+                    parent_hash: "0x02".into(),
+                },
+                Inconsistency::HeaderMismatch {
+                    block_num: 2,
+                    hash: "0x02".into(),
+                    column: "extrinsics_root",
+                    archived: "0x00".into(),
+                    expected: "0x01".into(),
+                },
+            ],
+        };
+        assert_eq!(report.repairs(), vec![2, 3]);
+        assert_eq!(report.inconsistencies[3].bad_row(), Some(vec![2]));
+        assert_eq!(report.inconsistencies[2].bad_row(), None);
+    }
+}
@@ -19,16 +19,22 @@
 
 mod batch;
 pub mod models;
+mod partitions;
 pub mod queries;
 
 use async_trait::async_trait;
 use batch::Batch;
 use codec::Encode;
-use sp_runtime::traits::{Block as BlockT, Header as _, NumberFor};
+use std::convert::TryFrom;
+use sp_runtime::{
+    traits::{Block as BlockT, Header as _},
+    RuntimeString, SaturatedConversion,
+};
 use sqlx::{PgPool, postgres::PgPoolOptions, Postgres};
 use sqlx::prelude::*;
 
 use self::models::*;
+pub use self::partitions::{ensure_storage_index, partition, Partitions};
 use crate::{
     error::{ArchiveResult, Error as ArchiveError},
     types::*,
@@ -37,6 +43,11 @@ use crate::{
 pub type DbReturn = Result<u64, ArchiveError>;
 pub type DbConn = sqlx::pool::PoolConnection<Postgres>;
 
+/// A block number as a Postgres `bigint`
+pub(crate) fn sql_num(n: u64) -> ArchiveResult<i64> {
+    i64::try_from(n).map_err(|_| ArchiveError::BlockNumberConversion)
+}
+
 #[async_trait]
 pub trait Insert: Sync {
     async fn insert(mut self, conn: &mut DbConn) -> DbReturn
@@ -85,7 +96,6 @@ impl Database {
 impl<B> Insert for Block<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
     async fn insert(mut self, conn: &mut DbConn) -> DbReturn {
         log::info!("Inserting single block");
@@ -94,34 +104,42 @@ where
             self.inner.block.header().number(),
             hex::encode(self.inner.block.header().hash().as_ref())
         );
+        // a block inserted again keeps a justification that arrived after it was first inserted.
+        // `block_num` is unique whether or not `blocks` is partitioned, unlike the primary key
         let query = sqlx::query(
             r#"
-            INSERT INTO blocks (parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec)
-            VALUES($1, $2, $3, $4, $5, $6, $7, $8)
-            ON CONFLICT DO NOTHING
+            INSERT INTO blocks (parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec, justification, author)
+            VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            ON CONFLICT (block_num) DO UPDATE SET
+                justification = COALESCE(EXCLUDED.justification, blocks.justification)
+            WHERE blocks.hash = EXCLUDED.hash
         "#,
         );
         let parent_hash = self.inner.block.header().parent_hash().as_ref();
         let hash = self.inner.block.header().hash();
-        let block_num: u32 = (*self.inner.block.header().number()).into();
+        let block_num: u64 = (*self.inner.block.header().number()).saturated_into();
         let state_root = self.inner.block.header().state_root().as_ref();
         let extrinsics_root = self.inner.block.header().extrinsics_root().as_ref();
         let digest = self.inner.block.header().digest().encode();
         let extrinsics = self.inner.block.extrinsics().encode();
+        let digest_items = DigestItemModel::<B>::from_header(self.inner.block.header(), block_num);
 
-        query
+        let rows = query
             .bind(parent_hash)
             .bind(hash.as_ref())
-            .bind(block_num)
+            .bind(sql_num(block_num)?)
             .bind(state_root)
             .bind(extrinsics_root)
             .bind(digest.as_slice())
             .bind(extrinsics.as_slice())
             .bind(self.spec)
-            .execute(conn)
-            .await
-            .map(|d| d.rows_affected())
-            .map_err(Into::into)
+            .bind(self.inner.justification.as_deref())
+            .bind(self.author.as_deref())
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+        digest_items.insert(conn).await?;
+        Ok(rows)
     }
 }
 
@@ -134,14 +152,14 @@ impl<B: BlockT> Insert for StorageModel<B> {
                 INSERT INTO storage (
                     block_num, hash, is_full, key, storage
                 ) VALUES (#1, $2, $3, $4, $5)
-                ON CONFLICT (hash, key, md5(storage)) DO UPDATE SET
+                ON CONFLICT (block_num, hash, key, md5(storage)) DO UPDATE SET
                     hash = EXCLUDED.hash,
                     key = EXCLUDED.key,
                     storage = EXCLUDED.storage,
                     is_full = EXCLUDED.is_full
             "#,
         )
-        .bind(self.block_num())
+        .bind(sql_num(self.block_num())?)
         .bind(self.hash().as_ref())
         .bind(self.is_full())
         .bind(self.key().0.as_slice())
@@ -164,7 +182,7 @@ impl<B: BlockT> Insert for Vec<StorageModel<B>> {
             ) VALUES
             "#,
             r#"
-            ON CONFLICT (hash, key, md5(storage)) DO UPDATE SET
+            ON CONFLICT (block_num, hash, key, md5(storage)) DO UPDATE SET
                 hash = EXCLUDED.hash,
                 key = EXCLUDED.key,
                 storage = EXCLUDED.storage,
@@ -178,7 +196,7 @@ impl<B: BlockT> Insert for Vec<StorageModel<B>> {
                 batch.append(",");
             }
             batch.append("(");
-            batch.bind(s.block_num())?;
+            batch.bind(sql_num(s.block_num())?)?;
             batch.append(",");
             batch.bind(s.hash().as_ref())?;
             batch.append(",");
@@ -193,6 +211,45 @@ impl<B: BlockT> Insert for Vec<StorageModel<B>> {
     }
 }
 
+#[async_trait]
+impl<B: BlockT> Insert for Vec<ChildStorageModel<B>> {
+    async fn insert(mut self, conn: &mut DbConn) -> DbReturn {
+        let mut batch = Batch::new(
+            "child_storage",
+            r#"
+            INSERT INTO "child_storage" (
+                block_num, hash, is_full, child_key, key, storage
+            ) VALUES
+            "#,
+            r#"
+            ON CONFLICT (block_num, hash, child_key, key, md5(storage)) DO UPDATE SET
+                is_full = EXCLUDED.is_full
+            "#,
+        );
+
+        for s in self.into_iter() {
+            batch.reserve(6)?;
+            if batch.current_num_arguments() > 0 {
+                batch.append(",");
+            }
+            batch.append("(");
+            batch.bind(sql_num(s.block_num())?)?;
+            batch.append(",");
+            batch.bind(s.hash().as_ref())?;
+            batch.append(",");
+            batch.bind(s.is_full())?;
+            batch.append(",");
+            batch.bind(s.child_key().0.as_slice())?;
+            batch.append(",");
+            batch.bind(s.key().0.as_slice())?;
+            batch.append(",");
+            batch.bind(s.data().map(|d| d.0.as_slice()))?;
+            batch.append(")");
+        }
+        Ok(batch.execute(conn).await?)
+    }
+}
+
 #[async_trait]
 impl Insert for Metadata {
     async fn insert(mut self, conn: &mut DbConn) -> DbReturn {
@@ -201,7 +258,7 @@ impl Insert for Metadata {
             r#"
             INSERT INTO metadata (version, meta)
             VALUES($1, $2)
-            ON CONFLICT DO NOTHING
+            ON CONFLICT (version) DO UPDATE SET meta = EXCLUDED.meta
         "#,
         )
         .bind(self.version())
@@ -213,42 +270,102 @@ impl Insert for Metadata {
     }
 }
 
+#[async_trait]
+impl<B: BlockT> Insert for RuntimeCode<B> {
+    async fn insert(mut self, conn: &mut DbConn) -> DbReturn {
+        sqlx::query(
+            r#"
+            INSERT INTO runtime_code (code_hash, code)
+            VALUES($1, $2)
+            ON CONFLICT DO NOTHING
+        "#,
+        )
+        .bind(self.code_hash.as_ref())
+        .bind(self.code.as_slice())
+        .execute(conn)
+        .await
+        .map(|d| d.rows_affected())
+        .map_err(Into::into)
+    }
+}
+
+#[async_trait]
+impl<B: BlockT> Insert for RuntimeUpgrade<B> {
+    async fn insert(mut self, conn: &mut DbConn) -> DbReturn {
+        log::info!(
+            "Inserting runtime upgrade to spec {}",
+            self.version.spec_version
+        );
+        let v = &self.version;
+        sqlx::query(
+            r#"
+            INSERT INTO runtime_upgrades (
+                block_num, block_hash, spec_name, impl_name, authoring_version,
+                spec_version, impl_version, transaction_version, apis, code_hash
+            )
+            VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            ON CONFLICT DO NOTHING
+        "#,
+        )
+        .bind(sql_num(self.block_num)?)
+        .bind(self.block_hash.as_ref())
+        .bind(runtime_string(&v.spec_name))
+        .bind(runtime_string(&v.impl_name))
+        .bind(v.authoring_version)
+        .bind(v.spec_version)
+        .bind(v.impl_version)
+        .bind(v.transaction_version)
+        .bind(v.apis.encode())
+        .bind(self.code_hash.as_ref())
+        .execute(conn)
+        .await
+        .map(|d| d.rows_affected())
+        .map_err(Into::into)
+    }
+}
+
 #[async_trait]
 impl<B> Insert for BatchBlock<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
     async fn insert(mut self, conn: &mut DbConn) -> DbReturn {
         let mut batch = Batch::new(
             "blocks",
             r#"
             INSERT INTO "blocks" (
-                parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec
+                parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec, justification, author
             ) VALUES
             "#,
             r#"
-            ON CONFLICT DO NOTHING
+            ON CONFLICT (block_num) DO UPDATE SET
+                justification = COALESCE(EXCLUDED.justification, blocks.justification)
+            WHERE blocks.hash = EXCLUDED.hash
             "#,
         );
+        let mut digest_items = Vec::new();
         for b in self.inner.into_iter() {
-            batch.reserve(8)?;
+            batch.reserve(10)?;
             if batch.current_num_arguments() > 0 {
                 batch.append(",");
             }
             let parent_hash = b.inner.block.header().parent_hash().as_ref();
             let hash = b.inner.block.header().hash();
-            let block_num: u32 = (*b.inner.block.header().number()).into();
+            let block_num: u64 = (*b.inner.block.header().number()).saturated_into();
             let state_root = b.inner.block.header().state_root().as_ref();
             let extrinsics_root = b.inner.block.header().extrinsics_root().as_ref();
             let digest = b.inner.block.header().digest().encode();
             let extrinsics = b.inner.block.extrinsics().encode();
+            digest_items.extend(DigestItemModel::<B>::from_header(
+                b.inner.block.header(),
+                block_num,
+            ));
             batch.append("(");
             batch.bind(parent_hash)?;
             batch.append(",");
             batch.bind(hash.as_ref())?;
             batch.append(",");
-            batch.bind(block_num)?;
+            batch.bind(sql_num(block_num)?)?;
             batch.append(",");
             batch.bind(state_root)?;
             batch.append(",");
@@ -259,12 +376,116 @@ where
             batch.bind(extrinsics.as_slice())?;
             batch.append(",");
             batch.bind(b.spec)?;
+            batch.append(",");
+            batch.bind(b.inner.justification.as_deref())?;
+            batch.append(",");
+            batch.bind(b.author.as_deref())?;
+            batch.append(")");
+        }
+        let rows = batch.execute(&mut *conn).await?;
+        digest_items.insert(conn).await?;
+        Ok(rows)
+    }
+}
+
+#[async_trait]
+impl<B: BlockT> Insert for Vec<DigestItemModel<B>> {
+    async fn insert(mut self, conn: &mut DbConn) -> DbReturn {
+        let mut batch = Batch::new(
+            "digest_items",
+            r#"
+            INSERT INTO "digest_items" (
+                block_num, hash, item_index, item_type, engine_id, data
+            ) VALUES
+            "#,
+            r#"
+            ON CONFLICT DO NOTHING
+            "#,
+        );
+        for d in self.iter() {
+            batch.reserve(6)?;
+            if batch.current_num_arguments() > 0 {
+                batch.append(",");
+            }
+            batch.append("(");
+            batch.bind(sql_num(d.block_num())?)?;
+            batch.append(",");
+            batch.bind(d.hash().as_ref())?;
+            batch.append(",");
+            batch.bind(d.index())?;
+            batch.append(",");
+            batch.bind(d.item_type())?;
+            batch.append(",");
+            batch.bind(d.engine_id())?;
+            batch.append(",");
+            batch.bind(d.data())?;
             batch.append(")");
         }
         Ok(batch.execute(conn).await?)
     }
 }
 
+#[async_trait]
+impl<B: BlockT> Insert for Vec<BalanceModel<B>> {
+    async fn insert(mut self, conn: &mut DbConn) -> DbReturn {
+        let mut batch = Batch::new(
+            "balances",
+            r#"
+            INSERT INTO "balances" (
+                block_num, hash, account, nonce, free, reserved, misc_frozen, fee_frozen
+            ) VALUES
+            "#,
+            r#"
+            ON CONFLICT (hash, account) DO UPDATE SET
+                nonce = EXCLUDED.nonce,
+                free = EXCLUDED.free,
+                reserved = EXCLUDED.reserved,
+                misc_frozen = EXCLUDED.misc_frozen,
+                fee_frozen = EXCLUDED.fee_frozen
+            "#,
+        );
+        for b in self.iter() {
+            batch.reserve(8)?;
+            if batch.current_num_arguments() > 0 {
+                batch.append(",");
+            }
+            batch.append("(");
+            batch.bind(sql_num(b.block_num())?)?;
+            batch.append(",");
+            batch.bind(b.hash().as_ref())?;
+            batch.append(",");
+            batch.bind(b.account())?;
+            batch.append(",");
+            batch.bind(i64::from(b.nonce()))?;
+            batch.append(",");
+            bind_numeric(&mut batch, b.free())?;
+            batch.append(",");
+            bind_numeric(&mut batch, b.reserved())?;
+            batch.append(",");
+            bind_numeric(&mut batch, b.misc_frozen())?;
+            batch.append(",");
+            bind_numeric(&mut batch, b.fee_frozen())?;
+            batch.append(")");
+        }
+        Ok(batch.execute(conn).await?)
+    }
+}
+
+fn runtime_string(s: &RuntimeString) -> String {
+    match s {
+        RuntimeString::Borrowed(s) => s.to_string(),
+        RuntimeString::Owned(s) => s.clone(),
+    }
+}
+
+/// u128 has no postgres equivalent, so it is sent as text and cast to `NUMERIC`
+fn bind_numeric(batch: &mut Batch, value: u128) -> ArchiveResult<()> {
+    batch.append("CAST(");
+    batch.bind(value.to_string())?;
+    batch.append(" AS NUMERIC)");
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     //! Must be connected to a local database
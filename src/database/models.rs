@@ -20,14 +20,20 @@
 
 use crate::actors::msg;
 use crate::types::*;
+use codec::{Decode, Encode};
 use serde::{Deserialize, Serialize};
-use sp_runtime::traits::Block as BlockT;
+use sp_core::hashing::{blake2_128, twox_128};
+use sp_runtime::{
+    generic::DigestItem,
+    traits::{Block as BlockT, Header as HeaderT},
+    ConsensusEngineId,
+};
 use sp_storage::{StorageData, StorageKey};
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct StorageModel<Block: BlockT> {
     hash: Block::Hash,
-    block_num: u32,
+    block_num: u64,
     full_storage: bool,
     key: StorageKey,
     data: Option<StorageData>,
@@ -36,7 +42,7 @@ pub struct StorageModel<Block: BlockT> {
 impl<Block: BlockT> StorageModel<Block> {
     pub fn new(
         hash: Block::Hash,
-        block_num: u32,
+        block_num: u64,
         full_storage: bool,
         key: StorageKey,
         data: Option<StorageData>,
@@ -54,7 +60,7 @@ impl<Block: BlockT> StorageModel<Block> {
         self.full_storage
     }
 
-    pub fn block_num(&self) -> u32 {
+    pub fn block_num(&self) -> u64 {
         self.block_num
     }
 
@@ -93,3 +99,309 @@ impl<Block: BlockT> From<msg::VecStorageWrap<Block>> for Vec<StorageModel<Block>
             .collect()
     }
 }
+
+/// A change of a key in a child trie
+#[derive(Clone, Debug)]
+pub struct ChildStorageModel<Block: BlockT> {
+    hash: Block::Hash,
+    block_num: u64,
+    full_storage: bool,
+    child_key: StorageKey,
+    key: StorageKey,
+    data: Option<StorageData>,
+}
+
+impl<Block: BlockT> ChildStorageModel<Block> {
+    pub fn new(
+        hash: Block::Hash,
+        block_num: u64,
+        full_storage: bool,
+        child_key: StorageKey,
+        key: StorageKey,
+        data: Option<StorageData>,
+    ) -> Self {
+        Self {
+            hash,
+            block_num,
+            full_storage,
+            child_key,
+            key,
+            data,
+        }
+    }
+
+    /// the changes of child tries in `storage`
+    pub fn from_storage(storage: &Storage<Block>) -> Vec<Self> {
+        storage
+            .child_changes
+            .iter()
+            .flat_map(|(child_key, changes)| {
+                changes.iter().map(move |(key, data)| Self {
+                    hash: *storage.hash(),
+                    block_num: storage.block_num(),
+                    full_storage: storage.is_full(),
+                    child_key: child_key.clone(),
+                    key: key.clone(),
+                    data: data.clone(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.full_storage
+    }
+
+    pub fn block_num(&self) -> u64 {
+        self.block_num
+    }
+
+    pub fn hash(&self) -> &Block::Hash {
+        &self.hash
+    }
+
+    /// storage key of the child trie, without the `:child_storage:default:` prefix
+    pub fn child_key(&self) -> &StorageKey {
+        &self.child_key
+    }
+
+    pub fn key(&self) -> &StorageKey {
+        &self.key
+    }
+
+    pub fn data(&self) -> Option<&StorageData> {
+        self.data.as_ref()
+    }
+}
+
+/// One item of a block's digest
+#[derive(Clone, Debug)]
+pub struct DigestItemModel<Block: BlockT> {
+    hash: Block::Hash,
+    block_num: u64,
+    index: u32,
+    item_type: &'static str,
+    engine_id: Option<ConsensusEngineId>,
+    data: Vec<u8>,
+}
+
+impl<Block: BlockT> DigestItemModel<Block> {
+    /// Decode the digest items of a header
+    pub fn from_header(header: &Block::Header, block_num: u64) -> Vec<Self> {
+        let hash = header.hash();
+        header
+            .digest()
+            .logs()
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                let (item_type, engine_id, data) = match item {
+                    DigestItem::PreRuntime(id, data) => ("PreRuntime", Some(*id), data.clone()),
+                    DigestItem::Consensus(id, data) => ("Consensus", Some(*id), data.clone()),
+                    DigestItem::Seal(id, data) => ("Seal", Some(*id), data.clone()),
+                    DigestItem::Other(data) => ("Other", None, data.clone()),
+                    DigestItem::ChangesTrieRoot(root) => ("ChangesTrieRoot", None, root.encode()),
+                    DigestItem::ChangesTrieSignal(s) => ("ChangesTrieSignal", None, s.encode()),
+                };
+                Self {
+                    hash,
+                    block_num,
+                    index: index as u32,
+                    item_type,
+                    engine_id,
+                    data,
+                }
+            })
+            .collect()
+    }
+
+    pub fn hash(&self) -> &Block::Hash {
+        &self.hash
+    }
+
+    pub fn block_num(&self) -> u64 {
+        self.block_num
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn item_type(&self) -> &'static str {
+        self.item_type
+    }
+
+    pub fn engine_id(&self) -> Option<&[u8]> {
+        self.engine_id.as_ref().map(|id| &id[..])
+    }
+
+    pub fn data(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+}
+
+/// Balance of an account at a block, decoded from `System::Account`.
+/// Balances of runtimes from before `System::Account` are not decoded, since the keys of
+/// `Balances::FreeBalance` are `blake2_256` hashes of the account, which can't be reversed.
+#[derive(Clone, Debug)]
+pub struct BalanceModel<Block: BlockT> {
+    hash: Block::Hash,
+    block_num: u64,
+    account: Vec<u8>,
+    nonce: u32,
+    data: AccountData,
+}
+
+/// `pallet_balances::AccountData`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Decode)]
+struct AccountData {
+    free: u128,
+    reserved: u128,
+    misc_frozen: u128,
+    fee_frozen: u128,
+}
+
+impl<Block: BlockT> BalanceModel<Block> {
+    /// Decode the balances changed in `storage`.
+    /// Values that can't be decoded are skipped.
+    pub fn from_storage(storage: &Storage<Block>) -> Vec<Self> {
+        let account_prefix = storage_prefix(b"System", b"Account");
+        storage
+            .changes()
+            .iter()
+            .filter(|(key, _)| key.0.starts_with(&account_prefix))
+            .filter_map(|(key, data)| {
+                let account = account_id(&key.0[32..])?;
+                let info = decode_account_info(data.as_ref().map(|d| d.0.as_slice()));
+                if info.is_none() {
+                    log::warn!(
+                        "could not decode balance of 0x{} at block {}",
+                        hex::encode(&key.0),
+                        storage.block_num()
+                    );
+                }
+                let (nonce, data) = info?;
+                Some(Self {
+                    hash: *storage.hash(),
+                    block_num: storage.block_num(),
+                    account,
+                    nonce,
+                    data,
+                })
+            })
+            .collect()
+    }
+
+    pub fn hash(&self) -> &Block::Hash {
+        &self.hash
+    }
+
+    pub fn block_num(&self) -> u64 {
+        self.block_num
+    }
+
+    /// the SCALE-encoded account id
+    pub fn account(&self) -> &[u8] {
+        self.account.as_slice()
+    }
+
+    pub fn nonce(&self) -> u32 {
+        self.nonce
+    }
+
+    pub fn free(&self) -> u128 {
+        self.data.free
+    }
+
+    pub fn reserved(&self) -> u128 {
+        self.data.reserved
+    }
+
+    pub fn misc_frozen(&self) -> u128 {
+        self.data.misc_frozen
+    }
+
+    pub fn fee_frozen(&self) -> u128 {
+        self.data.fee_frozen
+    }
+}
+
+/// Decode the nonce and `AccountData` of an `AccountInfo`.
+/// The reference counts between them have changed between runtimes,
+/// so the layout is told apart by the length of the value:
+/// - `refcount: u8`
+/// - `refcount: u32`
+/// - `consumers: u32, providers: u32`
+/// - `consumers: u32, providers: u32, sufficients: u32`
+///
+/// A removed account has nothing left.
+fn decode_account_info(data: Option<&[u8]>) -> Option<(u32, AccountData)> {
+    let mut data = match data {
+        Some(data) => data,
+        None => return Some((0, AccountData::default())),
+    };
+    let info = match data.len() {
+        69 => <(u32, u8, AccountData)>::decode(&mut data).map(|(n, _, d)| (n, d)),
+        72 => <(u32, u32, AccountData)>::decode(&mut data).map(|(n, _, d)| (n, d)),
+        76 => <(u32, u32, u32, AccountData)>::decode(&mut data).map(|(n, _, _, d)| (n, d)),
+        80 => <(u32, u32, u32, u32, AccountData)>::decode(&mut data).map(|(n, _, _, _, d)| (n, d)),
+        _ => return None,
+    };
+    info.ok()
+}
+
+fn storage_prefix(module: &[u8], item: &[u8]) -> [u8; 32] {
+    let mut prefix = [0u8; 32];
+    prefix[..16].copy_from_slice(&twox_128(module));
+    prefix[16..].copy_from_slice(&twox_128(item));
+    prefix
+}
+
+/// The account id at the end of a `blake2_128_concat` map key.
+/// Keys hashed any other way can't be reversed, and are skipped.
+fn account_id(hashed: &[u8]) -> Option<Vec<u8>> {
+    if hashed.len() > 16 && blake2_128(&hashed[16..])[..] == hashed[..16] {
+        Some(hashed[16..].to_vec())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_decode_account_info() {
+        let data = AccountData {
+            free: 100,
+            reserved: 20,
+            misc_frozen: 3,
+            fee_frozen: 4,
+        };
+        let balances = (100u128, 20u128, 3u128, 4u128);
+        // nonce, the reference counts and the four balances of `AccountData`
+        for info in vec![
+            (7u32, 1u8, balances).encode(),
+            (7u32, 1u32, balances).encode(),
+            (7u32, 1u32, 2u32, balances).encode(),
+            (7u32, 1u32, 2u32, 0u32, balances).encode(),
+        ] {
+            assert_eq!(
+                decode_account_info(Some(info.as_slice())),
+                Some((7, data.clone()))
+            );
+        }
+        assert_eq!(decode_account_info(None), Some((0, AccountData::default())));
+        assert!(decode_account_info(Some(&[0u8; 12])).is_none());
+        assert!(decode_account_info(Some(&[0u8; 70])).is_none());
+    }
+
+    #[test]
+    fn should_only_reverse_blake2_128_concat_keys() {
+        let account = [1u8; 32];
+        let key = [&blake2_128(&account)[..], &account[..]].concat();
+        assert_eq!(account_id(&key), Some(account.to_vec()));
+        assert_eq!(account_id(&sp_core::hashing::blake2_256(&account)), None);
+    }
+}
@@ -0,0 +1,308 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Range partitioning of the `blocks` and `storage` tables by block number.
+//! Partitioning is opt-in with `MigrationConfig::partition_size`.
+//! Existing tables are converted once when the archive starts,
+//! and partitions for new blocks are created as indexing advances.
+//!
+//! Partitioned tables can only enforce uniqueness on columns that include `block_num`,
+//! so tables referencing `blocks` do so by `(hash, block_num)` instead of `hash`.
+//! Foreign keys on `blocks(hash)`, including those of `StorageIndexer` tables,
+//! are converted when partitioning, which needs the referencing table to have a `block_num` column.
+
+use crate::error::{ArchiveResult, Error as ArchiveError};
+use hashbrown::HashSet;
+use sqlx::{Connection as _, PgConnection};
+
+/// A foreign key on `blocks`
+struct Reference {
+    /// referencing table, quoted if necessary
+    table: String,
+    /// constraint name, quoted if necessary
+    constraint: String,
+}
+
+/// Find the foreign keys that reference `blocks(hash)`.
+/// Fails if a referencing table has no `block_num` column to reference partitions by
+async fn references(conn: &mut PgConnection) -> ArchiveResult<Vec<Reference>> {
+    let rows: Vec<(String, String, Vec<String>, bool)> = sqlx::query_as(
+        r#"
+        SELECT
+            c.conrelid::regclass::text,
+            quote_ident(c.conname),
+            ARRAY(
+                SELECT attname::text FROM pg_attribute
+                WHERE attrelid = c.conrelid AND attnum = ANY(c.conkey)
+            ),
+            EXISTS(
+                SELECT 1 FROM pg_attribute
+                WHERE attrelid = c.conrelid AND attname = 'block_num' AND NOT attisdropped
+            )
+        FROM pg_constraint c
+        WHERE c.contype = 'f' AND c.confrelid = 'blocks'::regclass
+        "#,
+    )
+    .fetch_all(conn)
+    .await?;
+    rows.into_iter()
+        .map(|(table, constraint, columns, has_block_num)| {
+            if columns != ["hash"] || !has_block_num {
+                return Err(ArchiveError::UnpartitionableReference { table, constraint });
+            }
+            Ok(Reference { table, constraint })
+        })
+        .collect()
+}
+
+/// Storage is deduplicated by block number as well as hash, key and value,
+/// since unique indexes of a partitioned table must include the partition key.
+/// The index is built concurrently, so the archive stays usable while an existing `storage` table is indexed.
+pub async fn ensure_storage_index(conn: &mut PgConnection) -> ArchiveResult<()> {
+    let valid: Option<(bool,)> = sqlx::query_as(
+        "SELECT indisvalid FROM pg_index WHERE indexrelid = to_regclass('storage_block_hash_key_storage')",
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    match valid {
+        Some((true,)) => return Ok(()),
+        // an interrupted concurrent build leaves an invalid index behind
+        Some((false,)) => {
+            sqlx::query("DROP INDEX CONCURRENTLY storage_block_hash_key_storage")
+                .execute(&mut *conn)
+                .await?;
+        }
+        None => (),
+    }
+    log::info!("indexing storage by block number, hash, key and value");
+    sqlx::query(
+        "CREATE UNIQUE INDEX CONCURRENTLY storage_block_hash_key_storage ON storage (block_num, hash, key, md5(storage))",
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query("DROP INDEX CONCURRENTLY IF EXISTS only_unique_hash_key_storage")
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Creates the partitions that new blocks go into
+#[derive(Clone)]
+pub struct Partitions {
+    /// number of blocks in each partition
+    size: u32,
+    /// first block of the partitions known to exist
+    created: HashSet<u64>,
+}
+
+impl Partitions {
+    /// `None` if `blocks` and `storage` are not partitioned
+    pub async fn load(conn: &mut PgConnection) -> ArchiveResult<Option<Self>> {
+        let size: Option<(i32,)> = sqlx::query_as("SELECT size FROM partitioning")
+            .fetch_optional(conn)
+            .await?;
+        Ok(size.map(|s| Self {
+            size: s.0 as u32,
+            created: HashSet::new(),
+        }))
+    }
+
+    /// make sure the partitions that hold `block_nums` exist
+    pub async fn ensure(
+        &mut self,
+        conn: &mut PgConnection,
+        block_nums: impl Iterator<Item = u64>,
+    ) -> ArchiveResult<()> {
+        let size = self.size;
+        let mut starts = block_nums
+            .map(|n| n - n % u64::from(size))
+            .filter(|s| !self.created.contains(s))
+            .collect::<Vec<_>>();
+        starts.sort();
+        starts.dedup();
+        for start in starts.into_iter() {
+            create_partition(conn, "blocks", "blocks", start, size).await?;
+            create_partition(conn, "storage", "storage", start, size).await?;
+            self.created.insert(start);
+        }
+        Ok(())
+    }
+}
+
+async fn create_partition(
+    conn: &mut PgConnection,
+    parent: &str,
+    prefix: &str,
+    start: u64,
+    size: u32,
+) -> ArchiveResult<()> {
+    let name = format!("{}_{}", prefix, start);
+    let query = format!(
+        "CREATE TABLE IF NOT EXISTS {} PARTITION OF {} FOR VALUES FROM ({}) TO ({})",
+        name,
+        parent,
+        start,
+        start.saturating_add(size.into())
+    );
+    if let Err(e) = sqlx::query(query.as_str()).execute(&mut *conn).await {
+        // `IF NOT EXISTS` does not stop two connections from racing to create the same partition
+        let exists: (bool,) = sqlx::query_as("SELECT to_regclass($1) IS NOT NULL")
+            .bind(name.as_str())
+            .fetch_one(&mut *conn)
+            .await?;
+        if !exists.0 {
+            return Err(ArchiveError::from(e));
+        }
+    }
+    Ok(())
+}
+
+/// Convert `blocks` and `storage` into tables partitioned by ranges of `size` blocks.
+/// Every row is copied, so this may take a long time for a large archive.
+/// Does nothing if the tables are already partitioned.
+pub async fn partition(conn: &mut PgConnection, size: u32) -> ArchiveResult<()> {
+    if let Some(p) = Partitions::load(conn).await? {
+        if p.size != size {
+            log::warn!(
+                "tables are already partitioned by {} blocks, ignoring partition size {}",
+                p.size,
+                size
+            );
+        }
+        return Ok(());
+    }
+    if size == 0 {
+        log::warn!("partition size must be at least 1 block, not partitioning");
+        return Ok(());
+    }
+    log::info!("partitioning blocks and storage by {} blocks", size);
+    let mut tx = conn.begin().await?;
+    let references = references(&mut *tx).await?;
+    for r in references.iter() {
+        let query = format!("ALTER TABLE {} DROP CONSTRAINT {}", r.table, r.constraint);
+        sqlx::query(query.as_str()).execute(&mut *tx).await?;
+    }
+    sqlx::query(
+        r#"
+        CREATE TABLE blocks_partitioned (
+          id SERIAL NOT NULL,
+          parent_hash bytea NOT NULL,
+          hash bytea NOT NULL,
+          block_num bigint CONSTRAINT blocks_block_num_check CHECK (block_num >= 0) NOT NULL,
+          state_root bytea NOT NULL,
+          extrinsics_root bytea NOT NULL,
+          digest bytea NOT NULL,
+          ext bytea NOT NULL,
+          spec integer NOT NULL REFERENCES metadata(version) ON DELETE CASCADE ON UPDATE CASCADE,
+          justification bytea,
+          author bytea,
+          PRIMARY KEY (hash, block_num),
+          UNIQUE (block_num)
+        ) PARTITION BY RANGE (block_num)
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        r#"
+        CREATE TABLE storage_partitioned (
+          id SERIAL NOT NULL,
+          block_num bigint CONSTRAINT storage_block_num_check CHECK (block_num >= 0) NOT NULL,
+          hash bytea NOT NULL,
+          is_full boolean NOT NULL,
+          key bytea NOT NULL,
+          storage bytea,
+          PRIMARY KEY (id, block_num)
+        ) PARTITION BY RANGE (block_num)
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let max: (Option<i64>,) = sqlx::query_as("SELECT MAX(block_num) FROM blocks")
+        .fetch_one(&mut *tx)
+        .await?;
+    let max = max.0.unwrap_or(0) as u64;
+    let mut start = 0;
+    while start <= max {
+        create_partition(&mut *tx, "blocks_partitioned", "blocks", start, size).await?;
+        create_partition(&mut *tx, "storage_partitioned", "storage", start, size).await?;
+        start += u64::from(size);
+    }
+
+    let copied = sqlx::query(
+        r#"
+        INSERT INTO blocks_partitioned (
+            id, parent_hash, hash, block_num, state_root, extrinsics_root,
+            digest, ext, spec, justification, author
+        )
+        SELECT id, parent_hash, hash, block_num, state_root, extrinsics_root,
+            digest, ext, spec, justification, author
+        FROM blocks
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    log::info!("copied {} blocks", copied);
+    let copied = sqlx::query(
+        r#"
+        INSERT INTO storage_partitioned (id, block_num, hash, is_full, key, storage)
+        SELECT id, block_num, hash, is_full, key, storage FROM storage
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    log::info!("copied {} storage entries", copied);
+
+    for table in ["blocks", "storage"].iter() {
+        let statements = [
+            format!(
+                "SELECT setval(pg_get_serial_sequence('{0}_partitioned', 'id'), COALESCE(MAX(id), 0) + 1, false) FROM {0}",
+                table
+            ),
+            format!("DROP TABLE {}", table),
+            format!("ALTER TABLE {0}_partitioned RENAME TO {0}", table),
+        ];
+        for statement in statements.iter() {
+            sqlx::query(statement.as_str()).execute(&mut *tx).await?;
+        }
+    }
+
+    let indexes = [
+        "CREATE INDEX blocks_author_index ON blocks (author)",
+        "CREATE INDEX storage_block_num_index ON storage (block_num)",
+        "CREATE UNIQUE INDEX storage_block_hash_key_storage ON storage (block_num, hash, key, md5(storage))",
+    ];
+    for index in indexes.iter() {
+        sqlx::query(index).execute(&mut *tx).await?;
+    }
+    for r in references.iter() {
+        let query = format!(
+            "ALTER TABLE {} ADD CONSTRAINT {} FOREIGN KEY (hash, block_num)
+            REFERENCES blocks(hash, block_num) ON DELETE CASCADE ON UPDATE CASCADE",
+            r.table, r.constraint
+        );
+        sqlx::query(query.as_str()).execute(&mut *tx).await?;
+    }
+    sqlx::query("INSERT INTO partitioning (size) VALUES ($1)")
+        .bind(size as i32)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
@@ -17,6 +17,7 @@
 //! Common Sql queries on Archive Database abstracted into rust functions
 
 use crate::{
+    database::sql_num,
     error::{ArchiveResult, Error as ArchiveError},
     sql_block_builder::SqlBlock,
 };
@@ -26,15 +27,15 @@ use sqlx::PgConnection;
 
 #[derive(sqlx::FromRow, Debug, Clone)]
 pub struct BlockNumSeries {
-    pub generate_series: i32,
+    pub generate_series: i64,
 }
 
 /// get missing blocks from relational database as a stream
 #[allow(unused)]
 pub(crate) fn missing_blocks_stream(
     conn: &mut PgConnection,
-) -> impl Stream<Item = Result<(i32,), sqlx::Error>> + Send + '_ {
-    sqlx::query_as::<_, (i32,)>(
+) -> impl Stream<Item = Result<(i64,), sqlx::Error>> + Send + '_ {
+    sqlx::query_as::<_, (i64,)>(
         "SELECT generate_series
         FROM (SELECT 0 as a, max(block_num) as z FROM blocks) x, generate_series(a, z)
         WHERE
@@ -47,8 +48,8 @@ pub(crate) fn missing_blocks_stream(
 
 /// get missing blocks from relational database
 #[allow(unused)]
-pub(crate) async fn missing_blocks(conn: &mut PgConnection) -> ArchiveResult<Vec<u32>> {
-    Ok(sqlx::query_as::<_, (i32,)>(
+pub(crate) async fn missing_blocks(conn: &mut PgConnection) -> ArchiveResult<Vec<u64>> {
+    Ok(sqlx::query_as::<_, (i64,)>(
         "SELECT generate_series
         FROM (SELECT 0 as a, max(block_num) as z FROM blocks) x, generate_series(a, z)
         WHERE
@@ -59,15 +60,15 @@ pub(crate) async fn missing_blocks(conn: &mut PgConnection) -> ArchiveResult<Vec
     .fetch_all(conn)
     .await?
     .iter()
-    .map(|t| t.0 as u32)
+    .map(|t| t.0 as u64)
     .collect())
 }
 
 pub(crate) async fn missing_blocks_min_max(
     conn: &mut PgConnection,
-    min: u32,
-) -> ArchiveResult<Vec<u32>> {
-    Ok(sqlx::query_as::<_, (i32,)>(
+    min: u64,
+) -> ArchiveResult<Vec<u64>> {
+    Ok(sqlx::query_as::<_, (i64,)>(
         "SELECT generate_series
         FROM (SELECT $1 as a, max(block_num) as z FROM blocks) x, generate_series(a, z)
         WHERE
@@ -75,16 +76,16 @@ pub(crate) async fn missing_blocks_min_max(
         ORDER BY generate_series ASC
         ",
     )
-    .bind(min as i32)
+    .bind(sql_num(min)?)
     .fetch_all(conn)
     .await?
     .iter()
-    .map(|t| t.0 as u32)
+    .map(|t| t.0 as u64)
     .collect())
 }
 
 /// Will get blocks such that they exist in the `blocks` table but they
-/// do not exist in the `storage` table
+/// do not exist in the `storage` table, ignoring blocks whose storage has been pruned
 /// blocks are ordered by spec version
 /// this is so the runtime code can be kept in cache without
 /// constantly switching between runtime versions if the blocks will be executed
@@ -95,6 +96,7 @@ pub(crate) async fn blocks_storage_intersection(
         "SELECT *
         FROM blocks
         WHERE NOT EXISTS (SELECT * FROM storage WHERE storage.block_num = blocks.block_num)
+        AND blocks.block_num >= COALESCE((SELECT block_num FROM retention_horizon), 0)
         ORDER BY blocks.spec",
     )
     .fetch_all(conn)
@@ -102,19 +104,91 @@ pub(crate) async fn blocks_storage_intersection(
     .map_err(Into::into)
 }
 
+/// get at most `limit` blocks from `block_num` onwards, ordered by block number
+pub(crate) async fn blocks_from(
+    conn: &mut sqlx::PgConnection,
+    block_num: u64,
+    limit: usize,
+) -> Result<Vec<SqlBlock>, ArchiveError> {
+    sqlx::query_as(
+        "SELECT *
+        FROM blocks
+        WHERE block_num >= $1
+        ORDER BY block_num
+        LIMIT $2",
+    )
+    .bind(sql_num(block_num)?)
+    .bind(limit as i64)
+    .fetch_all(conn)
+    .await
+    .map_err(Into::into)
+}
+
+/// get the blocks from `from` to `to` (inclusive), ordered by spec version
+pub(crate) async fn blocks_in_range(
+    conn: &mut sqlx::PgConnection,
+    from: u64,
+    to: u64,
+) -> Result<Vec<SqlBlock>, ArchiveError> {
+    sqlx::query_as(
+        "SELECT *
+        FROM blocks
+        WHERE block_num BETWEEN $1 AND $2
+        ORDER BY blocks.spec",
+    )
+    .bind(sql_num(from)?)
+    .bind(sql_num(to)?)
+    .fetch_all(conn)
+    .await
+    .map_err(Into::into)
+}
+
+/// hash, parent hash and extrinsics root of the blocks from `from` to `to` (inclusive)
+pub(crate) async fn block_links_in_range(
+    conn: &mut PgConnection,
+    from: u64,
+    to: u64,
+) -> Result<Vec<(i64, Vec<u8>, Vec<u8>, Vec<u8>)>, ArchiveError> {
+    sqlx::query_as(
+        "SELECT block_num, hash, parent_hash, extrinsics_root
+        FROM blocks
+        WHERE block_num BETWEEN $1 AND $2",
+    )
+    .bind(sql_num(from)?)
+    .bind(sql_num(to)?)
+    .fetch_all(conn)
+    .await
+    .map_err(Into::into)
+}
+
+/// hashes of the blocks from `from` to `to` (inclusive) that have storage
+pub(crate) async fn blocks_with_storage(
+    conn: &mut PgConnection,
+    from: u64,
+    to: u64,
+) -> Result<Vec<Vec<u8>>, ArchiveError> {
+    let rows: Vec<(Vec<u8>,)> =
+        sqlx::query_as("SELECT DISTINCT hash FROM storage WHERE block_num BETWEEN $1 AND $2")
+            .bind(sql_num(from)?)
+            .bind(sql_num(to)?)
+            .fetch_all(conn)
+            .await?;
+    Ok(rows.into_iter().map(|r| r.0).collect())
+}
+
 #[cfg(test)]
 pub(crate) async fn get_full_block(
     conn: &mut sqlx::PgConnection,
-    block_num: u32,
+    block_num: u64,
 ) -> Result<SqlBlock, ArchiveError> {
     sqlx::query_as(
         "
-        SELECT parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec
+        SELECT parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec, justification
         FROM blocks
         WHERE block_num = $1
         ",
     )
-    .bind(block_num as i32)
+    .bind(sql_num(block_num)?)
     .fetch_one(pool)
     .await
     .map_err(Into::into)
@@ -154,7 +228,7 @@ pub(crate) async fn contains_block<B: BlockT>(
 }
 
 pub(crate) async fn contains_blocks<B: BlockT>(
-    nums: &[u32],
+    nums: &[u64],
     conn: &mut PgConnection,
 ) -> Result<bool, ArchiveError> {
     let mut query = String::from("SELECT EXISTS(SELECT block_num FROM blocks WHERE block_num IN (");
@@ -169,6 +243,54 @@ pub(crate) async fn contains_blocks<B: BlockT>(
     Ok(row.0)
 }
 
+/// check if the full state of a block has been archived
+pub(crate) async fn contains_full_storage(
+    block_num: u64,
+    conn: &mut PgConnection,
+) -> Result<bool, ArchiveError> {
+    let row: (bool,) =
+        sqlx::query_as("SELECT EXISTS(SELECT 1 FROM storage WHERE block_num = $1 AND is_full)")
+            .bind(sql_num(block_num)?)
+            .fetch_one(conn)
+            .await?;
+    Ok(row.0)
+}
+
+/// the last block the indexer `name` has indexed
+pub(crate) async fn indexer_checkpoint(
+    name: &str,
+    conn: &mut PgConnection,
+) -> Result<Option<u64>, ArchiveError> {
+    let row: Option<(i64,)> =
+        sqlx::query_as("SELECT block_num FROM indexer_checkpoints WHERE name = $1")
+            .bind(name)
+            .fetch_optional(conn)
+            .await?;
+    Ok(row.map(|r| r.0 as u64))
+}
+
+/// the state root of the archived block `hash`
+pub(crate) async fn state_root(
+    hash: &[u8],
+    conn: &mut PgConnection,
+) -> Result<Option<Vec<u8>>, ArchiveError> {
+    let row: Option<(Vec<u8>,)> = sqlx::query_as("SELECT state_root FROM blocks WHERE hash = $1")
+        .bind(hash)
+        .fetch_optional(conn)
+        .await?;
+    Ok(row.map(|r| r.0))
+}
+
+/// storage changes of blocks before this block have been pruned
+pub(crate) async fn retention_horizon(
+    conn: &mut PgConnection,
+) -> Result<Option<u64>, ArchiveError> {
+    let row: Option<(i64,)> = sqlx::query_as("SELECT block_num FROM retention_horizon")
+        .fetch_optional(conn)
+        .await?;
+    Ok(row.map(|r| r.0 as u64))
+}
+
 #[derive(sqlx::FromRow, Debug)]
 pub struct Version {
     pub version: i32,
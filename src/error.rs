@@ -13,7 +13,7 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::{env, io};
+use std::{env, fmt, io, sync::Arc};
 use thiserror::Error;
 
 pub type ArchiveResult<T> = std::result::Result<T, Error>;
@@ -27,55 +27,160 @@ pub enum Error {
     Env(#[from] env::VarError),
     #[error("decode")]
     Codec(#[from] codec::Error),
+    #[error("failed to decode {context}: {source}")]
+    Decode {
+        context: &'static str,
+        #[source]
+        source: codec::Error,
+    },
     #[error("Formatting {0}")]
     Fmt(#[from] std::fmt::Error),
     #[error("serialization error")]
     Serialization(#[from] serde_json::Error),
     #[error("sqlx error: {0}")]
-    Sql(#[from] sqlx::Error),
+    Sql(sqlx::Error),
+    #[error("postgres is unavailable: {0}")]
+    DatabaseUnavailable(sqlx::Error),
+    #[error("database constraint violated ({code}): {message}")]
+    ConstraintViolation { code: String, message: String },
     #[error("blockchain error: {0}")]
     Blockchain(String),
+    #[error("block {0} not found")]
+    BlockNotFound(String),
+    #[error("block {block_num} has not been inserted into the database yet")]
+    BlockNotArchived { block_num: u64 },
+    #[error("no state found for block {0}")]
+    MissingState(String),
+    #[error("could not read the state of block {hash}: {source}")]
+    StateUnavailable {
+        hash: String,
+        #[source]
+        source: Box<Error>,
+    },
+    #[error("invalid storage proof: {0}")]
+    InvalidProof(String),
+    #[error("runtime execution failed: {0}")]
+    Execution(String),
+    #[error("failed to execute block {block_num} ({hash}): {reason}")]
+    BlockExecution {
+        block_num: u64,
+        hash: String,
+        reason: String,
+    },
+    #[error("could not resolve the author of block {block_num}: {source}")]
+    AuthorUnresolved {
+        block_num: u64,
+        #[source]
+        source: Box<Error>,
+    },
+    #[error("indexers are stuck on block {block_num}, which is executed again")]
+    IndexerStalled { block_num: u64 },
+    #[error("{table} references blocks by hash with {constraint}, it needs a block_num column to reference partitioned blocks")]
+    UnpartitionableReference { table: String, constraint: String },
+    #[error("invalid block range {from}..={to}")]
+    InvalidRange { from: u64, to: u64 },
+    #[error("the archive must be running to {0}")]
+    NotRunning(&'static str),
+    #[error("block number is out of range for the database")]
+    BlockNumberConversion,
     #[error("JSONRPC request failed")]
     RpcRequest(#[from] jsonrpsee::client::RequestError),
     #[error("DNS error")]
     Dns(#[from] jsonrpsee::transport::ws::WsNewDnsError),
+    #[error("lost connection to the rpc node")]
+    RpcDisconnected,
     #[error("couldn't run migrations")]
     SqlMigration(#[from] refinery::Error),
     #[error("could not build threadpool")]
     ThreadPool(#[from] rayon::ThreadPoolBuildError),
-    #[error(
-        "the chain given to substrate-archive is different then the running chain. Trying to run {0}, running {1}"
-    )]
-    MismatchedChains(String, String),
+    #[error("the {origin} belongs to a different chain: found {found}, expected {expected}")]
+    MismatchedChains {
+        origin: &'static str,
+        expected: String,
+        found: String,
+    },
+    #[error("the genesis storage of the chain spec has state root {computed}, but block 0 has {expected}")]
+    GenesisStateMismatch { expected: String, computed: String },
+    #[error("could not build the genesis storage from the chain spec: {0}")]
+    GenesisStorage(String),
+    #[error("the node database is a {0} database, archiving needs the database of a full node")]
+    LightDatabase(String),
+    #[error("the state of block {block_num} has been pruned from the node database, the node must run with `--pruning=archive`")]
+    PrunedDatabase { block_num: u64 },
+    #[error("secondary database directory {0} is used by another archive")]
+    SecondaryDirInUse(String),
     #[error("sending on disconnected channel")]
     Channel,
     #[error("Trying to send to disconnected actor")]
     Disconnected,
-    #[error("Unexpected Error {0}")]
-    General(String),
+    #[error("actor {0} was restarted too many times")]
+    TooManyRestarts(String),
+    #[error("unrecoverable error: {0}")]
+    Unrecoverable(#[source] Arc<Error>),
 
     #[cfg(test)]
     #[error("{0}")]
     Bincode(#[from] Box<bincode::ErrorKind>),
 }
 
-impl From<&str> for Error {
-    fn from(e: &str) -> Error {
-        Error::General(e.to_string())
+impl Error {
+    /// Whether the operation that failed may succeed if it is tried again later.
+    /// Missing blocks may still be written by the node,
+    /// and the connections to Postgres and the RPC node may come back.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::StateUnavailable { source, .. } => source.is_retryable(),
+            _ => matches!(
+                self,
+                Error::Io(_)
+                    | Error::DatabaseUnavailable(_)
+                    | Error::BlockNotFound(_)
+                    | Error::BlockNotArchived { .. }
+                    | Error::RpcRequest(_)
+                    | Error::RpcDisconnected
+            ),
+        }
+    }
+
+    /// the state of the block `hash` could not be read because of `source`
+    pub(crate) fn state_unavailable(hash: impl fmt::Debug, source: sp_blockchain::Error) -> Self {
+        Error::StateUnavailable {
+            hash: format!("{:?}", hash),
+            source: Box::new(source.into()),
+        }
     }
 }
 
-impl From<String> for Error {
-    fn from(e: String) -> Error {
-        Error::General(e)
+impl From<sqlx::Error> for Error {
+    fn from(e: sqlx::Error) -> Error {
+        match e {
+            // class 23 is `Integrity Constraint Violation`
+            // https://www.postgresql.org/docs/current/errcodes-appendix.html
+            sqlx::Error::Database(ref db)
+                if db.code().map(|c| c.starts_with("23")) == Some(true) =>
+            {
+                Error::ConstraintViolation {
+                    code: db.code().map(|c| c.to_string()).unwrap_or_default(),
+                    message: db.message().to_string(),
+                }
+            }
+            sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed => {
+                Error::DatabaseUnavailable(e)
+            }
+            e => Error::Sql(e),
+        }
     }
 }
 
-// this conversion is required for our Error type to be
-// Send + Sync
+// sp_blockchain::Error is not Sync,
+// so we keep only what we need to tell the errors apart
 impl From<sp_blockchain::Error> for Error {
     fn from(e: sp_blockchain::Error) -> Error {
-        Error::Blockchain(e.to_string())
+        match e {
+            sp_blockchain::Error::UnknownBlock(b) => Error::BlockNotFound(b),
+            sp_blockchain::Error::Execution(e) => Error::Execution(e.to_string()),
+            e => Error::Blockchain(e.to_string()),
+        }
     }
 }
 
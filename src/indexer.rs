@@ -0,0 +1,62 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Plugins that maintain their own tables from the storage changes of every block.
+//! Each indexer is called once per block, in order of block number starting at block 1
+//! (the genesis block is not executed, so it has no changes), and remembers the last
+//! block it indexed in the `indexer_checkpoints` table. When the archive is restarted,
+//! blocks after the checkpoint are executed again so that the indexer can pick up where it left off.
+
+use crate::{backend::BlockChanges, error::ArchiveResult};
+use sp_runtime::{generic::SignedBlock, traits::Block as BlockT};
+use sqlx::{Postgres, Transaction};
+
+/// Maintains custom tables derived from storage changes
+#[async_trait::async_trait]
+pub trait StorageIndexer<B: BlockT>: Send + Sync {
+    /// Unique name of this indexer. The checkpoint of the indexer is stored under this name,
+    /// so renaming an indexer starts it over from genesis.
+    fn name(&self) -> &str;
+
+    /// SQL statements that create the tables of this indexer.
+    /// They are run every time the archive starts, so they must be idempotent
+    /// (IE, `CREATE TABLE IF NOT EXISTS`)
+    fn migrations(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Index the changes of one block.
+    /// The checkpoint of the indexer is updated in `tx`,
+    /// so either the block is indexed and checkpointed, or neither happens.
+    async fn index(
+        &self,
+        block: &SignedBlock<B>,
+        changes: &BlockChanges<B>,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> ArchiveResult<()>;
+
+    /// Delete everything this indexer has indexed from block `from` onwards.
+    /// Called when blocks are reindexed with `Reindex::delete_existing`.
+    /// Otherwise, indexers are passed blocks they have already indexed again,
+    /// so `index` should overwrite rather than duplicate rows.
+    async fn delete_from(
+        &self,
+        _from: u64,
+        _tx: &mut Transaction<'_, Postgres>,
+    ) -> ArchiveResult<()> {
+        Ok(())
+    }
+}
@@ -19,10 +19,15 @@
 
 mod actors;
 pub mod archive;
+mod author;
 pub mod backend;
+mod chain_info;
+mod consistency;
 mod database;
 mod error;
+mod indexer;
 mod migrations;
+mod proof;
 mod rpc;
 #[cfg(test)]
 mod simple_db;
@@ -30,24 +35,41 @@ mod sql_block_builder;
 mod threadpools;
 mod types;
 mod util;
+mod verify;
 
-pub use actors::System;
+pub use actors::{IndexingOptions, System};
 pub use archive::{ArchiveBuilder, ArchiveConfig};
+pub use author::{AuraAuthorResolver, AuthorResolver, BabeAuthorResolver};
+pub use backend::frontend::{
+    default_execution_strategies, uniform_execution_strategies, ExecutionMethod,
+};
+pub use backend::{CatchUpStats, SecondaryDir, TrieCacheStats};
+pub use consistency::{ConsistencyReport, Inconsistency};
 pub use database::queries;
 pub use error::Error;
+pub use indexer::StorageIndexer;
 pub use migrations::MigrationConfig;
-pub use types::Archive;
+pub use proof::ProofKey;
+pub use types::{Archive, Reindex, ReindexStage, RetentionPolicy};
+pub use verify::{StateRootMismatch, Verification, VerificationReport};
 
 #[cfg(feature = "logging")]
 pub use util::init_logger;
 
 // Re-Exports
+pub use sc_client_api::{execution_extensions::ExecutionStrategies, ExecutionStrategy};
 pub use sc_executor::native_executor_instance;
 pub use sp_blockchain::Error as BlockchainError;
-pub use sp_runtime::MultiSignature;
+pub use sp_runtime::{generic::BlockId, MultiSignature};
+pub use sp_state_machine::StorageProof;
 pub mod chain_traits {
     //! Traits defining functions on the client needed for indexing
     pub use sc_client_api::client::BlockBackend;
     pub use sp_blockchain::{HeaderBackend, HeaderMetadata};
     pub use sp_runtime::traits::{BlakeTwo256, Block, IdentifyAccount, Verify};
+    // bounds of `ArchiveBuilder`, for code that is generic over the runtime
+    pub use sc_client_api::backend::StateBackendFor;
+    pub use sc_executor::NativeExecutionDispatch;
+    pub use sp_api::{ApiExt, ConstructRuntimeApi, StateBackend};
+    pub use sp_block_builder::BlockBuilder as BlockBuilderApi;
 }
@@ -56,6 +56,10 @@ pub struct MigrationConfig {
     pub user: Option<String>,
     pub pass: Option<String>,
     pub name: Option<String>,
+    /// Range-partition the `blocks` and `storage` tables by this many blocks.
+    /// Existing tables are converted when the archive starts, which copies every row.
+    /// Tables are not partitioned if this is `None`
+    pub partition_size: Option<u32>,
 }
 
 impl MigrationConfig {
@@ -0,0 +1,7 @@
+-- storage changes of blocks before `block_num` have been pruned by the retention policy.
+-- Only ever holds a single row
+
+CREATE TABLE IF NOT EXISTS retention_horizon (
+  id boolean PRIMARY KEY DEFAULT true CHECK (id),
+  block_num int check (block_num >= 0 and block_num < 2147483647) NOT NULL
+);
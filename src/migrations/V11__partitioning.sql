@@ -0,0 +1,7 @@
+-- number of blocks in each partition of `blocks` and `storage`, if they are partitioned.
+-- Only ever holds a single row.
+-- The unique index of `storage` that partitioning needs is built concurrently by the archive on startup
+CREATE TABLE IF NOT EXISTS partitioning (
+  id boolean PRIMARY KEY DEFAULT true CHECK (id),
+  size int check (size > 0) NOT NULL
+);
@@ -0,0 +1,30 @@
+-- block numbers are bigint, so that chains with u64 block numbers fit
+-- (numbers past the maximum of a bigint wrap around when bound and are rejected)
+
+ALTER TABLE blocks DROP CONSTRAINT IF EXISTS blocks_block_num_check;
+ALTER TABLE blocks ALTER COLUMN block_num TYPE bigint;
+ALTER TABLE blocks ADD CONSTRAINT blocks_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE storage DROP CONSTRAINT IF EXISTS storage_block_num_check;
+ALTER TABLE storage ALTER COLUMN block_num TYPE bigint;
+ALTER TABLE storage ADD CONSTRAINT storage_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE runtime_upgrades DROP CONSTRAINT IF EXISTS runtime_upgrades_block_num_check;
+ALTER TABLE runtime_upgrades ALTER COLUMN block_num TYPE bigint;
+ALTER TABLE runtime_upgrades ADD CONSTRAINT runtime_upgrades_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE digest_items DROP CONSTRAINT IF EXISTS digest_items_block_num_check;
+ALTER TABLE digest_items ALTER COLUMN block_num TYPE bigint;
+ALTER TABLE digest_items ADD CONSTRAINT digest_items_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE balances DROP CONSTRAINT IF EXISTS balances_block_num_check;
+ALTER TABLE balances ALTER COLUMN block_num TYPE bigint;
+ALTER TABLE balances ADD CONSTRAINT balances_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE indexer_checkpoints DROP CONSTRAINT IF EXISTS indexer_checkpoints_block_num_check;
+ALTER TABLE indexer_checkpoints ALTER COLUMN block_num TYPE bigint;
+ALTER TABLE indexer_checkpoints ADD CONSTRAINT indexer_checkpoints_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE retention_horizon DROP CONSTRAINT IF EXISTS retention_horizon_block_num_check;
+ALTER TABLE retention_horizon ALTER COLUMN block_num TYPE bigint;
+ALTER TABLE retention_horizon ADD CONSTRAINT retention_horizon_block_num_check CHECK (block_num >= 0);
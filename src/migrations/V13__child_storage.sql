@@ -0,0 +1,26 @@
+-- changes of keys in child tries.
+-- `child_key` is the storage key of the child trie, without the `:child_storage:default:` prefix
+CREATE TABLE IF NOT EXISTS child_storage (
+  id SERIAL PRIMARY KEY,
+  block_num bigint CHECK (block_num >= 0) NOT NULL,
+  hash bytea NOT NULL,
+  is_full boolean NOT NULL,
+  child_key bytea NOT NULL,
+  key bytea NOT NULL,
+  storage bytea
+);
+
+-- partitioned `blocks` can only be referenced by `(hash, block_num)`
+DO $$
+BEGIN
+  IF EXISTS (SELECT 1 FROM pg_class WHERE oid = 'blocks'::regclass AND relkind = 'p') THEN
+    ALTER TABLE child_storage ADD CONSTRAINT child_storage_hash_fkey FOREIGN KEY (hash, block_num)
+      REFERENCES blocks(hash, block_num) ON DELETE CASCADE ON UPDATE CASCADE;
+  ELSE
+    ALTER TABLE child_storage ADD CONSTRAINT child_storage_hash_fkey FOREIGN KEY (hash)
+      REFERENCES blocks(hash) ON DELETE CASCADE ON UPDATE CASCADE;
+  END IF;
+END $$;
+
+CREATE UNIQUE INDEX IF NOT EXISTS child_storage_block_hash_child_key_storage ON child_storage (block_num, hash, child_key, key, md5(storage));
+CREATE INDEX IF NOT EXISTS child_storage_child_key_key_index ON child_storage (child_key, key);
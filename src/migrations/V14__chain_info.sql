@@ -0,0 +1,10 @@
+-- identity of the chain this archive belongs to, recorded on the first run.
+-- Only ever holds a single row
+CREATE TABLE IF NOT EXISTS chain_info (
+  id boolean PRIMARY KEY DEFAULT true CHECK (id),
+  genesis_hash bytea NOT NULL,
+  spec_id text NOT NULL,
+  ss58_format int,
+  token_symbol text,
+  token_decimals int
+);
@@ -0,0 +1,7 @@
+-- every balance is decoded from `System::Account`, which holds all of the balances of an account.
+-- The legacy `Balances::FreeBalance` is not decoded: its keys are `blake2_256` hashes,
+-- so the account a balance belongs to can't be recovered.
+ALTER TABLE balances ALTER COLUMN nonce SET NOT NULL;
+ALTER TABLE balances ALTER COLUMN reserved SET NOT NULL;
+ALTER TABLE balances ALTER COLUMN misc_frozen SET NOT NULL;
+ALTER TABLE balances ALTER COLUMN fee_frozen SET NOT NULL;
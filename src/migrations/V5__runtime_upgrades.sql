@@ -0,0 +1,25 @@
+-- runtime wasm blobs, deduplicated by the hash of the code
+
+CREATE TABLE IF NOT EXISTS runtime_code (
+  code_hash bytea NOT NULL PRIMARY KEY,
+  code bytea NOT NULL
+);
+
+-- every runtime enacted on chain, and the first block that ran it
+
+CREATE TABLE IF NOT EXISTS runtime_upgrades (
+  id SERIAL NOT NULL,
+  block_num int check (block_num >= 0 and block_num < 2147483647) NOT NULL PRIMARY KEY,
+  block_hash bytea NOT NULL UNIQUE,
+  spec_name text NOT NULL,
+  impl_name text NOT NULL,
+  authoring_version integer NOT NULL,
+  spec_version integer NOT NULL,
+  impl_version integer NOT NULL,
+  transaction_version integer NOT NULL,
+  -- SCALE-encoded list of (api id, api version)
+  apis bytea NOT NULL,
+  code_hash bytea NOT NULL REFERENCES runtime_code(code_hash)
+);
+
+CREATE INDEX runtime_upgrades_spec_version_index ON runtime_upgrades (spec_version);
@@ -0,0 +1,20 @@
+ALTER TABLE blocks ADD COLUMN IF NOT EXISTS justification bytea;
+
+-- the items of each block's digest, decoded
+-- engine_id is only set for PreRuntime, Consensus and Seal items
+
+CREATE TABLE IF NOT EXISTS digest_items (
+  id SERIAL NOT NULL,
+  block_num int check (block_num >= 0 and block_num < 2147483647) NOT NULL,
+  hash bytea NOT NULL REFERENCES blocks(hash) ON DELETE CASCADE ON UPDATE CASCADE,
+  item_index int NOT NULL,
+  item_type text check (item_type IN (
+    'PreRuntime', 'Consensus', 'Seal', 'Other', 'ChangesTrieRoot', 'ChangesTrieSignal'
+  )) NOT NULL,
+  engine_id bytea,
+  data bytea NOT NULL,
+  PRIMARY KEY (hash, item_index)
+);
+
+CREATE INDEX digest_items_block_num_index ON digest_items (block_num);
+CREATE INDEX digest_items_engine_id_index ON digest_items (engine_id);
@@ -0,0 +1,5 @@
+-- SCALE-encoded account of the block author
+-- only set if the archive was started with an author resolver
+ALTER TABLE blocks ADD COLUMN IF NOT EXISTS author bytea;
+
+CREATE INDEX IF NOT EXISTS blocks_author_index ON blocks (author);
@@ -0,0 +1,19 @@
+-- balances of accounts, decoded from `System::Account` storage.
+-- Balances decoded from the legacy `Balances::FreeBalance` only know the free balance,
+-- so the other columns are NULL for them.
+-- balances are unsigned 128-bit integers, which only fit into numeric
+
+CREATE TABLE IF NOT EXISTS balances (
+  id SERIAL NOT NULL,
+  block_num int check (block_num >= 0 and block_num < 2147483647) NOT NULL,
+  hash bytea NOT NULL REFERENCES blocks(hash) ON DELETE CASCADE ON UPDATE CASCADE,
+  account bytea NOT NULL,
+  nonce bigint,
+  free numeric(39, 0) NOT NULL,
+  reserved numeric(39, 0),
+  misc_frozen numeric(39, 0),
+  fee_frozen numeric(39, 0),
+  PRIMARY KEY (hash, account)
+);
+
+CREATE INDEX balances_account_block_num_index ON balances (account, block_num);
@@ -0,0 +1,6 @@
+-- the last block each `StorageIndexer` plugin has indexed
+
+CREATE TABLE IF NOT EXISTS indexer_checkpoints (
+  name text PRIMARY KEY,
+  block_num int check (block_num >= 0 and block_num < 2147483647) NOT NULL
+);
@@ -0,0 +1,206 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Merkle proofs of the state of archived blocks.
+//! A proof holds the trie nodes on the paths from the state root to the proven keys,
+//! so anyone who trusts the state root of a block can check the values without the archive.
+
+use crate::{
+    backend::ReadOnlyBackend,
+    error::{ArchiveResult, Error as ArchiveError},
+};
+use codec::Codec;
+use hash_db::Hasher;
+use hashbrown::HashMap;
+use sc_client_api::backend::{Backend, StateBackend};
+use sp_core::storage::{ChildInfo, ChildType};
+use sp_runtime::{
+    generic::BlockId,
+    traits::{Block as BlockT, HashFor},
+};
+use sp_state_machine::{
+    prove_child_read_on_trie_backend, prove_read_on_trie_backend, read_child_proof_check,
+    read_proof_check, StorageProof, TrieBackend, TrieBackendStorage,
+};
+use std::collections::BTreeMap;
+
+/// A storage key in the state of a block
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ProofKey {
+    /// a key in the top trie
+    Top(Vec<u8>),
+    /// A key in a child trie.
+    /// `child_key` is the storage key of the child trie, with or without the `:child_storage:default:` prefix
+    Child { child_key: Vec<u8>, key: Vec<u8> },
+}
+
+impl ProofKey {
+    pub fn key(&self) -> &[u8] {
+        match self {
+            ProofKey::Top(key) | ProofKey::Child { key, .. } => key.as_slice(),
+        }
+    }
+
+    pub fn child_key(&self) -> Option<&[u8]> {
+        match self {
+            ProofKey::Top(_) => None,
+            ProofKey::Child { child_key, .. } => Some(child_key.as_slice()),
+        }
+    }
+}
+
+/// Prove the values of `keys` in the state of the block `hash`
+pub fn prove_read<B>(
+    backend: &ReadOnlyBackend<B>,
+    hash: B::Hash,
+    keys: &[ProofKey],
+) -> ArchiveResult<StorageProof>
+where
+    B: BlockT,
+{
+    let mut state = Backend::state_at(backend, BlockId::Hash(hash))
+        .map_err(|e| ArchiveError::state_unavailable(hash, e))?;
+    let trie = state
+        .as_trie_backend()
+        .ok_or_else(|| ArchiveError::MissingState(format!("{:?}", hash)))?;
+    prove_on_trie(trie, keys).map_err(|e| ArchiveError::MissingState(format!("{:?}: {}", hash, e)))
+}
+
+fn prove_on_trie<S, H>(
+    trie: &TrieBackend<S, H>,
+    keys: &[ProofKey],
+) -> Result<StorageProof, Box<dyn std::error::Error>>
+where
+    S: TrieBackendStorage<H>,
+    H: Hasher,
+    H::Out: Ord + Codec,
+{
+    let (top, children) = split(keys);
+    let mut proofs = vec![prove_read_on_trie_backend(trie, top)?];
+    for (child_key, keys) in children.into_iter() {
+        proofs.push(prove_child_read_on_trie_backend(
+            trie,
+            &child_info(child_key),
+            keys,
+        )?);
+    }
+    Ok(StorageProof::merge(proofs))
+}
+
+/// Check `proof` against the state root of a block, returning the proven value of every key.
+/// Fails if the proof does not prove all of `keys`.
+pub fn verify_read_proof<B>(
+    state_root: B::Hash,
+    proof: &StorageProof,
+    keys: &[ProofKey],
+) -> ArchiveResult<Vec<(ProofKey, Option<Vec<u8>>)>>
+where
+    B: BlockT,
+{
+    let invalid = |e: Box<dyn std::error::Error>| ArchiveError::InvalidProof(e.to_string());
+    let (top, children) = split(keys);
+    let mut values = HashMap::new();
+    for (key, value) in
+        read_proof_check::<HashFor<B>, _>(state_root, proof.clone(), top).map_err(&invalid)?
+    {
+        values.insert(ProofKey::Top(key), value);
+    }
+    for (child_key, keys) in children.into_iter() {
+        let child_values = read_child_proof_check::<HashFor<B>, _>(
+            state_root,
+            proof.clone(),
+            &child_info(child_key),
+            keys,
+        )
+        .map_err(&invalid)?;
+        for (key, value) in child_values {
+            let child_key = child_key.to_vec();
+            values.insert(ProofKey::Child { child_key, key }, value);
+        }
+    }
+    Ok(keys
+        .iter()
+        .map(|k| (k.clone(), values.get(k).cloned().flatten()))
+        .collect())
+}
+
+/// keys of the top trie, and keys of each child trie
+fn split(keys: &[ProofKey]) -> (Vec<&[u8]>, BTreeMap<&[u8], Vec<&[u8]>>) {
+    let mut top = Vec::new();
+    let mut children = BTreeMap::new();
+    for k in keys.iter() {
+        match k.child_key() {
+            None => top.push(k.key()),
+            Some(child_key) => children
+                .entry(child_key)
+                .or_insert_with(Vec::new)
+                .push(k.key()),
+        }
+    }
+    (top, children)
+}
+
+fn child_info(child_key: &[u8]) -> ChildInfo {
+    match ChildType::from_prefixed_key(child_key) {
+        Some((ChildType::ParentKeyId, key)) => ChildInfo::new_default(key),
+        None => ChildInfo::new_default(child_key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sp_core::H256;
+    use sp_runtime::testing::{Block as TestBlock, ExtrinsicWrapper};
+    use sp_state_machine::InMemoryBackend;
+
+    type Block = TestBlock<ExtrinsicWrapper<u64>>;
+
+    #[test]
+    fn should_verify_top_and_child_keys() {
+        let child = ChildInfo::new_default(b"contract");
+        let trie = InMemoryBackend::<HashFor<Block>>::from(vec![
+            (
+                None,
+                vec![
+                    (b"alice".to_vec(), Some(b"10".to_vec())),
+                    (b"bob".to_vec(), Some(b"20".to_vec())),
+                ],
+            ),
+            (
+                Some(child.clone()),
+                vec![(b"x".to_vec(), Some(b"1".to_vec()))],
+            ),
+        ]);
+        let root = *trie.root();
+        let keys = vec![
+            ProofKey::Top(b"alice".to_vec()),
+            ProofKey::Top(b"carol".to_vec()),
+            ProofKey::Child {
+                child_key: child.prefixed_storage_key().into_inner(),
+                key: b"x".to_vec(),
+            },
+        ];
+        let proof = prove_on_trie(&trie, &keys).unwrap();
+
+        let values = verify_read_proof::<Block>(root, &proof, &keys).unwrap();
+        assert_eq!(
+            values.into_iter().map(|(_, v)| v).collect::<Vec<_>>(),
+            vec![Some(b"10".to_vec()), None, Some(b"1".to_vec())]
+        );
+        assert!(verify_read_proof::<Block>(H256::repeat_byte(1), &proof, &keys).is_err());
+    }
+}
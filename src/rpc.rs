@@ -22,8 +22,7 @@ use jsonrpsee::{
     Client,
 };
 use sp_core::Bytes;
-use sp_runtime::traits::{Block as BlockT, HashFor};
-use sp_version::RuntimeVersion;
+use sp_runtime::traits::Block as BlockT;
 use std::marker::PhantomData;
 
 use crate::error::Error as ArchiveError;
@@ -45,18 +44,6 @@ impl<Block: BlockT> Rpc<Block> {
         })
     }
 
-    pub(crate) async fn version(
-        &self,
-        hash: Option<&HashFor<Block>>,
-    ) -> Result<RuntimeVersion, ArchiveError> {
-        let params = Params::Array(vec![to_json_value(hash)?]);
-        let version = self
-            .client
-            .request("state_getRuntimeVersion", params)
-            .await?;
-        Ok(version)
-    }
-
     pub(crate) async fn metadata(
         &self,
         hash: Option<Block::Hash>,
@@ -66,6 +53,13 @@ impl<Block: BlockT> Rpc<Block> {
         Ok(bytes.0)
     }
 
+    /// hash of the block `num` on the canonical chain of the node
+    pub(crate) async fn block_hash(&self, num: u64) -> Result<Option<Block::Hash>, ArchiveError> {
+        let params = Params::Array(vec![to_json_value(num)?]);
+        let hash = self.client.request("chain_getBlockHash", params).await?;
+        Ok(hash)
+    }
+
     pub(crate) async fn subscribe_finalized_heads(
         &self,
     ) -> Result<Subscription<Block::Header>, ArchiveError> {
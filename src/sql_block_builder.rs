@@ -19,10 +19,11 @@
 //! this is a (much) faster alternative
 
 use crate::{error::Error as ArchiveError, types};
-use codec::{Decode, Encode};
+use codec::Decode;
 use sp_runtime::{
     generic::SignedBlock,
     traits::{Block as BlockT, DigestFor, Header as HeaderT},
+    SaturatedConversion,
 };
 use std::marker::PhantomData;
 
@@ -30,12 +31,13 @@ use std::marker::PhantomData;
 pub struct SqlBlock {
     parent_hash: Vec<u8>,
     hash: Vec<u8>,
-    block_num: i32,
+    block_num: i64,
     state_root: Vec<u8>,
     extrinsics_root: Vec<u8>,
     digest: Vec<u8>,
     ext: Vec<u8>,
     spec: i32,
+    justification: Option<Vec<u8>>,
 }
 
 pub struct BlockBuilder<B: BlockT> {
@@ -54,10 +56,11 @@ impl<'a, B: BlockT> BlockBuilder<B> {
         blocks
             .into_iter()
             .map(|b| {
+                let justification = b.justification.clone();
                 let (b, s) = self.with_single(b)?;
                 let b = SignedBlock {
                     block: b,
-                    justification: None,
+                    justification,
                 };
                 Ok(types::Block::new(b, s))
             })
@@ -65,18 +68,25 @@ impl<'a, B: BlockT> BlockBuilder<B> {
     }
 
     pub fn with_single(&self, block: SqlBlock) -> Result<(B, u32), ArchiveError> {
-        let digest: DigestFor<B> = Decode::decode(&mut block.digest.as_slice())?;
+        let digest: DigestFor<B> =
+            Decode::decode(&mut block.digest.as_slice()).map_err(|e| ArchiveError::Decode {
+                context: "digest",
+                source: e,
+            })?;
         let (parent_hash, state_root, extrinsics_root) = Self::into_generic(
             block.parent_hash.as_slice(),
             block.state_root.as_slice(),
             block.extrinsics_root.as_slice(),
         )?;
-        let num: <B::Header as HeaderT>::Number =
-            Decode::decode(&mut (block.block_num as u32).encode().as_slice())?;
+        let num: <B::Header as HeaderT>::Number = (block.block_num as u64).saturated_into();
 
         let header =
             <B::Header as HeaderT>::new(num, extrinsics_root, state_root, parent_hash, digest);
-        let ext: Vec<B::Extrinsic> = Decode::decode(&mut block.ext.as_slice())?;
+        let ext: Vec<B::Extrinsic> =
+            Decode::decode(&mut block.ext.as_slice()).map_err(|e| ArchiveError::Decode {
+                context: "extrinsics",
+                source: e,
+            })?;
         let spec = block.spec;
         Ok((B::new(header, ext), spec as u32))
     }
@@ -14,23 +14,40 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-pub use self::block_exec_pool::BlockData;
 use self::block_exec_pool::BlockExecPool;
+pub use self::block_exec_pool::{BlockData, ExecOutcome, FailedBlock};
 use self::block_fetcher::ThreadedBlockFetcher;
 use self::block_scheduler::BlockScheduler;
-use crate::backend::{ApiAccess, BlockChanges, ReadOnlyBackend as Backend};
-use crate::{actors::ActorContext, error::ArchiveResult, types::Block};
+use crate::backend::{ApiAccess, GetRuntimeVersion, ReadOnlyBackend as Backend};
+use crate::{
+    actors::{ActorContext, Supervisor},
+    error::ArchiveResult,
+    types::Block,
+};
 use block_scheduler::Ordering;
-use futures::{Stream, StreamExt};
+use futures::Stream;
+use hashbrown::HashMap;
 use sc_client_api::backend;
 use sp_api::{ApiExt, ConstructRuntimeApi};
 use sp_block_builder::BlockBuilder as BlockBuilderApi;
-use sp_runtime::traits::{Block as BlockT, NumberFor};
-use std::{sync::Arc, thread, time::Duration};
+use sp_runtime::traits::Block as BlockT;
+use std::{
+    sync::{
+        atomic::{self, AtomicUsize},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 mod block_exec_pool;
 mod block_fetcher;
 mod block_scheduler;
 
+/// How many times a block that failed with a retryable error is re-queued for execution
+const MAX_EXEC_RETRIES: usize = 3;
+/// Delay before the first retry of a failed block execution, doubled on every further retry
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
 // TODO: Can abstract these two structs into just something that implements a trait
 // this follows a similar API to xtra's Actor/Address api (attach_stream)
 // maybe we could create an extension trait that is like Actix's Threadpooled Actors, but for xtra?
@@ -40,24 +57,32 @@ mod block_scheduler;
 pub struct BlockFetcher<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
-    sender: flume::Sender<u32>,
-    pair: (flume::Sender<Block<B>>, Option<flume::Receiver<Block<B>>>),
+    sender: flume::Sender<u64>,
+    /// blocks that are fetched even if they have been fetched before
+    reindex: flume::Sender<u64>,
+    pair: (
+        flume::Sender<ArchiveResult<Block<B>>>,
+        Option<flume::Receiver<ArchiveResult<Block<B>>>>,
+    ),
     _handle: jod_thread::JoinHandle<ArchiveResult<()>>,
 }
 
 impl<B> BlockFetcher<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
-    pub fn new(ctx: ActorContext<B>, threads: Option<usize>) -> ArchiveResult<Self> {
+    pub fn new(
+        ctx: ActorContext<B>,
+        supervisor: Supervisor,
+        threads: Option<usize>,
+    ) -> ArchiveResult<Self> {
         let (tx, rx) = flume::unbounded();
+        let (reindex_tx, reindex_rx) = flume::unbounded();
         let (sender, receiver) = flume::unbounded();
         let res_sender = sender.clone();
         let handle = jod_thread::spawn(move || -> ArchiveResult<()> {
-            let pool = ThreadedBlockFetcher::new(ctx, threads)?;
+            let pool = ThreadedBlockFetcher::new(ctx, supervisor, threads)?;
             let mut pool = BlockScheduler::new("fetch", pool, 1000, Ordering::Ascending);
             'sched: loop {
                 // ideally, there should be a way to check if senders
@@ -73,6 +98,7 @@ where
                     },
                 }
                 pool.add_data(rx.drain().collect());
+                reindex_rx.drain().for_each(|n| pool.requeue(n));
                 let work = pool.check_work()?;
                 for w in work.into_iter() {
                     res_sender.send(w)?;
@@ -84,59 +110,54 @@ where
         Ok(Self {
             pair: (sender, Some(receiver)),
             sender: tx,
+            reindex: reindex_tx,
             _handle: handle,
         })
     }
 
-    /// attach a stream to this threadpool
-    /// Forwards all messages from the stream to the threadpool
-    pub fn attach_stream(&self, mut stream: impl Stream<Item = u32> + Send + Unpin + 'static) {
-        let tx = self.sender.clone();
-        crate::util::spawn(async move {
-            while let Some(m) = stream.next().await {
-                tx.send(m)?;
-            }
-            Ok(())
-        });
-    }
-
     /// Convert this Threadpool into a stream of its outputs
     ///
     /// # Panics
     /// panics if the stream has already been taken
-    pub fn get_stream(&mut self) -> impl Stream<Item = Block<B>> {
+    pub fn get_stream(&mut self) -> impl Stream<Item = ArchiveResult<Block<B>>> {
         self.pair.1.take().unwrap()
     }
 
     /// get the channel to send work to this threadpool
-    pub fn sender(&self) -> flume::Sender<u32> {
+    pub fn sender(&self) -> flume::Sender<u64> {
         self.sender.clone()
     }
+
+    /// get the channel to send blocks that should be fetched again
+    pub fn reindex_sender(&self) -> flume::Sender<u64> {
+        self.reindex.clone()
+    }
 }
 
 /// Threadpool that executes blocks
 pub struct ThreadedBlockExecutor<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
     /// The main sender
     sender: flume::Sender<BlockData<B>>,
+    /// how many blocks are queued or being executed
+    queued: Arc<AtomicUsize>,
     _handle: jod_thread::JoinHandle<ArchiveResult<()>>,
     pair: (
-        flume::Sender<BlockChanges<B>>,
-        Option<flume::Receiver<BlockChanges<B>>>,
+        flume::Sender<ExecOutcome<B>>,
+        Option<flume::Receiver<ExecOutcome<B>>>,
     ),
 }
 
 impl<B> ThreadedBlockExecutor<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
     pub fn new<R, A>(
         client: Arc<A>,
         backend: Arc<Backend<B>>,
+        versions: Arc<dyn GetRuntimeVersion<B>>,
         threads: Option<usize>,
     ) -> ArchiveResult<Self>
     where
@@ -148,9 +169,14 @@ where
         let (tx, rx) = flume::unbounded();
         let (sender, receiver) = flume::unbounded();
         let res_sender = sender.clone();
+        let queued = Arc::new(AtomicUsize::new(0));
+        let queued_sched = queued.clone();
         let handle = jod_thread::spawn(move || -> ArchiveResult<()> {
-            let pool = BlockExecPool::<B, R, A>::new(threads, client, backend)?;
+            let pool = BlockExecPool::<B, R, A>::new(threads, client, backend, versions)?;
             let mut pool = BlockScheduler::new("exec", pool, 256, Ordering::Ascending);
+            let mut retries: HashMap<B::Hash, usize> = HashMap::new();
+            // blocks waiting out their backoff before being re-queued
+            let mut delayed: Vec<(Instant, Block<B>)> = Vec::new();
             'sched: loop {
                 thread::sleep(Duration::from_millis(50));
                 // ideally, there should be a way to check if senders
@@ -161,6 +187,7 @@ where
                     Ok(v) => match v {
                         BlockData::Batch(v) => pool.add_data(v),
                         BlockData::Single(v) => pool.add_data_single(v),
+                        BlockData::Reindex(v) => v.into_iter().for_each(|b| pool.requeue(b)),
                     },
                     Err(e) => match e {
                         flume::TryRecvError::Disconnected => break 'sched,
@@ -170,41 +197,68 @@ where
                 rx.drain().for_each(|v| match v {
                     BlockData::Batch(v) => pool.add_data(v),
                     BlockData::Single(v) => pool.add_data_single(v),
+                    BlockData::Reindex(v) => v.into_iter().for_each(|b| pool.requeue(b)),
                 });
+                let now = Instant::now();
+                let (due, waiting): (Vec<_>, Vec<_>) =
+                    delayed.into_iter().partition(|(at, _)| *at <= now);
+                delayed = waiting;
+                due.into_iter().for_each(|(_, b)| pool.requeue(b));
                 for w in pool.check_work()?.into_iter() {
-                    res_sender.send(w)?;
+                    match w {
+                        ExecOutcome::Failed(f) if f.error.is_retryable() => {
+                            let hash = f.block.inner.block.hash();
+                            let tries = retries.entry(hash).or_insert(0);
+                            *tries += 1;
+                            if *tries <= MAX_EXEC_RETRIES {
+                                log::debug!(
+                                    "re-queueing block {} ({}/{})",
+                                    hash,
+                                    tries,
+                                    MAX_EXEC_RETRIES
+                                );
+                                let backoff = RETRY_BASE_DELAY * 2u32.pow(*tries as u32 - 1);
+                                delayed.push((Instant::now() + backoff, f.block));
+                            } else {
+                                retries.remove(&hash);
+                                pool.forget(&f.block);
+                                res_sender.send(ExecOutcome::Failed(f))?;
+                            }
+                        }
+                        ExecOutcome::Failed(f) => {
+                            retries.remove(&f.block.inner.block.hash());
+                            pool.forget(&f.block);
+                            res_sender.send(ExecOutcome::Failed(f))?;
+                        }
+                        ExecOutcome::Changes(c) => {
+                            retries.remove(&c.block_hash);
+                            res_sender.send(ExecOutcome::Changes(c))?;
+                        }
+                        w => res_sender.send(w)?,
+                    }
                 }
+                queued_sched.store(pool.pending() + delayed.len(), atomic::Ordering::Relaxed);
             }
             Ok(())
         });
 
         Ok(Self {
             sender: tx,
+            queued,
             pair: (sender, Some(receiver)),
             _handle: handle,
         })
     }
 
-    /// attach a stream to this threadpool
-    /// Forwards all messages from the stream to the threadpool
-    #[allow(unused)]
-    pub fn attach_stream(
-        &self,
-        mut stream: impl Stream<Item = BlockData<B>> + Send + Unpin + 'static,
-    ) {
-        let tx = self.sender.clone();
-        crate::util::spawn(async move {
-            while let Some(m) = stream.next().await {
-                tx.send(m)?;
-            }
-            Ok(())
-        });
+    /// how many blocks are queued or being executed, updated as the executor works
+    pub fn queued(&self) -> Arc<AtomicUsize> {
+        self.queued.clone()
     }
 
     /// Convert this Threadpool into a stream of its outputs
     /// # Panics
     /// panics if the stream has already been taken
-    pub fn get_stream(&mut self) -> impl Stream<Item = BlockChanges<B>> {
+    pub fn get_stream(&mut self) -> impl Stream<Item = ExecOutcome<B>> {
         self.pair.1.take().unwrap()
     }
 
@@ -17,7 +17,9 @@
 //! Executes blocks concurrently
 
 use crate::{
-    backend::{ApiAccess, BlockChanges, BlockExecutor, ReadOnlyBackend as Backend},
+    backend::{
+        ApiAccess, BlockChanges, BlockExecutor, GetRuntimeVersion, ReadOnlyBackend as Backend,
+    },
     error::{ArchiveResult, Error as ArchiveError},
     types::{self, PriorityIdent, ThreadPool},
 };
@@ -26,7 +28,8 @@ use sp_api::{ApiExt, ConstructRuntimeApi};
 use sp_block_builder::BlockBuilder as BlockBuilderApi;
 use sp_runtime::{
     generic::BlockId,
-    traits::{Block as BlockT, Header, NumberFor},
+    traits::{Block as BlockT, Header},
+    SaturatedConversion,
 };
 use std::{marker::PhantomData, sync::Arc};
 
@@ -39,6 +42,45 @@ use std::{marker::PhantomData, sync::Arc};
 pub enum BlockData<B: BlockT> {
     Batch(Vec<types::Block<B>>),
     Single(types::Block<B>),
+    /// blocks that are executed even if they have been executed before
+    Reindex(Vec<types::Block<B>>),
+}
+
+/// The outcome of executing one block in the threadpool
+#[derive(Debug)]
+pub enum ExecOutcome<B: BlockT> {
+    /// The block was executed
+    Changes(BlockChanges<B>),
+    /// The block is the genesis block, which is never executed
+    Genesis,
+    /// The block could not be executed
+    Failed(FailedBlock<B>),
+}
+
+/// A block that failed to execute, along with the reason it failed
+#[derive(Debug)]
+pub struct FailedBlock<B: BlockT> {
+    pub block: types::Block<B>,
+    pub error: ArchiveError,
+}
+
+impl<B> From<FailedBlock<B>> for ArchiveError
+where
+    B: BlockT,
+{
+    fn from(f: FailedBlock<B>) -> ArchiveError {
+        match f.error {
+            e @ ArchiveError::BlockExecution { .. } => e,
+            e => {
+                let header = f.block.inner.block.header();
+                ArchiveError::BlockExecution {
+                    block_num: (*header.number()).saturated_into(),
+                    hash: format!("{:?}", header.hash()),
+                    reason: e.to_string(),
+                }
+            }
+        }
+    }
 }
 
 /// Executor that sends blocks to a thread pool for execution
@@ -47,13 +89,13 @@ pub struct BlockExecPool<Block: BlockT, RA, Api> {
     pool: rayon::ThreadPool,
     client: Arc<Api>,
     backend: Arc<Backend<Block>>,
+    versions: Arc<dyn GetRuntimeVersion<Block>>,
     _marker: PhantomData<(Block, RA)>,
 }
 
 impl<B, RA, Api> BlockExecPool<B, RA, Api>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
     RA: ConstructRuntimeApi<B, Api> + Send + 'static,
     RA::RuntimeApi: BlockBuilderApi<B, Error = sp_blockchain::Error>
         + ApiExt<B, StateBackend = backend::StateBackendFor<Backend<B>, B>>,
@@ -64,6 +106,7 @@ where
         num_threads: Option<usize>,
         client: Arc<Api>,
         backend: Arc<Backend<B>>,
+        versions: Arc<dyn GetRuntimeVersion<B>>,
     ) -> Result<Self, ArchiveError> {
         // channel pair for sending and receiving BlockChanges
 
@@ -76,6 +119,7 @@ where
             pool,
             client,
             backend,
+            versions,
             _marker: PhantomData,
         })
     }
@@ -84,28 +128,26 @@ where
         block: B,
         client: &Arc<Api>,
         backend: &Arc<Backend<B>>,
-        sender: &flume::Sender<BlockChanges<B>>,
-    ) -> Result<(), ArchiveError> {
+        versions: &Arc<dyn GetRuntimeVersion<B>>,
+    ) -> ArchiveResult<ExecOutcome<B>> {
         let api = client.runtime_api();
 
         // don't execute genesis block
         if *block.header().parent_hash() == Default::default() {
-            return Ok(());
+            return Ok(ExecOutcome::Genesis);
         }
 
         log::trace!(
             "Executing Block: {}:{}, version {}",
             block.header().hash(),
             block.header().number(),
-            client
-                .runtime_version_at(&BlockId::Hash(block.header().hash()))?
+            versions
+                .runtime_version(&BlockId::Hash(block.header().hash()))?
                 .spec_version,
         );
 
-        let block = BlockExecutor::new(api, backend, block)?.block_into_storage()?;
-
-        sender.send(block).expect("Could not send");
-        Ok(())
+        let changes = BlockExecutor::new(api, backend, block)?.block_into_storage()?;
+        Ok(ExecOutcome::Changes(changes))
     }
 
     /// inserts tasks for the threadpool
@@ -113,20 +155,27 @@ where
     pub fn add_vec_task(
         &self,
         blocks: Vec<types::Block<B>>,
-        sender: flume::Sender<BlockChanges<B>>,
+        sender: flume::Sender<ExecOutcome<B>>,
     ) -> Result<usize, ArchiveError> {
         let len = blocks.len();
 
         for blocks in blocks.chunks(5) {
             let client = self.client.clone();
             let backend = self.backend.clone();
+            let versions = self.versions.clone();
             let sender = sender.clone();
             let blocks = blocks.to_vec();
             self.pool.spawn_fifo(move || {
                 for block in blocks.into_iter() {
-                    match Self::work(block.inner.block, &client, &backend, &sender) {
-                        Ok(_) => (),
-                        Err(e) => log::error!("{:?}", e),
+                    // every block produces an outcome, so that the scheduler counts it as finished
+                    let outcome =
+                        match Self::work(block.inner.block.clone(), &client, &backend, &versions) {
+                            Ok(o) => o,
+                            Err(error) => ExecOutcome::Failed(FailedBlock { block, error }),
+                        };
+                    if sender.send(outcome).is_err() {
+                        log::warn!("block executor disconnected, dropping execution results");
+                        return;
                     }
                 }
             });
@@ -138,19 +187,18 @@ where
 impl<B, R, A> ThreadPool for BlockExecPool<B, R, A>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
     R: ConstructRuntimeApi<B, A> + Send + 'static,
     R::RuntimeApi: BlockBuilderApi<B, Error = sp_blockchain::Error>
         + ApiExt<B, StateBackend = backend::StateBackendFor<Backend<B>, B>>,
     A: ApiAccess<B, Backend<B>, R> + 'static,
 {
     type In = types::Block<B>;
-    type Out = BlockChanges<B>;
+    type Out = ExecOutcome<B>;
 
     fn add_task(
         &self,
         d: Vec<types::Block<B>>,
-        tx: flume::Sender<BlockChanges<B>>,
+        tx: flume::Sender<ExecOutcome<B>>,
     ) -> ArchiveResult<usize> {
         self.add_vec_task(d, tx)
     }
@@ -16,75 +16,121 @@
 
 use crate::types::*;
 use crate::{
-    actors::ActorContext,
-    backend::{GetRuntimeVersion, ReadOnlyBackend},
-    error::ArchiveResult,
+    actors::{ActorContext, Supervisor},
+    author::AuthorResolver,
+    backend::{GetRuntimeVersion, ReadOnlyBackend, RuntimeVersionCache},
+    error::{ArchiveResult, Error as ArchiveError},
 };
 use sp_runtime::{
     generic::BlockId,
-    traits::{Block as BlockT, NumberFor},
+    traits::{Block as BlockT, Header as _, NumberFor},
+    SaturatedConversion,
 };
 use std::sync::Arc;
 
 pub struct ThreadedBlockFetcher<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
     pool: rayon::ThreadPool,
     backend: Arc<ReadOnlyBackend<B>>,
-    api: Arc<dyn GetRuntimeVersion<B>>,
+    api: Arc<RuntimeVersionCache<B>>,
+    author_resolver: Option<Arc<dyn AuthorResolver<B>>>,
+    /// authors that could not be resolved are reported, without failing their block
+    supervisor: Supervisor,
 }
 
 impl<B> ThreadedBlockFetcher<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
-    pub fn new(context: ActorContext<B>, num_threads: Option<usize>) -> ArchiveResult<Self> {
+    pub fn new(
+        context: ActorContext<B>,
+        supervisor: Supervisor,
+        num_threads: Option<usize>,
+    ) -> ArchiveResult<Self> {
         let pool = rayon::ThreadPoolBuilder::new()
             .num_threads(num_threads.unwrap_or(0))
             .thread_name(|i| format!("blk-fetch-{}", i))
             .build()?;
-        let api = context.api();
+        let api = context.runtime_versions();
 
         Ok(Self {
             pool,
             api,
             backend: context.backend().clone(),
+            author_resolver: context.author_resolver(),
+            supervisor,
         })
     }
 
     /// Represents one unit of work for the threadpool
     fn work(
-        block_num: u32,
-        api: &Arc<dyn GetRuntimeVersion<B>>,
+        block_num: u64,
+        api: &Arc<RuntimeVersionCache<B>>,
         backend: &Arc<ReadOnlyBackend<B>>,
-        tx: &flume::Sender<Block<B>>,
-    ) -> ArchiveResult<()> {
-        let num = NumberFor::<B>::from(block_num);
-        let b = backend.block(&BlockId::Number(num));
-        if b.is_none() {
-            log::warn!("Block {} not found!", num);
-        } else {
-            let b = b.expect("Checked for none; qed");
-            let version = api.runtime_version(&BlockId::Hash(b.block.hash()))?;
-            tx.send(Block::<B>::new(b, version.spec_version))?;
+        author_resolver: Option<&dyn AuthorResolver<B>>,
+        supervisor: &Supervisor,
+    ) -> ArchiveResult<Block<B>> {
+        let num: NumberFor<B> = block_num.saturated_into();
+        let b = backend
+            .block(&BlockId::Number(num))
+            .ok_or_else(|| ArchiveError::BlockNotFound(block_num.to_string()))?;
+        let (hash, parent_hash) = (b.block.hash(), *b.block.header().parent_hash());
+        let version = api.runtime_version(&BlockId::Hash(hash))?;
+        let spec = version.spec_version;
+
+        // the runtime changed if its code differs from the code at the parent block,
+        // which also catches upgrades that leave the spec version alone.
+        // Code hashes of recent blocks are cached, so `:code` is only hashed again
+        // for blocks the cache has not seen. Genesis always starts a runtime
+        let code_hash = api.code_hash(&BlockId::Hash(hash))?;
+        let is_upgrade = parent_hash == Default::default()
+            || api.code_hash(&BlockId::Hash(parent_hash))? != code_hash;
+        let mut block = Block::<B>::new(b, spec);
+        if is_upgrade {
+            log::info!("runtime upgrade to spec {} at block {}", spec, block_num);
+            let upgrade = RuntimeUpgrade {
+                version,
+                block_num,
+                block_hash: hash,
+                code_hash,
+            };
+            block = block.with_upgrade(upgrade);
+        }
+
+        // authors are an optional extra, so failing to resolve one doesn't fail the block
+        if let Some(resolver) = author_resolver {
+            match resolver.author(block.inner.block.header(), backend) {
+                Ok(author) => block.author = author,
+                Err(e) => supervisor.report(ArchiveError::AuthorUnresolved {
+                    block_num,
+                    source: Box::new(e),
+                }),
+            }
         }
-        Ok(())
+        Ok(block)
     }
 
-    fn add_task(&self, nums: &[u32], sender: flume::Sender<Block<B>>) -> ArchiveResult<usize> {
+    fn add_task(
+        &self,
+        nums: &[u64],
+        sender: flume::Sender<ArchiveResult<Block<B>>>,
+    ) -> ArchiveResult<usize> {
         for nums in nums.chunks(10) {
             let api = self.api.clone();
             let backend = self.backend.clone();
+            let author_resolver = self.author_resolver.clone();
+            let supervisor = self.supervisor.clone();
             let tx = sender.clone();
             let nums = nums.to_vec();
             self.pool.spawn_fifo(move || {
                 for num in nums.into_iter() {
-                    match Self::work(num, &api, &backend, &tx) {
-                        Ok(_) => (),
-                        Err(e) => log::error!("{}", e.to_string()),
+                    // failures are sent too, so that the scheduler counts them as finished
+                    let block =
+                        Self::work(num, &api, &backend, author_resolver.as_deref(), &supervisor);
+                    if tx.send(block).is_err() {
+                        log::warn!("block fetcher disconnected, dropping block {}", num);
                     }
                 }
             });
@@ -96,19 +142,22 @@ where
 impl<B> ThreadPool for ThreadedBlockFetcher<B>
 where
     B: BlockT,
-    NumberFor<B>: Into<u32>,
 {
-    type In = u32;
-    type Out = Block<B>;
+    type In = u64;
+    type Out = ArchiveResult<Block<B>>;
 
-    fn add_task(&self, d: Vec<u32>, tx: flume::Sender<Block<B>>) -> ArchiveResult<usize> {
+    fn add_task(
+        &self,
+        d: Vec<u64>,
+        tx: flume::Sender<ArchiveResult<Block<B>>>,
+    ) -> ArchiveResult<usize> {
         self.add_task(&d, tx)
     }
 }
 
-impl PriorityIdent for u32 {
-    type Ident = u32;
-    fn identifier(&self) -> u32 {
+impl PriorityIdent for u64 {
+    type Ident = u64;
+    fn identifier(&self) -> u64 {
         *self
     }
 }
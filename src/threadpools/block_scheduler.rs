@@ -83,7 +83,7 @@ pub enum Ordering {
 pub struct BlockScheduler<I, O, T>
 where
     I: Clone + Send + Sync + Encode + Decode + PriorityIdent,
-    O: Send + Sync + Debug,
+    O: Send + Debug,
     T: ThreadPool<In = I, Out = O>,
 {
     name: String,
@@ -112,7 +112,7 @@ where
 impl<I, O, T> BlockScheduler<I, O, T>
 where
     I: Clone + Send + Sync + Encode + Decode + PriorityIdent + Debug,
-    O: Send + Sync + Debug + Clone,
+    O: Send + Debug,
     T: ThreadPool<In = I, Out = O>,
 {
     pub fn new(name: &str, exec: T, max_size: usize, ord: Ordering) -> Self {
@@ -150,6 +150,23 @@ where
         }
     }
 
+    /// Put data that was already scheduled back into the queue,
+    /// bypassing the check for duplicates
+    pub fn requeue(&mut self, data: I) {
+        self.queue.push(EncodedIn::from(data))
+    }
+
+    /// Stop tracking data that will not be executed again,
+    /// so that it may be scheduled anew once it is sent again
+    pub fn forget(&mut self, data: &I) {
+        self.dups.remove(&data.encode());
+    }
+
+    /// how many items are queued or in the threadpool
+    pub fn pending(&self) -> usize {
+        self.queue.len() + (self.added - self.finished)
+    }
+
     pub fn check_work(&mut self) -> ArchiveResult<Vec<O>> {
         // we try to maintain a MAX queue of max_size tasks at a time in the threadpool
         let delta = self.added - self.finished;
@@ -14,16 +14,20 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::error::{ArchiveResult, Error as ArchiveError};
+use crate::{
+    consistency::ConsistencyReport,
+    error::{ArchiveResult, Error as ArchiveError},
+};
 use codec::{Decode, Encode};
 use serde::{Deserialize, Serialize};
 use sp_runtime::{generic::SignedBlock, traits::Block as BlockT};
 use sp_storage::{StorageData, StorageKey};
+use sp_version::RuntimeVersion;
 use xtra::prelude::*;
 
 pub trait ThreadPool: Send {
     type In: Clone + Send + Sync + Encode + Decode + PriorityIdent;
-    type Out: Send + Sync + std::fmt::Debug;
+    type Out: Send + std::fmt::Debug;
     fn add_task(&self, d: Vec<Self::In>, tx: flume::Sender<Self::Out>) -> ArchiveResult<usize>;
 }
 
@@ -38,14 +42,98 @@ pub trait Archive<B: BlockT> {
     /// start driving the execution of the archive
     async fn drive(&mut self) -> Result<(), ArchiveError>;
 
-    /// this method will block indefinitely
-    async fn block_until_stopped(&self) -> ();
+    /// this method will block until the archive encounters an error it cannot recover from
+    async fn block_until_stopped(&self) -> Result<(), ArchiveError>;
+
+    /// Register a callback that is invoked with every error the archive reports while running
+    fn register_error_handler(&self, handler: impl Fn(&ArchiveError) + Send + Sync + 'static);
 
     /// shutdown the system
     fn shutdown(self) -> Result<(), ArchiveError>;
 
     /// Get a reference to the context the actors are using
     fn context(&self) -> Result<super::actors::ActorContext<B>, ArchiveError>;
+
+    /// Index a range of blocks again.
+    /// The archive must be driven before anything can be reindexed.
+    /// Returns once the work has been handed to the threadpools, not once it is finished.
+    async fn reindex(&self, request: Reindex) -> Result<(), ArchiveError>;
+
+    /// Repair the inconsistencies found by `ArchiveBuilder::check_consistency`,
+    /// by fetching the affected blocks again.
+    /// The archive must be driven before anything can be repaired.
+    async fn repair(&self, report: &ConsistencyReport) -> Result<(), ArchiveError>;
+}
+
+/// The data that can be indexed again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReindexStage {
+    /// blocks, along with their digest items, authors and runtime upgrades
+    Blocks,
+    /// the storage changes of executing the blocks
+    Storage,
+    /// metadata of the runtimes the blocks ran with
+    Metadata,
+    /// tables derived from storage changes, like `balances` and the tables of `StorageIndexer`s
+    Derived,
+}
+
+/// A range of blocks to index again
+#[derive(Debug, Clone)]
+pub struct Reindex {
+    /// first block to index again
+    pub from: u64,
+    /// last block to index again (inclusive)
+    pub to: u64,
+    pub stages: Vec<ReindexStage>,
+    /// Delete the existing rows of the stages in the range before indexing them again.
+    /// Metadata is never deleted, since other blocks may depend on it.
+    pub delete_existing: bool,
+}
+
+impl ReindexStage {
+    pub const ALL: [ReindexStage; 4] = [
+        ReindexStage::Blocks,
+        ReindexStage::Storage,
+        ReindexStage::Metadata,
+        ReindexStage::Derived,
+    ];
+}
+
+impl std::str::FromStr for ReindexStage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "blocks" => Ok(ReindexStage::Blocks),
+            "storage" => Ok(ReindexStage::Storage),
+            "metadata" => Ok(ReindexStage::Metadata),
+            "derived" => Ok(ReindexStage::Derived),
+            s => Err(format!("unknown reindex stage {}", s)),
+        }
+    }
+}
+
+impl Reindex {
+    pub fn has_stage(&self, stage: ReindexStage) -> bool {
+        self.stages.contains(&stage)
+    }
+}
+
+/// How long the storage changes of blocks are kept in Postgres.
+/// Blocks themselves are always kept.
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    /// Keep the storage changes of this many of the latest blocks.
+    /// With snapshots, up to twice as many are kept, since the horizon moves in steps of `keep_blocks`
+    pub keep_blocks: u32,
+    /// Write the full state of the oldest block that is kept (`is_full = true`)
+    /// before deleting the changes before it, so that the state of every block
+    /// that is kept can still be reconstructed from Postgres alone
+    pub snapshot: bool,
+    /// how often to prune
+    pub interval: std::time::Duration,
 }
 
 #[derive(Debug)]
@@ -55,7 +143,7 @@ pub struct Metadata {
 }
 
 impl Message for Metadata {
-    type Result = ();
+    type Result = ArchiveResult<()>;
 }
 
 impl Metadata {
@@ -76,16 +164,53 @@ impl Metadata {
 pub struct Block<B: BlockT> {
     pub inner: SignedBlock<B>,
     pub spec: u32,
+    /// Set if this block is the first to run a new runtime
+    pub upgrade: Option<RuntimeUpgrade<B>>,
+    /// SCALE-encoded account of the block author, if it was resolved
+    pub author: Option<Vec<u8>>,
 }
 
 impl<B: BlockT> Message for Block<B> {
-    type Result = ();
+    type Result = ArchiveResult<()>;
 }
 
 impl<B: BlockT> Block<B> {
     pub fn new(block: SignedBlock<B>, spec: u32) -> Self {
-        Self { inner: block, spec }
+        Self {
+            inner: block,
+            spec,
+            upgrade: None,
+            author: None,
+        }
     }
+
+    /// Mark this block as the first block of a new runtime
+    pub fn with_upgrade(mut self, upgrade: RuntimeUpgrade<B>) -> Self {
+        self.upgrade = Some(upgrade);
+        self
+    }
+}
+
+/// A runtime that was enacted on chain, along with the block that first ran it
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct RuntimeUpgrade<B: BlockT> {
+    pub version: RuntimeVersion,
+    pub block_num: u64,
+    pub block_hash: B::Hash,
+    /// hash of the runtime wasm blob
+    pub code_hash: B::Hash,
+}
+
+impl<B: BlockT> Message for RuntimeUpgrade<B> {
+    type Result = ArchiveResult<()>;
+}
+
+/// A runtime wasm blob.
+/// Read from state only when its upgrade is written, so the blob never travels with blocks
+#[derive(Debug, Clone)]
+pub struct RuntimeCode<B: BlockT> {
+    pub code_hash: B::Hash,
+    pub code: Vec<u8>,
 }
 
 /// NewType for committing many blocks to the database at once
@@ -95,7 +220,7 @@ pub struct BatchBlock<B: BlockT> {
 }
 
 impl<B: BlockT> Message for BatchBlock<B> {
-    type Result = ();
+    type Result = ArchiveResult<()>;
 }
 
 impl<B: BlockT> BatchBlock<B> {
@@ -116,19 +241,21 @@ impl<B: BlockT> BatchBlock<B> {
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Storage<Block: BlockT> {
     hash: Block::Hash,
-    block_num: u32,
+    block_num: u64,
     full_storage: bool,
     pub changes: Vec<(StorageKey, Option<StorageData>)>,
+    /// changes of child tries, by the storage key of the child trie
+    pub child_changes: Vec<(StorageKey, Vec<(StorageKey, Option<StorageData>)>)>,
 }
 
 impl<Block: BlockT> Message for Storage<Block> {
-    type Result = ();
+    type Result = ArchiveResult<()>;
 }
 
 impl<Block: BlockT> Storage<Block> {
     pub fn new(
         hash: Block::Hash,
-        block_num: u32,
+        block_num: u64,
         full_storage: bool,
         changes: Vec<(StorageKey, Option<StorageData>)>,
     ) -> Self {
@@ -137,14 +264,24 @@ impl<Block: BlockT> Storage<Block> {
             hash,
             full_storage,
             changes,
+            child_changes: Vec::new(),
         }
     }
 
+    /// Add the changes of child tries
+    pub fn with_child_changes(
+        mut self,
+        child_changes: Vec<(StorageKey, Vec<(StorageKey, Option<StorageData>)>)>,
+    ) -> Self {
+        self.child_changes = child_changes;
+        self
+    }
+
     pub fn is_full(&self) -> bool {
         self.full_storage
     }
 
-    pub fn block_num(&self) -> u32 {
+    pub fn block_num(&self) -> u64 {
         self.block_num
     }
 
@@ -0,0 +1,230 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Verify that the archived storage is faithful to the chain.
+//! The storage changes of a block in Postgres are applied to the state of its parent in RocksDB.
+//! If the changes are complete and correct, the root of the resulting trie is the state root in the header.
+//! Changes of child tries are archived in `child_storage` and applied to the child tries of the parent.
+
+use crate::{
+    backend::{ReadOnlyBackend, TrieState},
+    database::sql_num,
+    error::{ArchiveResult, Error as ArchiveError},
+};
+use codec::Decode;
+use hashbrown::HashMap;
+use sc_client_api::backend::{Backend, StateBackend};
+use sp_core::storage::ChildInfo;
+use sp_runtime::{generic::BlockId, traits::Block as BlockT};
+use sqlx::PgConnection;
+
+/// The blocks to verify
+#[derive(Debug, Clone)]
+pub struct Verification {
+    /// first block to verify
+    pub from: u64,
+    /// last block to verify (inclusive)
+    pub to: u64,
+    /// Only verify this many blocks, spread evenly over the range.
+    /// Every block in the range is verified if this is `None`
+    pub sample: Option<u64>,
+}
+
+impl Verification {
+    fn block_nums(&self) -> Vec<u64> {
+        let len = self.to.saturating_sub(self.from) + 1;
+        match self.sample {
+            Some(n) if n > 0 && n < len => {
+                let step = len as f64 / n as f64;
+                (0..n)
+                    .map(|i| self.from + (i as f64 * step) as u64)
+                    .collect()
+            }
+            _ => (self.from..=self.to).collect(),
+        }
+    }
+}
+
+/// A block whose archived storage changes do not produce its state root
+#[derive(Debug, Clone)]
+pub struct StateRootMismatch<B: BlockT> {
+    pub block_num: u64,
+    pub hash: B::Hash,
+    /// state root in the block header
+    pub expected: B::Hash,
+    /// state root computed from the archived storage changes
+    pub computed: B::Hash,
+    /// Archived keys whose value differs from the state of the block.
+    /// If this is empty, changes are missing from the archive instead
+    pub keys: Vec<Vec<u8>>,
+    /// Archived `(child trie, key)` pairs whose value differs from the state of the block
+    pub child_keys: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct VerificationReport<B: BlockT> {
+    /// how many blocks were verified
+    pub verified: usize,
+    pub mismatches: Vec<StateRootMismatch<B>>,
+    /// blocks that could not be verified, because they or their storage are not archived
+    pub missing: Vec<u64>,
+}
+
+impl<B: BlockT> VerificationReport<B> {
+    /// true if the storage of every verified block produced its state root
+    pub fn is_faithful(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Recompute the state roots of the blocks in `verification` from the storage archived in Postgres
+pub async fn verify_state_roots<B: BlockT>(
+    backend: &ReadOnlyBackend<B>,
+    conn: &mut PgConnection,
+    verification: &Verification,
+) -> ArchiveResult<VerificationReport<B>> {
+    let mut report = VerificationReport {
+        verified: 0,
+        mismatches: Vec::new(),
+        missing: Vec::new(),
+    };
+    for num in verification.block_nums().into_iter() {
+        let block: Option<(Vec<u8>, Vec<u8>, Vec<u8>)> =
+            sqlx::query_as("SELECT hash, parent_hash, state_root FROM blocks WHERE block_num = $1")
+                .bind(sql_num(num)?)
+                .fetch_optional(&mut *conn)
+                .await?;
+        let (hash, parent_hash, state_root) = match block {
+            Some(b) => b,
+            None => {
+                report.missing.push(num);
+                continue;
+            }
+        };
+        let changes: Vec<(Vec<u8>, Option<Vec<u8>>)> =
+            sqlx::query_as("SELECT key, storage FROM storage WHERE hash = $1 ORDER BY id")
+                .bind(hash.as_slice())
+                .fetch_all(&mut *conn)
+                .await?;
+        let child_changes: Vec<(Vec<u8>, Vec<u8>, Option<Vec<u8>>)> = sqlx::query_as(
+            "SELECT child_key, key, storage FROM child_storage WHERE hash = $1 ORDER BY id",
+        )
+        .bind(hash.as_slice())
+        .fetch_all(&mut *conn)
+        .await?;
+        if changes.is_empty() && child_changes.is_empty() {
+            report.missing.push(num);
+            continue;
+        }
+        // if a block was indexed more than once, the latest value of a key wins
+        let changes = changes.into_iter().collect::<HashMap<_, _>>();
+        let mut children: HashMap<Vec<u8>, HashMap<Vec<u8>, Option<Vec<u8>>>> = HashMap::new();
+        for (child_key, key, value) in child_changes.into_iter() {
+            children.entry(child_key).or_default().insert(key, value);
+        }
+        let children = children
+            .into_iter()
+            .map(|(child_key, changes)| (ChildInfo::new_default(&child_key), changes))
+            .collect::<Vec<_>>();
+
+        let hash = decode_hash::<B>(&hash)?;
+        let expected = decode_hash::<B>(&state_root)?;
+        let parent = state_at(backend, decode_hash::<B>(&parent_hash)?)?;
+        let (computed, _) = parent.full_storage_root(
+            changes.iter().map(|(k, v)| (k.as_slice(), v.as_deref())),
+            children.iter().map(|(info, changes)| {
+                (
+                    info,
+                    changes.iter().map(|(k, v)| (k.as_slice(), v.as_deref())),
+                )
+            }),
+        );
+        report.verified += 1;
+        if computed == expected {
+            continue;
+        }
+
+        let state = state_at(backend, hash)?;
+        let mut keys = Vec::new();
+        for (key, value) in changes.iter() {
+            let actual = state.storage(key).map_err(ArchiveError::Blockchain)?;
+            if actual.as_ref() != value.as_ref() {
+                keys.push(key.clone());
+            }
+        }
+        keys.sort();
+        let mut child_keys = Vec::new();
+        for (info, changes) in children.iter() {
+            for (key, value) in changes.iter() {
+                let actual = state
+                    .child_storage(info, key)
+                    .map_err(ArchiveError::Blockchain)?;
+                if actual.as_ref() != value.as_ref() {
+                    child_keys.push((info.storage_key().to_vec(), key.clone()));
+                }
+            }
+        }
+        child_keys.sort();
+        log::warn!(
+            "state root of block {} does not match, {} archived keys and {} child trie keys differ",
+            num,
+            keys.len(),
+            child_keys.len()
+        );
+        report.mismatches.push(StateRootMismatch {
+            block_num: num,
+            hash,
+            expected,
+            computed,
+            keys,
+            child_keys,
+        });
+    }
+    Ok(report)
+}
+
+fn state_at<B: BlockT>(backend: &ReadOnlyBackend<B>, hash: B::Hash) -> ArchiveResult<TrieState<B>> {
+    Backend::state_at(backend, BlockId::Hash(hash))
+        .map_err(|e| ArchiveError::state_unavailable(hash, e))
+}
+
+fn decode_hash<B: BlockT>(mut hash: &[u8]) -> ArchiveResult<B::Hash> {
+    Decode::decode(&mut hash).map_err(|e| ArchiveError::Decode {
+        context: "block hash",
+        source: e,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_sample_evenly() {
+        let v = Verification {
+            from: 10,
+            to: 19,
+            sample: Some(5),
+        };
+        assert_eq!(v.block_nums(), vec![10, 12, 14, 16, 18]);
+        let v = Verification {
+            from: 10,
+            to: 12,
+            sample: None,
+        };
+        assert_eq!(v.block_nums(), vec![10, 11, 12]);
+    }
+}